@@ -4,7 +4,7 @@ use crate::ui;
 use crate::states::window_state;
 use crate::states::app_state::MediaPlayer;
 use iced::keyboard::{Key, key::Named};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::fs;
 use tokio::time::sleep; // Use tokio instead
@@ -52,9 +52,11 @@ pub enum Message {
     /// Set a temporary status message
     SetStatusMessage(String, Duration),
     /// Batch file processing result
-    BatchProcessingResult(Vec<(String, PathBuf)>, u32),
+    BatchProcessingResult(Vec<core::Track>, u32),
     /// Process next batch of files
     ProcessNextBatch(BatchProcessingJob),
+    /// A batch's files have been read, in the background
+    BatchTracksRead(BatchProcessingJob, Vec<core::Track>),
     /// Batch processing complete notification
     BatchProcessingComplete(usize, usize, u32),
     /// Directory scan result for batch processing
@@ -79,6 +81,11 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
                 state.player_state.shuffle_enabled = shuffle_before;
                 state.check_for_completed_tracks();
             }
+            state.check_loudness_analysis();
+            state.check_library_scan();
+            state.check_library_watcher();
+            state.check_device_events();
+            state.autosave_session();
 
             // Clear status message if expired
             if let (Some(time), Some(duration)) = (state.status_message_time, state.status_message_duration) {
@@ -204,49 +211,29 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
             }
 
             let end_index = std::cmp::min(job_clone.current_index + job_clone.batch_size, job_clone.files.len());
-            
-            let mut tracks = Vec::new();
-            let playlist_id = job_clone.playlist_id;
-            let mut _processed_in_batch = 0; // Added underscore to fix warning
-            let mut _failed_in_batch = 0;    // Added underscore to fix warning
-
-            for i in job_clone.current_index..end_index {
-                if let Some(file_path) = job_clone.files.get(i) {
-                    match std::fs::canonicalize(file_path) {
-                        Ok(abs_path) => {
-                            let path_str = abs_path.to_string_lossy().to_string();
-
-                            if core::audio::decoder::is_supported_audio_format(&path_str) {
-                                let filename = abs_path
-                                    .file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("Unknown")
-                                    .to_string();
-
-                                tracks.push(core::Track {
-                                    path: path_str,
-                                    title: Some(filename),
-                                    artist: None,
-                                    album: None,
-                                    play_count: 0,
-                                });
 
+            // Reading tags opens every file, so it happens off the UI thread
+            Task::perform(
+                async move {
+                    let mut tracks = Vec::new();
+                    for file_path in &job_clone.files[job_clone.current_index..end_index] {
+                        match read_added_file(file_path) {
+                            Some(track) => {
+                                tracks.push(track);
                                 job_clone.processed_count += 1;
-                                _processed_in_batch += 1;  // Updated with underscore
-                            } else {
-                                job_clone.failed_count += 1;
-                                _failed_in_batch += 1;     // Updated with underscore
                             }
-                        },
-                        Err(_) => {
-                            job_clone.failed_count += 1;
-                            _failed_in_batch += 1;         // Updated with underscore
+                            None => job_clone.failed_count += 1,
                         }
                     }
-                }
-            }
+                    job_clone.current_index = end_index;
+                    (job_clone, tracks)
+                },
+                |(job, tracks)| Message::BatchTracksRead(job, tracks)
+            )
+        },
 
-            job_clone.current_index = end_index;
+        Message::BatchTracksRead(job_clone, tracks) => {
+            let playlist_id = job_clone.playlist_id;
 
             if !tracks.is_empty() {
                 state.handle_action(core::Action::Playlist(
@@ -305,37 +292,15 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
             )
         },
 
-        Message::BatchProcessingResult(files, playlist_id) => {
-            if files.is_empty() {
+        Message::BatchProcessingResult(tracks, playlist_id) => {
+            if tracks.is_empty() {
                 return Task::perform(
                     async { sleep(Duration::from_millis(1)).await; },
                     |_| Message::SetStatusMessage("No valid files to add".to_string(), Duration::from_secs(2))
                 );
             }
 
-            let mut tracks = Vec::with_capacity(files.len());
-            let mut supported_count = 0;
-
-            for (path_str, abs_path) in files {
-                if core::audio::decoder::is_supported_audio_format(&path_str) {
-                    let filename = abs_path
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("Unknown")
-                        .to_string();
-
-                    tracks.push(core::Track {
-                        path: path_str.clone(),
-                        title: Some(filename),
-                        artist: None,
-                        album: None,
-                        play_count: 0,
-                    });
-
-                    supported_count += 1;
-                }
-            }
-
+            let supported_count = tracks.len();
             if !tracks.is_empty() {
                 // Explicitly use both variants - the core action and UI action
                 let tracks_for_ui = tracks.clone();
@@ -374,12 +339,9 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
                         core::PlayerAction::Seek(pos) => {
                             state.playlist_view_state.is_seeking = true;
                             state.player.clear_audio_buffers();
-                            if let Ok(mut lock) = state.player.playback_position.lock() {
-                                lock.request_seek(pos);
-                                log::debug!("(Action) Requested seek to {:.4}", pos);
-                            } else {
-                                log::error!("(Action) Failed to acquire lock for seek to {:.4}", pos);
-                            }
+                            // The player ignores seeks on live streams
+                            state.player.seek(pos);
+                            log::debug!("(Action) Requested seek to {:.4}", pos);
                             state.player_state.progress = pos;
                             Task::perform(
                                 async { sleep(Duration::from_millis(50)).await; },
@@ -392,7 +354,7 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
                             Task::none() // Volume change is synchronous
                         },
                         core::PlayerAction::Shuffle => {
-                            state.player_state.shuffle_enabled = !state.player_state.shuffle_enabled;
+                            state.toggle_shuffle();
                             Task::none() // Shuffle toggle is synchronous
                         },
                         core::PlayerAction::Play(ref path) => {
@@ -507,18 +469,14 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
                             Task::none()
                         },
                         core::PlayerAction::Shuffle => {
-                            state.player_state.shuffle_enabled = !state.player_state.shuffle_enabled;
+                            state.toggle_shuffle();
                             Task::none()
                         },
                         core::PlayerAction::Seek(pos) => {
                             state.playlist_view_state.is_seeking = true;
                             state.player.clear_audio_buffers();
-                            if let Ok(mut lock) = state.player.playback_position.lock() {
-                                lock.request_seek(pos);
-                                log::debug!("(PlayerControl) Seek request set for {:.4}", pos);
-                            } else {
-                                log::error!("(PlayerControl) Failed to acquire lock for seek request");
-                            }
+                            state.player.seek(pos);
+                            log::debug!("(PlayerControl) Seek request set for {:.4}", pos);
                             state.player_state.progress = pos;
                             Task::perform(
                                 async { sleep(Duration::from_millis(100)).await; },
@@ -661,16 +619,8 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
                         let playlist_id_clone = playlist_id;
                         
                         Task::perform(
-                            async move {
-                                match std::fs::canonicalize(&path_clone) {
-                                    Ok(abs_path) => {
-                                        let path_str = abs_path.to_string_lossy().to_string();
-                                        vec![(path_str, abs_path)]
-                                    },
-                                    _ => vec![],
-                                }
-                            },
-                            move |files| Message::BatchProcessingResult(files, playlist_id_clone)
+                            async move { read_added_file(&path_clone).into_iter().collect() },
+                            move |tracks| Message::BatchProcessingResult(tracks, playlist_id_clone)
                         )
                     }
                 } else {
@@ -690,6 +640,24 @@ fn update(state: &mut MediaPlayer, message: Message) -> Task<Message> {
     } // End main match
 }
 
+// Read a dropped or added file, None if it isn't audio that can be played. Local files are
+// opened once, which both checks the format and reads the tags; network files are only
+// checked by extension, opening them all would take far too long.
+fn read_added_file(path: &Path) -> Option<core::Track> {
+    let path_str = fs::canonicalize(path).ok()?.to_string_lossy().to_string();
+    if core::audio::decoder::is_network_path(&path_str) {
+        return core::audio::decoder::is_supported_audio_format(&path_str)
+            .then(|| core::library::read_track(&path_str));
+    }
+    match core::library::scan_file(&path_str) {
+        Ok(track) => Some(track),
+        Err(e) => {
+            log::debug!("Skipping {}: {}", path_str, e);
+            None
+        }
+    }
+}
+
 // Recursive directory scanner function
 fn scan_directory_recursively(dir: &PathBuf, files: &mut Vec<PathBuf>) {
     info!("SCAN: Scanning directory: {:?}", dir);
@@ -718,6 +686,8 @@ fn view(state: &MediaPlayer) -> Element<Message> {
         &state.player_state,
         &state.playlists,
         &state.library,
+        &state.queue,
+        &state.player.history,
         &state.playlist_view_state,
         &state.status_message,
     );
@@ -1,26 +1,42 @@
 // app/src/states/app_state.rs
 // This file handles the core application state and actions
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::{Instant, Duration};
 use log::{debug, error, info};
-use core::{Action, PlayerAction, PlaylistAction, LibraryAction, Track, Player, PlayerState, PlaybackStatus, PlaylistState, LibraryState};
+use core::{Action, PlayerAction, PlaylistAction, LibraryAction, QueueAction, Track, PlayQueue, QueueSource, Shuffler, Player, PlayerState, PlaybackStatus, PlaylistState, LibraryState, LibraryScanner, LibraryStore, LibraryChange, LibraryWatcher, FileFingerprint, PlayerSettings, PlaybackHistory, RepeatMode, Session};
+use core::audio::analysis::BatchAnalysis;
 use crate::states::playlist_state::PlaylistViewState;
-use rand::Rng; // For picking random track if shuffle is on
 use anyhow::Result;
 use std::fs;
 
+// How often the session is saved while the app runs
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+// Stopping this close to the end of a long track counts as having heard it
+const RESUME_FINISHED_MARGIN_SECS: f64 = 10.0;
+
 pub struct MediaPlayer {
     pub player: Player,
     pub player_state: PlayerState,
     pub playlists: PlaylistState,
     pub library: LibraryState,
+    pub queue: PlayQueue,                            // What plays next, independent of the selected playlist
+    pub shuffler: Shuffler,                          // Shuffles the queue when shuffle is on
+    pub settings: PlayerSettings,
     pub data_dir: PathBuf,
     pub playlist_view_state: PlaylistViewState,
     pub status_message: Option<String>,              // For displaying status messages
     pub status_message_time: Option<Instant>,        // When the message was set
     pub status_message_duration: Option<Duration>,   // How long to show it
     pub is_batch_processing: bool,                   // Track when batch processing is active
+    pub loudness_analysis: Option<BatchAnalysis>,    // Background loudness analysis, if running
+    pub library_scan: Option<LibraryScanner>,        // Background library scan, if running
+    pub library_watcher: Option<LibraryWatcher>,     // Watches the scan directories for changes
+    pub watched_changes: Vec<PathBuf>,               // Changed files waiting for the running scan to finish
+    pub store: Option<LibraryStore>,                 // data/library.db, None if it couldn't be opened
+    pub playlists_changed: bool,                     // Playlists created, deleted or renamed since the last save to the database
+    pub session_saved_at: Instant,                   // Last time data/session.json was written
 }
 
 impl std::fmt::Debug for MediaPlayer {
@@ -30,9 +46,16 @@ impl std::fmt::Debug for MediaPlayer {
             .field("player_state", &self.player_state)
             .field("playlists", &self.playlists)
             .field("library", &self.library)
+            .field("queue", &self.queue)
+            .field("settings", &self.settings)
             .field("data_dir", &self.data_dir)
             .field("status_message", &self.status_message)
             .field("is_batch_processing", &self.is_batch_processing)
+            .field("loudness_analysis", &self.loudness_analysis.as_ref().map(|a| a.progress()))
+            .field("library_scan", &self.library_scan.as_ref().map(|s| s.progress()))
+            .field("library_watcher", &self.library_watcher.as_ref().map(|w| w.is_watching()))
+            .field("watched_changes", &self.watched_changes.len())
+            .field("store", &self.store.is_some())
             .finish()
     }
 }
@@ -44,41 +67,139 @@ impl Default for MediaPlayer {
             std::fs::create_dir_all(&data_dir).expect("Failed to create data directory");
         }
 
+        let store = match LibraryStore::open(&data_dir.join("library.db")) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                error!("Failed to open library database, falling back to JSON: {}", e);
+                None
+            }
+        };
+
+        // Playlists live in the database once they've been saved there; until then
+        // (first run after upgrading, or no database) they come from the JSON file
+        let stored_playlists = match store.as_ref().map(|s| s.load_playlists()) {
+            Some(Ok(playlists)) => playlists,
+            Some(Err(e)) => {
+                error!("Failed to load playlists from the library database: {}", e);
+                None
+            }
+            None => None,
+        };
         let playlists_path = data_dir.join("playlists.json");
-        let playlists = if playlists_path.exists() {
-            match PlaylistState::load_from_file(&playlists_path) {
-                Ok(pl) => pl,
-                Err(e) => {
-                    error!("Failed to load playlists: {}", e);
-                    PlaylistState::new()
+        let playlists = match stored_playlists {
+            Some(playlists) => playlists,
+            None if playlists_path.exists() => {
+                match PlaylistState::load_from_file(&playlists_path) {
+                    Ok(pl) => {
+                        if let Some(store) = &store {
+                            info!("Importing playlists.json into the library database");
+                            if let Err(e) = store.save_playlists(&pl) {
+                                error!("Failed to import playlists: {}", e);
+                            }
+                        }
+                        pl
+                    }
+                    Err(e) => {
+                        error!("Failed to load playlists: {}", e);
+                        PlaylistState::new()
+                    }
                 }
             }
-        } else {
-            PlaylistState::new()
+            None => PlaylistState::new(),
+        };
+
+        // Only album summaries are loaded, the tracks stay in the database
+        let mut library = LibraryState::new();
+        if let Some(store) = &store {
+            match (store.albums(None), store.track_count()) {
+                (Ok(albums), Ok(count)) => {
+                    info!("Library database has {} tracks in {} albums", count, albums.len());
+                    library.albums = albums;
+                    library.track_count = count;
+                }
+                (Err(e), _) | (_, Err(e)) => error!("Failed to read the library database: {}", e),
+            }
+        }
+
+        let settings_path = data_dir.join("settings.json");
+        let settings = match PlayerSettings::load_from_file(&settings_path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("Failed to load settings: {}", e);
+                PlayerSettings::new()
+            }
         };
 
         // Initialize the player with FFmpeg
         let mut player = Player::new();
         // Configure network buffer settings
         player.configure_network(8 * 1024 * 1024, 5); // 8MB buffer, 5 second pre-buffering
+        player.set_crossfade(settings.crossfade.clone());
+        player.set_normalization(settings.normalization.clone());
+        player.set_equalizer(settings.equalizer.clone());
+        player.set_speed(settings.playback_speed);
+        player.set_repeat_mode(settings.repeat_mode);
+        player.set_shuffle_mode(settings.shuffle_mode);
+        if let Err(e) = player.set_output_device(settings.output_device.clone()) {
+            error!("Failed to select output device: {}", e);
+        }
+        player.history = match PlaybackHistory::load_from_file(&data_dir.join("history.json")) {
+            Ok(history) => history,
+            Err(e) => {
+                error!("Failed to load playback history: {}", e);
+                PlaybackHistory::new()
+            }
+        };
+
+        // Previously analyzed tracks can be normalized even without tags
+        for playlist in &playlists.playlists {
+            for track in &playlist.tracks {
+                if let Some(loudness) = track.loudness {
+                    player.set_loudness_hint(&track.path, loudness);
+                }
+            }
+        }
         let mut player_state = player.get_state();
         // Make sure shuffle starts off
         player_state.shuffle_enabled = false;
 
+        let session = match Session::load_from_file(&data_dir.join("session.json")) {
+            Ok(session) => session,
+            Err(e) => {
+                error!("Failed to load last session: {}", e);
+                None
+            }
+        };
+
         info!("MediaPlayer default state created");
 
-        Self {
+        let mut media_player = Self {
             player,
             player_state,
             playlists,
-            library: LibraryState::new(),
+            library,
+            queue: PlayQueue::new(),
+            shuffler: Shuffler::new(),
+            settings,
             data_dir,
             playlist_view_state: PlaylistViewState::new(),
             status_message: None,
             status_message_time: None,
             status_message_duration: None,
             is_batch_processing: false,
+            loudness_analysis: None,
+            library_scan: None,
+            library_watcher: None,
+            watched_changes: Vec::new(),
+            store,
+            playlists_changed: false,
+            session_saved_at: Instant::now(),
+        };
+        if let Some(session) = session {
+            media_player.restore_session(session);
         }
+        media_player.restart_library_watcher();
+        media_player
     }
 }
 
@@ -89,69 +210,285 @@ impl MediaPlayer {
             Action::Player(act) => self.handle_player_action(act),
             Action::Playlist(act) => self.handle_playlist_action(act),
             Action::Library(act) => self.handle_library_action(act),
+            Action::Queue(act) => self.handle_queue_action(act),
         }
         // Always update the player state after handling actions
         self.player_state = self.player.get_state();
     }
     
-    // Helper method to get a smart-shuffled track index
-    fn get_smart_shuffled_track_index(&self, playlist_id: u32) -> Option<usize> {
-        if let Some(playlist) = self.playlists.get_playlist(playlist_id) {
-            if playlist.tracks.is_empty() {
-                return None;
+    // Nothing queued yet: where to start the selected playlist, as (playlist id, track index)
+    fn selected_playlist_start(&mut self, from_end: bool) -> Option<(u32, usize)> {
+        let playlist = self.playlists.playlists.get(self.playlists.selected?)?;
+        let last_idx = playlist.tracks.len().checked_sub(1)?;
+        let track_idx = if self.player_state.shuffle_enabled {
+            // Wherever the shuffle order would start
+            self.shuffler.order(self.player_state.shuffle_mode, &playlist.tracks)[0]
+        } else if from_end {
+            last_idx
+        } else {
+            0
+        };
+        Some((playlist.id, track_idx))
+    }
+    
+    // Queue position next/previous should move to, ignoring repeat-one. With shuffle on
+    // the queue is already in shuffled order. Only repeat-all wraps around the ends.
+    fn step_queue(&self, forward: bool) -> Option<usize> {
+        self.queue.step(forward, self.player_state.repeat_mode == RepeatMode::All)
+    }
+    
+    pub fn toggle_shuffle(&mut self) {
+        self.player_state.shuffle_enabled = !self.player_state.shuffle_enabled;
+        if self.player_state.shuffle_enabled {
+            info!("Shuffle enabled ({:?})", self.player_state.shuffle_mode);
+            self.queue.shuffle(&mut self.shuffler, self.player_state.shuffle_mode);
+        } else {
+            info!("Shuffle disabled");
+            // Carry on in playlist order from the current track
+            if let Some(QueueSource::Playlist(playlist_id)) = self.queue.source.clone() {
+                if let Some(playlist) = self.playlists.get_playlist(playlist_id) {
+                    self.queue.restore_order(playlist.tracks.clone());
+                }
             }
-            
-            // Find the minimum play count in the playlist
-            let min_play_count = playlist.tracks
-                .iter()
-                .map(|track| track.play_count)
-                .min()
-                .unwrap_or(0);
-            
-            // Filter tracks that have this minimum play count
-            let candidate_tracks: Vec<usize> = playlist.tracks
-                .iter()
-                .enumerate()
-                .filter(|(_, track)| track.play_count == min_play_count)
-                .map(|(i, _)| i)
-                .collect();
-            
-            if !candidate_tracks.is_empty() {
-                // Pick a random track from the filtered list
-                let random_idx = rand::thread_rng().gen_range(0..candidate_tracks.len());
-                return Some(candidate_tracks[random_idx]);
+        }
+        // The queued track depends on the shuffle mode
+        self.queue_next_track();
+    }
+    
+    // Pick the queue position that should follow the current track.
+    // None means playback stops after the current track.
+    fn get_following_track(&self) -> Option<usize> {
+        let current_idx = self.queue.current_index()?;
+        match self.player_state.repeat_mode {
+            RepeatMode::StopAfterCurrent => None,
+            RepeatMode::One => Some(current_idx),
+            RepeatMode::Off | RepeatMode::All => self.step_queue(true),
+        }
+    }
+    
+    // Play a track from the history, at its place in the queue if it's still there
+    fn play_history_track(&mut self, path: String) {
+        if let Some(queue_idx) = self.queue.find_following(&path) {
+            self.play_queue_track(queue_idx);
+        } else {
+            let shuffle_enabled = self.player_state.shuffle_enabled;
+            self.handle_action(core::Action::Player(core::PlayerAction::Play(path)));
+            self.player_state.shuffle_enabled = shuffle_enabled;
+            self.queue_next_track();
+        }
+    }
+    
+    fn get_following_track_path(&self) -> Option<String> {
+        let queue_idx = self.get_following_track()?;
+        self.queue.get(queue_idx).map(|track| track.path.clone())
+    }
+    
+    // Make a queue position the current track and play it
+    fn play_queue_track(&mut self, queue_idx: usize) {
+        let Some(track) = self.queue.jump(queue_idx) else {
+            return;
+        };
+        let path = track.path.clone();
+        // Long-form audio picks up where listening stopped
+        let resume_at = track.resume_position
+            .filter(|_| !track.finished)
+            .map(|secs| Duration::from_secs_f64(secs.max(0.0)));
+        
+        // Check if it's a network path before playing
+        if path.starts_with("\\\\") || path.contains("://") {
+            self.status_message = Some(format!("Loading network track: {}", 
+                track.title.as_deref().unwrap_or("Unknown")));
+            self.status_message_time = Some(Instant::now());
+            self.status_message_duration = Some(Duration::from_secs(5));
+        }
+        
+        // Playing refreshes player_state, which doesn't carry the shuffle flag
+        let shuffle_enabled = self.player_state.shuffle_enabled;
+        let action = match resume_at {
+            Some(position) => {
+                info!("Resuming {} at {:.1}s", path, position.as_secs_f64());
+                core::PlayerAction::PlayFrom(path, position)
             }
+            None => core::PlayerAction::Play(path),
+        };
+        self.handle_action(core::Action::Player(action));
+        self.player_state.shuffle_enabled = shuffle_enabled;
+        self.queue_next_track();
+    }
+    
+    // Tell the player what comes next so it can continue without a gap
+    pub fn queue_next_track(&mut self) {
+        let next = if self.player_state.current_track.is_some() {
+            self.get_following_track_path()
+        } else {
+            None
+        };
+        
+        if let Some(path) = &next {
+            debug!("Queued next track for gapless playback: {}", path);
         }
-        None
+        self.player.set_next_track(next);
     }
     
-    fn handle_player_action(&mut self, action: PlayerAction) {
-        match action {
-            PlayerAction::Play(path) => {
-                info!("Attempting to play file: {}", path);
-                
-                // For network paths, show a status message but don't do special buffering
-                if path.starts_with("\\\\") || path.contains("://") {
-                    self.status_message = Some(format!("Direct streaming: {}", 
-                        std::path::Path::new(&path)
-                            .file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("Unknown")));
-                    self.status_message_time = Some(Instant::now());
-                    self.status_message_duration = Some(Duration::from_secs(3));
+    fn increment_play_count(&mut self, track_path: &str) {
+        // Find and update the track's play count in the playlists
+        for playlist in &mut self.playlists.playlists {
+            for track in &mut playlist.tracks {
+                if track.path == track_path {
+                    track.play_count += 1;
+                    info!("Updated play count for '{}' to {}", 
+                          track.title.as_ref().unwrap_or(&track.path), 
+                          track.play_count);
+                    break;
                 }
-                
-                // Play the file directly - no buffering needed
-                if let Err(e) = self.player.play(&path) {
-                    error!("Failed to play: {}", e);
-                    self.status_message = Some(format!("Error: {}", e));
-                    self.status_message_time = Some(Instant::now());
-                    self.status_message_duration = Some(Duration::from_secs(3));
-                } else {
-                    info!("Started playback successfully");
+            }
+        }
+        self.queue.update_track(track_path, |track| track.play_count += 1);
+        // Library tracks that aren't in any playlist are counted in the database
+        if let Some(store) = &self.store {
+            if let Err(e) = store.record_play(track_path) {
+                error!("Failed to record play: {}", e);
+            }
+        }
+    }
+    
+    // Apply a change to every copy of a track: playlists, library and queue
+    fn update_track(&mut self, path: &str, update: impl Fn(&mut Track)) {
+        for playlist in &mut self.playlists.playlists {
+            for track in playlist.tracks.iter_mut().filter(|t| t.path == path) {
+                update(track);
+            }
+        }
+        for track in self.library.tracks.iter_mut().filter(|t| t.path == path) {
+            update(track);
+        }
+        self.queue.update_track(path, update);
+    }
+    
+    fn find_track(&self, path: &str) -> Option<&Track> {
+        self.playlists.playlists.iter()
+            .flat_map(|playlist| playlist.tracks.iter())
+            .chain(self.library.tracks.iter())
+            .chain(self.queue.tracks().iter())
+            .find(|t| t.path == path)
+    }
+    
+    // Remember where listening got to in the current track, if it's long enough to be
+    // worth resuming. Returns whether anything changed that needs saving.
+    fn remember_resume_position(&mut self) -> bool {
+        let state = &self.player_state;
+        if !matches!(state.status, PlaybackStatus::Playing | PlaybackStatus::Paused) {
+            return false;
+        }
+        let (Some(path), Some(duration), Some(position)) =
+            (state.current_track.clone(), state.duration, state.position) else {
+            return false;
+        };
+        if duration.as_secs() < self.settings.resume_min_duration_secs as u64 {
+            return false;
+        }
+        
+        let duration_secs = duration.as_secs_f64();
+        let position_secs = position.as_secs_f64();
+        let finished = duration_secs - position_secs <= RESUME_FINISHED_MARGIN_SECS;
+        let resume_position = (!finished).then_some(position_secs);
+        
+        let unchanged = self.find_track(&path).is_some_and(|track| {
+            track.finished == finished
+                && track.duration_secs == Some(duration_secs)
+                && match (track.resume_position, resume_position) {
+                    (Some(saved), Some(now)) => (saved - now).abs() < 1.0,
+                    (saved, now) => saved.is_none() && now.is_none(),
                 }
+        });
+        if unchanged {
+            return false;
+        }
+        
+        debug!("Resume position for {}: {:?} (finished: {})", path, resume_position, finished);
+        self.update_track(&path, |track| {
+            track.resume_position = resume_position;
+            track.duration_secs = Some(duration_secs);
+            track.finished = finished;
+        });
+        true
+    }
+    
+    // Keep resume positions on disk, skipped while a big import is running. Without a
+    // database they go out with the session's queue instead, rewriting playlists.json
+    // every few seconds while playing would be far too much.
+    fn save_resume_position(&mut self) {
+        if self.remember_resume_position() && !self.is_batch_processing && self.store.is_some() {
+            if let Some(path) = self.player_state.current_track.clone() {
+                self.save_play_state(&path);
+            }
+        }
+    }
+    
+    // Write a track's play count, resume position and finished flag. The database only
+    // needs that one track updated, without it the playlists are saved.
+    fn save_play_state(&mut self, path: &str) {
+        let result = match &self.store {
+            Some(store) => self.find_track(path).map_or(Ok(()), |track| store.save_play_state(track)),
+            None => self.save_playlists_file(),
+        };
+        if let Err(e) = result {
+            error!("Failed to save play state for {}: {}", path, e);
+        }
+    }
+    
+    // Long-form tracks played to the end start from the beginning next time
+    fn mark_heard(&mut self, track_path: &str) {
+        let min_duration = self.settings.resume_min_duration_secs as f64;
+        self.update_track(track_path, |track| {
+            if track.duration_secs.is_some_and(|d| d >= min_duration) {
+                track.mark_finished();
+            }
+        });
+    }
+    
+    fn start_track(&mut self, path: String, position: Duration) {
+        info!("Attempting to play file: {}", path);
+        // The track being left keeps its place
+        self.save_resume_position();
+        
+        // For network paths, show a status message but don't do special buffering
+        if path.starts_with("\\\\") || path.contains("://") {
+            self.status_message = Some(format!("Direct streaming: {}", 
+                std::path::Path::new(&path)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("Unknown")));
+            self.status_message_time = Some(Instant::now());
+            self.status_message_duration = Some(Duration::from_secs(3));
+        }
+        
+        // Play the file directly - no buffering needed
+        if let Err(e) = self.player.play_from(&path, position) {
+            error!("Failed to play: {}", e);
+            self.status_message = Some(format!("Error: {}", e));
+            self.status_message_time = Some(Instant::now());
+            self.status_message_duration = Some(Duration::from_secs(3));
+        } else {
+            info!("Started playback successfully");
+            if let Err(e) = self.save_history() {
+                error!("Failed to save playback history: {}", e);
             }
-            PlayerAction::Pause => self.player.pause(),
+        }
+    }
+    
+    fn handle_player_action(&mut self, action: PlayerAction) {
+        match action {
+            PlayerAction::Play(path) => self.start_track(path, Duration::ZERO),
+            PlayerAction::PlayFrom(path, position) => self.start_track(path, position),
+            PlayerAction::Pause => {
+                self.save_resume_position();
+                self.player.pause();
+                // Closing the app while paused should come back to exactly this spot
+                if let Err(e) = self.save_session() {
+                    error!("Failed to save session: {}", e);
+                }
+            },
             PlayerAction::Resume => {
                 // Check if we're already playing or paused
                 if self.player_state.status == PlaybackStatus::Paused {
@@ -160,182 +497,169 @@ impl MediaPlayer {
                     self.player.resume();
                 } else if self.player_state.status == PlaybackStatus::Stopped {
                     // New logic to start playing a track when nothing is playing
-                    info!("Starting playback from Now Playing list");
+                    info!("Starting playback from the queue");
                     
-                    // Check if we have a selected playlist
-                    if let Some(idx) = self.playlists.selected {
-                        if idx < self.playlists.playlists.len() {
-                            let playlist = &self.playlists.playlists[idx];
-                            
-                            if !playlist.tracks.is_empty() {
-                                let track_idx = if self.player_state.shuffle_enabled {
-                                    // Get a smart-shuffled track index
-                                    if let Some(idx) = self.get_smart_shuffled_track_index(playlist.id) {
-                                        idx
-                                    } else {
-                                        0 // Fallback to first track
-                                    }
-                                } else {
-                                    // No shuffle, just start with the first track
-                                    0
-                                };
-                                
-                                let track = &playlist.tracks[track_idx];
-                                info!("Auto-playing track: {}", track.title.as_ref().unwrap_or(&track.path));
-                                
-                                // Play the selected track
-                                self.handle_action(core::Action::Playlist(
-                                    core::PlaylistAction::PlayTrack(playlist.id, track_idx)
-                                ));
-                            }
-                        }
+                    // Pick up the queue where it was, or start the selected playlist
+                    if let Some(queue_idx) = self.queue.current_index().or(self.queue.step(true, false)) {
+                        self.play_queue_track(queue_idx);
+                    } else if let Some((playlist_id, track_idx)) = self.selected_playlist_start(false) {
+                        info!("Auto-playing track {} of playlist {}", track_idx, playlist_id);
+                        self.handle_action(core::Action::Playlist(
+                            core::PlaylistAction::PlayTrack(playlist_id, track_idx)
+                        ));
                     }
                 }
             },
-            PlayerAction::Stop => self.player.stop(),
+            PlayerAction::Stop => {
+                self.save_resume_position();
+                self.player.stop();
+            },
             PlayerAction::SetVolume(v) => self.player.set_volume(v),
             PlayerAction::Seek(pos) => self.player.seek(pos),
+            PlayerAction::SeekTo(position) => self.player.seek_to(position),
             PlayerAction::SkipForward(seconds) => {
-                if let Some(current) = self.player_state.position {
-                    if let Some(duration) = self.player_state.duration {
-                        // Calculate relative position
-                        let total_secs = duration.as_secs_f32();
-                        let current_secs = current.as_secs_f32();
-                        let new_secs = (current_secs + seconds).min(total_secs);
-                        let new_pos = new_secs / total_secs;
-                        info!("Skipping forward {} seconds to position {:.2}", seconds, new_pos);
-                        self.player.seek(new_pos);
-                    }
-                }
+                info!("Skipping forward {} seconds", seconds);
+                self.player.seek_by(seconds as f64);
             },
             PlayerAction::SkipBackward(seconds) => {
-                if let Some(current) = self.player_state.position {
-                    if let Some(duration) = self.player_state.duration {
-                        // Calculate relative position
-                        let total_secs = duration.as_secs_f32();
-                        let current_secs = current.as_secs_f32();
-                        let new_secs = (current_secs - seconds).max(0.0);
-                        let new_pos = new_secs / total_secs;
-                        info!("Skipping backward {} seconds to position {:.2}", seconds, new_pos);
-                        self.player.seek(new_pos);
-                    }
-                }
+                info!("Skipping backward {} seconds", seconds);
+                self.player.seek_by(-(seconds as f64));
             },
-            PlayerAction::Shuffle => {
-                // Toggle shuffle mode
-                self.player_state.shuffle_enabled = !self.player_state.shuffle_enabled;
+            PlayerAction::Shuffle => self.toggle_shuffle(),
+            PlayerAction::CycleShuffleMode => {
+                let mode = self.player.shuffle_mode().next();
+                self.player.set_shuffle_mode(mode);
+                self.player_state.shuffle_mode = mode;
+                self.settings.shuffle_mode = mode;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
                 if self.player_state.shuffle_enabled {
-                    info!("Shuffle enabled");
-                } else {
-                    info!("Shuffle disabled");
+                    self.queue.shuffle(&mut self.shuffler, mode);
+                    self.queue_next_track();
                 }
             },
             PlayerAction::NextTrack => {
-                // Code for next track with smart shuffle consideration
                 info!("Next track button pressed");
                 
-                if let Some(idx) = self.playlists.selected {
-                    if idx < self.playlists.playlists.len() {
-                        let playlist = &self.playlists.playlists[idx];
-                        
-                        if self.player_state.shuffle_enabled {
-                            // If shuffle is enabled, use smart shuffle logic
-                            if !playlist.tracks.is_empty() {
-                                if let Some(track_idx) = self.get_smart_shuffled_track_index(playlist.id) {
-                                    let track = &playlist.tracks[track_idx];
-                                    info!("Playing least-played track: {} (play count: {})", 
-                                          track.title.as_deref().unwrap_or(&track.path), track.play_count);
-                                    
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, track_idx)
-                                    ));
-                                }
-                            }
-                        } else {
-                            // Original sequential next track logic (unchanged)
-                            if let Some(current_track_path) = &self.player_state.current_track {
-                                let current_idx = playlist.tracks.iter()
-                                    .position(|track| &track.path == current_track_path);
-                                    
-                                if let Some(idx) = current_idx {
-                                    let next_idx = (idx + 1) % playlist.tracks.len();
-                                    
-                                    let track = &playlist.tracks[next_idx];
-                                    info!("Playing next track: {}", track.path);
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, next_idx)
-                                    ));
-                                } else if !playlist.tracks.is_empty() {
-                                    // Current track not in playlist, start with first
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, 0)
-                                    ));
-                                }
-                            } else if !playlist.tracks.is_empty() {
-                                // No track playing, start with first
-                                self.handle_action(core::Action::Playlist(
-                                    core::PlaylistAction::PlayTrack(playlist.id, 0)
-                                ));
-                            }
-                        }
+                if self.queue.is_empty() {
+                    if let Some((playlist_id, track_idx)) = self.selected_playlist_start(false) {
+                        self.handle_action(core::Action::Playlist(
+                            core::PlaylistAction::PlayTrack(playlist_id, track_idx)
+                        ));
                     }
+                } else if let Some(queue_idx) = self.step_queue(true) {
+                    self.play_queue_track(queue_idx);
+                } else {
+                    info!("Already at the end of the queue and repeat is not on");
+                }
+            },
+            PlayerAction::CycleRepeatMode => {
+                let mode = self.player.repeat_mode().next();
+                self.player.set_repeat_mode(mode);
+                self.player_state.repeat_mode = mode;
+                self.settings.repeat_mode = mode;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+                // The queued track depends on the repeat mode
+                self.queue_next_track();
+            },
+            PlayerAction::SetCrossfade(crossfade) => {
+                self.player.set_crossfade(crossfade.clone());
+                self.settings.crossfade = crossfade;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+            },
+            PlayerAction::SetSpeed(speed) => {
+                self.player.set_speed(speed);
+                self.settings.playback_speed = self.player.speed();
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+            },
+            PlayerAction::SetLoopIn => self.player.set_loop_in(),
+            PlayerAction::SetLoopOut => self.player.set_loop_out(),
+            PlayerAction::ClearLoop => self.player.clear_loop(),
+            PlayerAction::SetNormalization(normalization) => {
+                self.player.set_normalization(normalization.clone());
+                self.settings.normalization = normalization;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+            },
+            PlayerAction::SetEqualizer(equalizer) => {
+                self.player.set_equalizer(equalizer.clone());
+                self.settings.equalizer = equalizer;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+            },
+            PlayerAction::LoadEqPreset(name) => {
+                if let Some(preset) = self.settings.find_eq_preset(&name) {
+                    info!("Loading equalizer preset '{}'", name);
+                    self.player.set_equalizer(preset.settings.clone());
+                    self.settings.equalizer = preset.settings;
+                    if let Err(e) = self.save_settings() {
+                        error!("Failed to save settings: {}", e);
+                    }
+                } else {
+                    error!("Unknown equalizer preset '{}'", name);
+                }
+            },
+            PlayerAction::SaveEqPreset(name) => {
+                info!("Saving equalizer preset '{}'", name);
+                self.settings.save_eq_preset(&name);
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
+                }
+            },
+            PlayerAction::DeleteEqPreset(name) => {
+                if self.settings.delete_eq_preset(&name) {
+                    info!("Deleted equalizer preset '{}'", name);
+                    if let Err(e) = self.save_settings() {
+                        error!("Failed to save settings: {}", e);
+                    }
+                }
+            },
+            PlayerAction::SetOutputDevice(device) => {
+                if let Err(e) = self.player.set_output_device(device.clone()) {
+                    error!("Failed to switch output device: {}", e);
+                    self.status_message = Some(format!("Error: {}", e));
+                    self.status_message_time = Some(Instant::now());
+                    self.status_message_duration = Some(Duration::from_secs(3));
+                }
+                // Restarting the stream clears the queued track
+                let shuffle_enabled = self.player_state.shuffle_enabled;
+                self.player_state = self.player.get_state();
+                self.player_state.shuffle_enabled = shuffle_enabled;
+                self.queue_next_track();
+
+                self.settings.output_device = device;
+                if let Err(e) = self.save_settings() {
+                    error!("Failed to save settings: {}", e);
                 }
             },
             PlayerAction::PreviousTrack => {
-                // Code for previous track (similar to NextTrack but going backwards)
                 info!("Previous track button pressed");
                 
-                if let Some(idx) = self.playlists.selected {
-                    if idx < self.playlists.playlists.len() {
-                        let playlist = &self.playlists.playlists[idx];
-                        
-                        if self.player_state.shuffle_enabled {
-                            // Use smart shuffle for previous as well
-                            if !playlist.tracks.is_empty() {
-                                if let Some(track_idx) = self.get_smart_shuffled_track_index(playlist.id) {
-                                    let track = &playlist.tracks[track_idx];
-                                    info!("Playing least-played track: {} (play count: {})", 
-                                          track.title.as_deref().unwrap_or(&track.path), track.play_count);
-                                    
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, track_idx)
-                                    ));
-                                }
-                            }
-                        } else {
-                            // Original sequential previous track logic
-                            if let Some(current_track_path) = &self.player_state.current_track {
-                                let current_idx = playlist.tracks.iter()
-                                    .position(|track| &track.path == current_track_path);
-                                    
-                                if let Some(idx) = current_idx {
-                                    let prev_idx = if idx == 0 {
-                                        playlist.tracks.len() - 1 // Wrap around to the end
-                                    } else {
-                                        idx - 1
-                                    };
-                                    
-                                    let track = &playlist.tracks[prev_idx];
-                                    info!("Playing previous track: {}", track.path);
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, prev_idx)
-                                    ));
-                                } else if !playlist.tracks.is_empty() {
-                                    // Current track not in playlist, start with last
-                                    let last_idx = playlist.tracks.len() - 1;
-                                    self.handle_action(core::Action::Playlist(
-                                        core::PlaylistAction::PlayTrack(playlist.id, last_idx)
-                                    ));
-                                }
-                            } else if !playlist.tracks.is_empty() {
-                                // No track playing, start with last
-                                let last_idx = playlist.tracks.len() - 1;
-                                self.handle_action(core::Action::Playlist(
-                                    core::PlaylistAction::PlayTrack(playlist.id, last_idx)
-                                ));
-                            }
-                        }
+                if self.player_state.shuffle_enabled {
+                    // The queue order says nothing about what played before, the history does
+                    match self.player.previous_in_history() {
+                        Some(path) => self.play_history_track(path),
+                        None => info!("Nothing earlier in the playback history"),
+                    }
+                } else if self.queue.is_empty() {
+                    if let Some((playlist_id, track_idx)) = self.selected_playlist_start(true) {
+                        self.handle_action(core::Action::Playlist(
+                            core::PlaylistAction::PlayTrack(playlist_id, track_idx)
+                        ));
                     }
+                } else if let Some(queue_idx) = self.step_queue(false) {
+                    self.play_queue_track(queue_idx);
+                } else {
+                    info!("Already at the start of the queue and repeat is not on");
                 }
             },
         }
@@ -346,29 +670,47 @@ impl MediaPlayer {
             PlaylistAction::Create(name) => {
                 self.playlists.create_playlist(name);
                 // Save after creating playlist
+                self.playlists_changed = true;
                 let _ = self.save_playlists();
             },
             PlaylistAction::Delete(id) => {
                 self.playlists.delete_playlist(id);
                 // Save after deleting playlist
+                self.playlists_changed = true;
                 let _ = self.save_playlists();
             },
             PlaylistAction::Select(id) => {
                 if let Some(pos) = self.playlists.playlists.iter().position(|p| p.id == id) {
                     self.playlists.selected = Some(pos);
-                    // No need to save for selection changes
+                    // The file is saved after every action anyway, the database needs telling
+                    if let Some(store) = &self.store {
+                        if let Err(e) = store.save_selected_playlist(Some(pos)) {
+                            error!("Failed to save the selected playlist: {}", e);
+                        }
+                    }
                 }
             },
             PlaylistAction::Rename(id, new_name) => {
                 let renamed = self.playlists.rename_playlist(id, new_name);
                 if renamed {
                     // Save after renaming playlist
+                    self.playlists_changed = true;
                     let _ = self.save_playlists();
                 }
             },
             PlaylistAction::AddTrack(playlist_id, track) => {
                 if let Some(playlist) = self.playlists.get_playlist_mut(playlist_id) {
-                    playlist.tracks.push(track);
+                    if let Some(store) = &self.store {
+                        if let Err(e) = store.add_playlist_tracks(playlist_id, playlist.tracks.len(), std::slice::from_ref(&*track)) {
+                            error!("Failed to store added track: {}", e);
+                        }
+                    }
+                    playlist.tracks.push((*track).clone());
+                    // A playing playlist grows its queue too
+                    if self.queue.source == Some(QueueSource::Playlist(playlist_id)) {
+                        self.queue.extend([*track]);
+                        self.queue_next_track();
+                    }
                     // Save after adding track
                     let _ = self.save_playlists();
                 }
@@ -377,6 +719,11 @@ impl MediaPlayer {
                 if let Some(playlist) = self.playlists.get_playlist_mut(playlist_id) {
                     if index < playlist.tracks.len() {
                         playlist.tracks.remove(index);
+                        if let Some(store) = &self.store {
+                            if let Err(e) = store.remove_playlist_track(playlist_id, index) {
+                                error!("Failed to remove stored playlist track: {}", e);
+                            }
+                        }
                         // Save after removing track
                         let _ = self.save_playlists();
                     }
@@ -385,7 +732,17 @@ impl MediaPlayer {
             PlaylistAction::BatchAddTracks(playlist_id, tracks) => {
                 if let Some(playlist) = self.playlists.get_playlist_mut(playlist_id) {
                     info!("Adding batch of {} tracks to playlist {}", tracks.len(), playlist_id);
-                    playlist.tracks.extend(tracks);
+                    // The database takes every batch as it comes, even during a big import
+                    if let Some(store) = &self.store {
+                        if let Err(e) = store.add_playlist_tracks(playlist_id, playlist.tracks.len(), &tracks) {
+                            error!("Failed to store added tracks: {}", e);
+                        }
+                    }
+                    playlist.tracks.extend(tracks.iter().cloned());
+                    if self.queue.source == Some(QueueSource::Playlist(playlist_id)) {
+                        self.queue.extend(tracks);
+                        self.queue_next_track();
+                    }
                     
                     // Only save if we're not in the middle of a large batch operation
                     if !self.is_batch_processing {
@@ -397,22 +754,40 @@ impl MediaPlayer {
                     }
                 }
             },
+            PlaylistAction::AnalyzeLoudness(playlist_id) => {
+                if self.loudness_analysis.is_some() {
+                    info!("Loudness analysis already running");
+                    self.status_message = Some("Loudness analysis already running".to_string());
+                    self.status_message_time = Some(Instant::now());
+                    self.status_message_duration = Some(Duration::from_secs(3));
+                } else if let Some(playlist) = self.playlists.get_playlist(playlist_id) {
+                    if !playlist.tracks.is_empty() {
+                        info!("Analyzing loudness of playlist '{}'", playlist.name);
+                        self.loudness_analysis = Some(BatchAnalysis::start(playlist.tracks.clone()));
+                    }
+                }
+            },
+            PlaylistAction::MarkFinished(playlist_id, track_idx) => {
+                let path = self.playlists.get_playlist(playlist_id)
+                    .and_then(|playlist| playlist.tracks.get(track_idx))
+                    .map(|track| track.path.clone());
+                if let Some(path) = path {
+                    info!("Marking {} as finished", path);
+                    self.update_track(&path, |track| track.mark_finished());
+                    self.save_play_state(&path);
+                }
+            },
             PlaylistAction::PlayTrack(playlist_id, track_idx) => {
                 if let Some(playlist) = self.playlists.get_playlist(playlist_id) {
                     if track_idx < playlist.tracks.len() {
-                        let track = &playlist.tracks[track_idx];
-                        
-                        // Check if it's a network path before playing
-                        if track.path.starts_with("\\\\") || track.path.contains("://") {
-                            self.status_message = Some(format!("Loading network track: {}", 
-                                track.title.as_deref().unwrap_or("Unknown")));
-                            self.status_message_time = Some(Instant::now());
-                            self.status_message_duration = Some(Duration::from_secs(5));
+                        // The playlist becomes the queue, later browsing leaves it alone
+                        self.queue.start(QueueSource::Playlist(playlist_id), playlist.tracks.clone(), track_idx);
+                        if self.player_state.shuffle_enabled {
+                            self.queue.shuffle(&mut self.shuffler, self.player_state.shuffle_mode);
+                        }
+                        if let Some(queue_idx) = self.queue.current_index() {
+                            self.play_queue_track(queue_idx);
                         }
-                        
-                        self.handle_action(core::Action::Player(
-                            core::PlayerAction::Play(track.path.clone())
-                        ));
                     }
                 }
             },
@@ -420,63 +795,59 @@ impl MediaPlayer {
         }
     }
     
+    fn handle_queue_action(&mut self, action: QueueAction) {
+        match action {
+            QueueAction::PlayNext(track) => self.queue.play_next(*track),
+            QueueAction::AddToQueue(track) => self.queue.add_to_queue(*track),
+            QueueAction::Move(from, to) => {
+                self.queue.move_track(from, to);
+            }
+            QueueAction::Remove(queue_idx) => {
+                self.queue.remove(queue_idx);
+            }
+            QueueAction::Clear => self.queue.clear(),
+            QueueAction::Jump(queue_idx) => {
+                self.play_queue_track(queue_idx);
+                return;
+            }
+        }
+        // Whatever is next in line may have changed
+        self.queue_next_track();
+    }
+    
     fn handle_library_action(&mut self, action: LibraryAction) {
         match action {
             LibraryAction::AddScanDirectory(dir) => {
                 self.library.scan_dirs.push(dir);
+                self.restart_library_watcher();
             }
             LibraryAction::RemoveScanDirectory(dir) => {
                 self.library.scan_dirs.retain(|d| d != &dir);
+                self.restart_library_watcher();
             }
             LibraryAction::StartScan => {
+                if self.library_scan.is_some() {
+                    info!("Library scan already running");
+                    return;
+                }
+                // A full scan picks up whatever the watcher was waiting to read
+                self.watched_changes.clear();
+                let known = self.known_fingerprints();
                 self.library.scanning = true;
-                // Scan logic would go here
-                // For demonstration, just add sample tracks
-                self.library.tracks.push(Track {
-                    path: "sample1.mp3".to_string(),
-                    title: Some("Sample Track 1".to_string()),
-                    artist: Some("Artist 1".to_string()),
-                    album: Some("Album 1".to_string()),
-                    play_count: 0,
-                });
-                self.library.tracks.push(Track {
-                    path: "sample2.mp3".to_string(),
-                    title: Some("Sample Track 2".to_string()),
-                    artist: Some("Artist 2".to_string()),
-                    album: Some("Album 2".to_string()),
-                    play_count: 0,
-                });
-                // Add a sample Opus file to show support
-                self.library.tracks.push(Track {
-                    path: "sample.opus".to_string(),
-                    title: Some("Sample Opus Track".to_string()),
-                    artist: Some("Artist 3".to_string()),
-                    album: Some("Album 3".to_string()),
-                    play_count: 0,
-                });
-                self.library.scanning = false;
+                self.library.scan_errors.clear();
+                self.library_scan = Some(LibraryScanner::start(self.library.scan_dirs.clone(), known));
             }
             LibraryAction::ImportFile(path) => {
-                let file_path = std::path::Path::new(&path);
-                let filename = file_path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or(&path)
-                    .to_string();
-                
                 // Check if file is a valid audio file using FFmpeg
                 if core::audio::decoder::is_supported_audio_format(&path) {
-                    // Log before moving the path
                     info!("Imported audio file: {}", path);
-                    
-                    // Now move the path into the Track
-                    self.library.tracks.push(Track {
-                        path,
-                        title: Some(filename),
-                        artist: None,
-                        album: None,
-                        play_count: 0,
-                    });
+                    let track = core::library::read_track(&path);
+                    if let Some(store) = &self.store {
+                        if let Err(e) = store.add_tracks(std::slice::from_ref(&track)) {
+                            error!("Failed to store imported track: {}", e);
+                        }
+                    }
+                    self.library.tracks.push(track);
                 } else {
                     info!("Skipped unsupported file format: {}", path);
                 }
@@ -488,39 +859,353 @@ impl MediaPlayer {
     
     // Add track completion handling
     pub fn check_for_completed_tracks(&mut self) {
+        // Tracks that ended in a gapless transition - playback already moved on
+        let finished_tracks = self.player.take_finished_tracks();
+        if !finished_tracks.is_empty() {
+            for track_path in &finished_tracks {
+                self.increment_play_count(track_path);
+                self.mark_heard(track_path);
+                // Save updated play counts to disk
+                self.save_play_state(track_path);
+            }
+            // The tracks that started are in the history now
+            if let Err(e) = self.save_history() {
+                error!("Failed to save playback history: {}", e);
+            }
+            
+            // Catch the queue up with the track that just started
+            if let Some(path) = self.player.get_state().current_track {
+                if self.queue.current().map(|track| &track.path) != Some(&path) {
+                    if let Some(queue_idx) = self.queue.find_following(&path) {
+                        self.queue.jump(queue_idx);
+                    }
+                }
+            }
+            
+            // Queue up the one after the track that just started
+            self.queue_next_track();
+        }
+        
         // If the player signals that a track was completed
         if self.player.track_completed_signal {
             // Reset the signal
             self.player.track_completed_signal = false;
             
             // Get the currently playing track path
-            if let Some(track_path) = &self.player_state.current_track {
-                // Find and update the track's play count in the playlists
-                for playlist in &mut self.playlists.playlists {
-                    for track in &mut playlist.tracks {
-                        if &track.path == track_path {
-                            track.play_count += 1;
-                            info!("Updated play count for '{}' to {}", 
-                                  track.title.as_ref().unwrap_or(&track.path), 
-                                  track.play_count);
-                            break;
+            if let Some(track_path) = self.player_state.current_track.clone() {
+                self.increment_play_count(&track_path);
+                self.mark_heard(&track_path);
+                
+                // Save updated play counts to disk
+                self.save_play_state(&track_path);
+                
+                // Continue according to the repeat mode
+                match self.get_following_track() {
+                    Some(queue_idx) => self.play_queue_track(queue_idx),
+                    None => {
+                        info!("Nothing follows with repeat mode {:?}, stopping", self.player_state.repeat_mode);
+                        self.player.stop();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save_settings(&self) -> Result<(), anyhow::Error> {
+        let path = self.data_dir.join("settings.json");
+        info!("Saving settings to {}", path.display());
+        self.settings.save_to_file(&path)
+    }
+
+    // Pick up where the last session left off, paused at the same spot
+    fn restore_session(&mut self, session: Session) {
+        info!("Restoring last session: {:?} at {:.1}s", session.current_track, session.position_secs);
+        self.queue = session.queue;
+        // Without a database the latest resume positions are the ones saved with the queue
+        if self.store.is_none() {
+            let play_states: Vec<(String, Option<f64>, bool)> = self.queue.tracks().iter()
+                .map(|track| (track.path.clone(), track.resume_position, track.finished))
+                .collect();
+            for (path, resume_position, finished) in play_states {
+                self.update_track(&path, |track| {
+                    track.resume_position = resume_position;
+                    track.finished = finished;
+                });
+            }
+        }
+        self.player.set_volume(session.volume);
+        self.player.set_shuffle_mode(session.shuffle_mode);
+        self.player.set_repeat_mode(session.repeat_mode);
+        self.settings.shuffle_mode = session.shuffle_mode;
+        self.settings.repeat_mode = session.repeat_mode;
+
+        if let Some(path) = &session.current_track {
+            let position = Duration::from_secs_f64(session.position_secs.max(0.0));
+            if let Err(e) = self.player.load_paused(path, position) {
+                error!("Failed to reopen {}: {}", path, e);
+            }
+        }
+
+        self.player_state = self.player.get_state();
+        self.player_state.shuffle_enabled = session.shuffle_enabled;
+        self.queue_next_track();
+    }
+
+    pub fn save_session(&mut self) -> Result<(), anyhow::Error> {
+        let state = &self.player_state;
+        let active = state.status != PlaybackStatus::Stopped;
+        let session = Session {
+            queue: self.queue.clone(),
+            current_track: state.current_track.clone().filter(|_| active),
+            position_secs: state.position.filter(|_| active).map_or(0.0, |p| p.as_secs_f64()),
+            volume: state.volume,
+            shuffle_enabled: state.shuffle_enabled,
+            shuffle_mode: state.shuffle_mode,
+            repeat_mode: state.repeat_mode,
+        };
+        self.session_saved_at = Instant::now();
+        session.save_to_file(&self.data_dir.join("session.json"))
+    }
+
+    // Called on every tick, writes the session every few seconds
+    pub fn autosave_session(&mut self) {
+        if self.session_saved_at.elapsed() >= SESSION_SAVE_INTERVAL {
+            self.save_resume_position();
+            if let Err(e) = self.save_session() {
+                error!("Failed to save session: {}", e);
+            }
+        }
+    }
+
+    pub fn save_history(&self) -> Result<(), anyhow::Error> {
+        let path = self.data_dir.join("history.json");
+        debug!("Saving playback history to {}", path.display());
+        self.player.history.save_to_file(&path)
+    }
+
+    // Tell the user when the output device went away or playback moved to another one
+    pub fn check_device_events(&mut self) {
+        let events = self.player.take_device_events();
+        if let Some(latest) = events.last() {
+            info!("Output device event: {}", latest);
+            self.status_message = Some(latest.clone());
+            self.status_message_time = Some(Instant::now());
+            self.status_message_duration = Some(Duration::from_secs(5));
+        }
+    }
+
+    // Collect results from the background loudness analysis
+    pub fn check_loudness_analysis(&mut self) {
+        let Some(analysis) = &self.loudness_analysis else {
+            return;
+        };
+
+        let results = analysis.take_results();
+        for (path, error) in analysis.take_errors() {
+            error!("Loudness analysis failed for {}: {}", path, error);
+        }
+        let finished = analysis.is_finished();
+        let (completed, total) = analysis.progress();
+
+        for (path, loudness) in &results {
+            for playlist in &mut self.playlists.playlists {
+                for track in playlist.tracks.iter_mut().filter(|t| &t.path == path) {
+                    track.loudness = Some(*loudness);
+                }
+            }
+            for track in self.library.tracks.iter_mut().filter(|t| &t.path == path) {
+                track.loudness = Some(*loudness);
+            }
+            self.player.set_loudness_hint(path, *loudness);
+            if let Some(store) = &self.store {
+                if let Err(e) = store.set_loudness(path, loudness) {
+                    error!("Failed to store loudness of {}: {}", path, e);
+                }
+            }
+        }
+
+        if finished {
+            info!("Loudness analysis complete: {}/{} tracks", completed, total);
+            self.loudness_analysis = None;
+            if let Err(e) = self.save_playlists() {
+                error!("Failed to save loudness results: {}", e);
+            }
+            self.status_message = Some(format!("Loudness analysis complete ({} tracks)", completed));
+            self.status_message_duration = Some(Duration::from_secs(3));
+        } else {
+            self.status_message = Some(format!("Analyzing loudness: {}/{}", completed, total));
+            self.status_message_duration = Some(Duration::from_secs(2));
+        }
+        self.status_message_time = Some(Instant::now());
+    }
+
+    // Collect what the background library scan has found so far
+    pub fn check_library_scan(&mut self) {
+        let Some(scanner) = &self.library_scan else {
+            return;
+        };
+
+        // With a database the tracks go there instead of into memory
+        match &self.store {
+            Some(store) => {
+                let tracks = scanner.take_tracks();
+                if !tracks.is_empty() {
+                    if let Err(e) = store.add_scanned_tracks(&tracks) {
+                        error!("Failed to store scanned tracks: {}", e);
+                    }
+                }
+                let removed = scanner.take_removed();
+                if !removed.is_empty() {
+                    if let Err(e) = store.mark_missing(&removed) {
+                        error!("Failed to flag removed tracks: {}", e);
+                    }
+                }
+                self.library.update_scan_progress(scanner);
+            }
+            None => self.library.apply_scan(scanner),
+        }
+        let progress = self.library.scan_progress.clone();
+
+        if progress.finished {
+            info!("Library scan complete: {} files, {} unreadable",
+                  progress.scanned, self.library.scan_errors.len());
+            self.refresh_library_albums();
+            self.status_message = Some(format!("Library scan complete: {}", progress.report.summary()));
+            self.status_message_duration = Some(Duration::from_secs(3));
+            self.library_scan = None;
+        } else {
+            self.status_message = Some(format!("Scanning library: {}/{}", progress.scanned, progress.total));
+            self.status_message_duration = Some(Duration::from_secs(2));
+        }
+        self.status_message_time = Some(Instant::now());
+    }
+
+    // Files read before and unchanged since aren't opened again
+    fn known_fingerprints(&self) -> HashMap<String, FileFingerprint> {
+        match self.store.as_ref().map(|store| store.fingerprints()) {
+            Some(Ok(known)) => known,
+            Some(Err(e)) => {
+                error!("Failed to read file fingerprints, scanning everything: {}", e);
+                HashMap::new()
+            }
+            None => HashMap::new(),
+        }
+    }
+
+    // Watch the current scan directories, dropping the old watcher
+    pub fn restart_library_watcher(&mut self) {
+        if let Some(mut watcher) = self.library_watcher.take() {
+            watcher.stop();
+        }
+        if self.library.scan_dirs.is_empty() {
+            return;
+        }
+        // A vanished path that isn't an audio file only matters if the library has files in
+        // it. The watcher asks on its own thread, through its own database connection.
+        // Without a database, removing a directory's tracks from memory is no more work than
+        // looking them up, so every such path is passed on.
+        let watcher = match self.store.as_ref().map(|store| store.reopen()) {
+            Some(Ok(store)) => LibraryWatcher::start(&self.library.scan_dirs, move |dir| {
+                store.has_tracks_under(dir).unwrap_or_else(|e| {
+                    error!("Failed to look up tracks under {}: {}", dir, e);
+                    false
+                })
+            }),
+            Some(Err(e)) => {
+                error!("Failed to open the library database for the watcher: {}", e);
+                LibraryWatcher::start(&self.library.scan_dirs, |_| true)
+            }
+            None => LibraryWatcher::start(&self.library.scan_dirs, |_| true),
+        };
+        self.library_watcher = Some(watcher);
+    }
+
+    // Apply the changes the library watcher has seen settle
+    pub fn check_library_watcher(&mut self) {
+        let Some(watcher) = &self.library_watcher else {
+            return;
+        };
+        let changes = watcher.take_changes();
+
+        let mut removed_any = false;
+        for change in changes {
+            match change {
+                LibraryChange::Changed(path) => {
+                    let path = PathBuf::from(path);
+                    if !self.watched_changes.contains(&path) {
+                        self.watched_changes.push(path);
+                    }
+                }
+                LibraryChange::Removed(path) => {
+                    info!("Library file removed: {}", path);
+                    match &self.store {
+                        Some(store) => {
+                            if let Err(e) = store.mark_missing(std::slice::from_ref(&path)) {
+                                error!("Failed to flag removed track: {}", e);
+                            }
                         }
+                        None => self.library.tracks.retain(|t| t.path != path),
                     }
+                    removed_any = true;
                 }
-                
-                // Save updated play counts to disk
-                if let Err(e) = self.save_playlists() {
-                    error!("Failed to save play count: {}", e);
+                LibraryChange::RemovedDir(dir) => {
+                    info!("Library directory removed: {}", dir);
+                    match &self.store {
+                        Some(store) => {
+                            if let Err(e) = store.mark_missing_under(&dir) {
+                                error!("Failed to flag removed tracks: {}", e);
+                            }
+                        }
+                        None => self.library.tracks.retain(|t| !std::path::Path::new(&t.path).starts_with(&dir)),
+                    }
+                    removed_any = true;
                 }
-                
-                // Auto-play the next track
-                self.handle_action(core::Action::Player(core::PlayerAction::NextTrack));
             }
         }
+        if removed_any {
+            self.refresh_library_albums();
+        }
+
+        // Changed files are read in the background, once any running scan is done
+        if self.library_scan.is_none() && !self.watched_changes.is_empty() {
+            let files = std::mem::take(&mut self.watched_changes);
+            info!("Reading {} changed library files", files.len());
+            let known = self.known_fingerprints();
+            self.library.scanning = true;
+            self.library_scan = Some(LibraryScanner::start_files(files, known));
+        }
+    }
+
+    // Reload the album summaries after the database changed
+    fn refresh_library_albums(&mut self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        match (store.albums(None), store.track_count()) {
+            (Ok(albums), Ok(count)) => {
+                self.library.albums = albums;
+                self.library.track_count = count;
+            }
+            (Err(e), _) | (_, Err(e)) => error!("Failed to read the library database: {}", e),
+        }
+    }
+
+    // Save the playlists. Track changes reach the database as they happen, so it is only
+    // rewritten after playlists were created, deleted or renamed.
+    pub fn save_playlists(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(store) = &self.store {
+            if self.playlists_changed {
+                debug!("Saving playlists to the library database");
+                store.save_playlists(&self.playlists)?;
+                self.playlists_changed = false;
+            }
+            return Ok(());
+        }
+        self.save_playlists_file()
     }
 
     // Improved save_playlists implementation with atomic file operations
-    pub fn save_playlists(&self) -> Result<(), anyhow::Error> {
+    fn save_playlists_file(&self) -> Result<(), anyhow::Error> {
         let path = self.data_dir.join("playlists.json");
         info!("Saving playlists to {}", path.display());
         
@@ -113,9 +113,12 @@ impl PlaylistViewState {
                     PlaylistAction::PlayTrack(pid, tid) => Action::Playlist(CorePlaylistAction::PlayTrack(pid, tid)),
                     PlaylistAction::RemoveTrack(pid, idx) => Action::Playlist(CorePlaylistAction::RemoveTrack(pid, idx)),
                     PlaylistAction::BatchAddTracks(pid, tracks) => Action::Playlist(CorePlaylistAction::BatchAddTracks(pid, tracks)),
+                    PlaylistAction::AnalyzeLoudness(pid) => Action::Playlist(CorePlaylistAction::AnalyzeLoudness(pid)),
+                    PlaylistAction::MarkFinished(pid, idx) => Action::Playlist(CorePlaylistAction::MarkFinished(pid, idx)),
                     PlaylistAction::Seek(pos) => Action::Player(core::PlayerAction::Seek(pos)),
                     PlaylistAction::UpdateProgress(_) => Action::Playlist(CorePlaylistAction::None),
                     PlaylistAction::PlayerControl(action) => Action::Player(action),
+                    PlaylistAction::Queue(action) => Action::Queue(action),
                     PlaylistAction::Library(action) => match action {
                         LibraryMessage::AddMusicFolder => Action::Library(core::LibraryAction::None),
                         LibraryMessage::ToggleView => Action::Library(core::LibraryAction::None),
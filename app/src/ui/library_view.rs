@@ -74,7 +74,7 @@ pub fn view_with_search(library: &LibraryState) -> Element<LibraryMessage> {
     .align_y(Alignment::Center);
     
     // Album grid
-    let album_grid = if library.tracks.is_empty() {
+    let album_grid = if library.tracks.is_empty() && library.albums.is_empty() {
         // Empty state with add button
         let empty_content = column![
             text("No music found in library").size(16).style(|_: &Theme| text::Style {
@@ -115,25 +115,33 @@ pub fn view_with_search(library: &LibraryState) -> Element<LibraryMessage> {
             .center_x(Length::Fill)
             .center_y(Length::Fill)
     } else {
+        // Albums from the library database, or grouped from the imported tracks
+        let albums: Vec<(&str, usize)> = if !library.albums.is_empty() {
+            library.albums.iter()
+                .map(|album| (album.title.as_str(), album.track_count))
+                .collect()
+        } else {
+            library.tracks.iter()
+                .fold(Vec::<(&str, usize)>::new(), |mut acc, track| {
+                    let album = track.album.as_deref().unwrap_or("Unknown");
+                    if let Some((_, count)) = acc.iter_mut().find(|(a, _)| *a == album) {
+                        *count += 1;
+                    } else {
+                        acc.push((album, 1));
+                    }
+                    acc
+                })
+        };
+        
         // Grid of albums
         let grid = scrollable(
             column(
-                // Group tracks by album and create album cards
-                library.tracks.iter()
-                    .fold(Vec::<(&str, Vec<&core::Track>)>::new(), |mut acc, track| {
-                        let album = track.album.as_deref().unwrap_or("Unknown");
-                        if let Some((_, tracks)) = acc.iter_mut().find(|(a, _)| *a == album) {
-                            tracks.push(track);
-                        } else {
-                            acc.push((album, vec![track]));
-                        }
-                        acc
-                    })
+                albums
                     .chunks(3)
                     .map(|chunk| {
                         row(
-                            chunk.iter().map(|(album, tracks)| {
-                                create_album_card(album, tracks)
+                            chunk.iter().map(|&(album, track_count)| {
+                                create_album_card(album, track_count)
                             }).collect::<Vec<Element<'_, LibraryMessage>>>()
                         )
                         .spacing(20)
@@ -151,12 +159,33 @@ pub fn view_with_search(library: &LibraryState) -> Element<LibraryMessage> {
             .height(Length::Fill)
     };
     
+    // Scan progress while scanning, and how many files couldn't be read
+    let progress = &library.scan_progress;
+    let scan_status = if library.scanning {
+        format!("Scanning: {}/{} files", progress.scanned, progress.total)
+    } else if progress.finished {
+        progress.report.summary()
+    } else if !library.scan_errors.is_empty() {
+        format!("{} files could not be read", library.scan_errors.len())
+    } else if library.track_count > 0 {
+        format!("{} tracks", library.track_count)
+    } else {
+        String::new()
+    };
+    
     // Library title and main content
     column![
-        text("Library").size(20).style(|_: &Theme| text::Style {
-            color: Some(GREEN_COLOR),
-            ..Default::default()
-        }),
+        row![
+            text("Library").size(20).style(|_: &Theme| text::Style {
+                color: Some(GREEN_COLOR),
+                ..Default::default()
+            }),
+            Space::with_width(Length::Fill),
+            text(scan_status).size(12).style(|_: &Theme| text::Style {
+                color: Some(iced::Color::from_rgb(0.7, 0.7, 0.7)),
+            }),
+        ]
+        .align_y(Alignment::Center),
         search_bar,
         Space::with_height(10),
         album_grid
@@ -169,7 +198,7 @@ pub fn view_with_search(library: &LibraryState) -> Element<LibraryMessage> {
 }
 
 // Helper function to create an album card - fixed lifetime issues
-fn create_album_card<'a>(album: &'a str, tracks: &[&'a core::Track]) -> Element<'a, LibraryMessage> {
+fn create_album_card(album: &str, track_count: usize) -> Element<'_, LibraryMessage> {
     let album_art = container(
         Space::new(Length::Fixed(120.0), Length::Fixed(120.0))
     )
@@ -183,7 +212,7 @@ fn create_album_card<'a>(album: &'a str, tracks: &[&'a core::Track]) -> Element<
             color: Some(GREEN_COLOR),
             ..Default::default()
         }),
-        text(format!("{} tracks", tracks.len())).size(12).style(|_: &Theme| text::Style {
+        text(format!("{} tracks", track_count)).size(12).style(|_: &Theme| text::Style {
             color: Some(iced::Color::from_rgb(0.7, 0.7, 0.7)),
             ..Default::default()
         }),
@@ -16,7 +16,25 @@ pub enum PlayerAction {
     VolumeChange(f32),
     Seek(f32),
     Shuffle,
+    CycleShuffleMode,
+    CycleRepeat,
     UpdateProgress(f32),
+    SetSpeed(f32),
+    SetLoopIn,
+    SetLoopOut,
+    ClearLoop,
+}
+
+// Speeds the speed button steps through
+const SPEED_STEPS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 3.0];
+
+// The next step up from the current speed, wrapping around to the slowest
+fn next_speed(current: f32) -> f32 {
+    SPEED_STEPS
+        .iter()
+        .copied()
+        .find(|&step| step > current + 0.01)
+        .unwrap_or(SPEED_STEPS[0])
 }
 
 // Load SVG icons as svg widgets
@@ -28,6 +46,38 @@ fn load_icon(name: &str) -> svg::Svg<iced::Theme> {
     svg::Svg::new(svg::Handle::from_path(icon_path))
 }
 
+// The A–B loop as a bar under the seek slider, or a tick while only the in point is set
+fn loop_markers<'a>(player: &PlayerState) -> Element<'a, PlayerAction> {
+    let (Some(duration), Some(loop_in)) = (player.duration.filter(|d| !d.is_zero()), player.loop_in) else {
+        return Space::with_height(4).into();
+    };
+
+    // Positions in thousandths of the track, for FillPortion
+    let portion = |point: std::time::Duration| {
+        ((point.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0) * 1000.0) as u16
+    };
+    let start = portion(loop_in);
+    let end = player.loop_out.map(portion).unwrap_or(start);
+    let marker_width = if player.loop_out.is_some() {
+        Length::FillPortion(end.saturating_sub(start).max(1))
+    } else {
+        Length::Fixed(2.0)
+    };
+
+    row![
+        Space::with_width(Length::FillPortion(start.max(1))),
+        container(Space::new(Length::Fill, Length::Fixed(4.0)))
+            .width(marker_width)
+            .style(|_: &Theme| container::Style {
+                background: Some(iced::Background::Color(GREEN_COLOR)),
+                ..Default::default()
+            }),
+        Space::with_width(Length::FillPortion(1000u16.saturating_sub(end).max(1))),
+    ]
+    .width(Length::Fill)
+    .into()
+}
+
 pub fn view(player: &PlayerState) -> Element<PlayerAction> {
     // Left section: Album art and track info
     let track_info = if let Some(track_path) = &player.current_track {
@@ -82,7 +132,9 @@ pub fn view(player: &PlayerState) -> Element<PlayerAction> {
         format!("{}:{:02}", secs / 60, secs % 60)
     });
     
-    let total_time = player.duration.map_or("0:00".to_string(), |dur| {
+    // A playing track without a length is a live stream
+    let unknown_length = if player.current_track.is_some() { "--:--" } else { "0:00" };
+    let total_time = player.duration.map_or(unknown_length.to_string(), |dur| {
         let secs = dur.as_secs();
         format!("{}:{:02}", secs / 60, secs % 60)
     });
@@ -120,7 +172,7 @@ pub fn view(player: &PlayerState) -> Element<PlayerAction> {
         },
     });
     
-    let progress_container = container(progress_slider)
+    let progress_container = container(column![progress_slider, loop_markers(player)].spacing(2))
         .width(Length::Fill)
         .padding([0, 5]);
     
@@ -232,6 +284,67 @@ pub fn view(player: &PlayerState) -> Element<PlayerAction> {
             ..Default::default()
         }),
         
+        // Shuffle mode, click to step through the modes
+        button(green_text(player.shuffle_mode.label()).size(12))
+            .padding(5)
+            .on_press(PlayerAction::CycleShuffleMode)
+            .style(|_theme, _| button::Style {
+                background: None,
+                ..Default::default()
+            }),
+        
+        // Repeat button, cycles off -> all -> one -> stop after current
+        button(
+            load_icon(match player.repeat_mode {
+                core::RepeatMode::Off => "ph--repeat-off-bold.svg",
+                core::RepeatMode::All => "ph--repeat-bold.svg",
+                core::RepeatMode::One => "ph--repeat-once-bold.svg",
+                core::RepeatMode::StopAfterCurrent => "ph--arrow-line-right-bold.svg",
+            })
+                .width(20)
+                .height(20)
+        )
+        .padding(5)
+        .on_press(PlayerAction::CycleRepeat)
+        .style(|_theme, _| button::Style {
+            background: None,
+            ..Default::default()
+        }),
+        
+        // Playback speed, click to step through the presets
+        button(green_text(format!("{}x", player.speed)).size(12))
+            .padding(5)
+            .on_press(PlayerAction::SetSpeed(next_speed(player.speed)))
+            .style(|_theme, _| button::Style {
+                background: None,
+                ..Default::default()
+            }),
+        
+        // A–B loop: set the in and out points at the played position
+        button(green_text("A").size(12))
+            .padding(5)
+            .on_press(PlayerAction::SetLoopIn)
+            .style(|_theme, _| button::Style {
+                background: None,
+                ..Default::default()
+            }),
+        
+        button(green_text("B").size(12))
+            .padding(5)
+            .on_press(PlayerAction::SetLoopOut)
+            .style(|_theme, _| button::Style {
+                background: None,
+                ..Default::default()
+            }),
+        
+        button(green_text("x").size(12))
+            .padding(5)
+            .on_press_maybe(player.loop_in.map(|_| PlayerAction::ClearLoop))
+            .style(|_theme, _| button::Style {
+                background: None,
+                ..Default::default()
+            }),
+        
         Space::with_width(20),
         
         // Volume control
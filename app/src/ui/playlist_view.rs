@@ -21,6 +21,9 @@ pub enum PlaylistAction {
     UpdateProgress(f32),
     RemoveTrack(u32, usize),
     BatchAddTracks(u32, Vec<core::Track>),
+    AnalyzeLoudness(u32),
+    MarkFinished(u32, usize),
+    Queue(core::QueueAction),
     Library(crate::ui::library_view::LibraryMessage), 
 }
 
@@ -138,6 +141,21 @@ pub fn view_with_state<'a>(
                         })
                     );
                     
+                    // Add loudness analysis button
+                    row_elements = row_elements.push(
+                        button(
+                            load_icon("ph--waveform-bold.svg")
+                                .width(16)
+                                .height(16)
+                        )
+                        .padding(5)
+                        .on_press(PlaylistAction::AnalyzeLoudness(id))
+                        .style(|_theme, _| button::Style {
+                            background: None,
+                            ..Default::default()
+                        })
+                    );
+                    
                     // Add delete button
                     row_elements = row_elements.push(
                         button(
@@ -154,7 +172,7 @@ pub fn view_with_state<'a>(
                     );
                 } else {
                     // Add an empty space of the same width when not selected
-                    row_elements = row_elements.push(Space::with_width(52)); // Space for both buttons
+                    row_elements = row_elements.push(Space::with_width(78)); // Space for all three buttons
                 }
                 
                 row_elements
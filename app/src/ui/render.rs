@@ -1,5 +1,5 @@
 // app/src/ui/render.rs
-use iced::widget::{Column, Container, Row, container, text, scrollable, Space, horizontal_rule, button};
+use iced::widget::{Column, Container, Row, container, text, scrollable, Space, horizontal_rule, button, progress_bar};
 use iced::{Element, Length, Background, Alignment, Theme, widget::svg, Color};
 use crate::ui::theme::{
     library_container_style,
@@ -14,6 +14,8 @@ use crate::ui::theme::{
 use core::player::state::PlayerState;
 use core::playlist::PlaylistState;
 use core::library::LibraryState;
+use core::queue::{PlayQueue, QueueAction};
+use core::player::history::PlaybackHistory;
 
 use crate::ui::{player_view, playlist_view, library_view};
 use crate::states::playlist_state::PlaylistViewState; 
@@ -32,6 +34,8 @@ pub fn render_with_state<'a>(
     player_state: &'a PlayerState,
     playlists: &'a PlaylistState,
     library: &'a LibraryState,
+    queue: &'a PlayQueue,
+    history: &'a PlaybackHistory,
     playlist_view_state: &'a PlaylistViewState,
     status_message: &'a Option<String>, // Status message for user feedback
 ) -> Element<'a, playlist_view::PlaylistAction> {
@@ -41,7 +45,7 @@ pub fn render_with_state<'a>(
     // Create the three panels for the main content area
     let playlist_section = playlist_view::view_with_state(playlists, playlist_view_state);
     let library_section = library_view::view_with_search(library);
-    let now_playing_section = create_now_playing_section(playlists, player_state);
+    let now_playing_section = create_now_playing_section(playlists, queue, history, player_state);
     
     // Map player actions -> playlist actions
     let player_container = Container::new(
@@ -75,6 +79,18 @@ pub fn render_with_state<'a>(
                 },
                 player_view::PlayerAction::Shuffle =>
                     PlaylistAction::PlayerControl(core::PlayerAction::Shuffle),
+                player_view::PlayerAction::CycleShuffleMode =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::CycleShuffleMode),
+                player_view::PlayerAction::CycleRepeat =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::CycleRepeatMode),
+                player_view::PlayerAction::SetSpeed(speed) =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::SetSpeed(speed)),
+                player_view::PlayerAction::SetLoopIn =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::SetLoopIn),
+                player_view::PlayerAction::SetLoopOut =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::SetLoopOut),
+                player_view::PlayerAction::ClearLoop =>
+                    PlaylistAction::PlayerControl(core::PlayerAction::ClearLoop),
                 player_view::PlayerAction::UpdateProgress(pos) => {
                     // For continuous updates during slider dragging
                     PlaylistAction::UpdateProgress(pos)
@@ -165,6 +181,8 @@ pub fn render_with_state<'a>(
 // Helper: now playing section
 fn create_now_playing_section<'a>(
     playlists: &'a PlaylistState, 
+    queue: &'a PlayQueue,
+    history: &'a PlaybackHistory,
     player_state: &'a PlayerState
 ) -> Element<'a, PlaylistAction> {
     let title = text("Now Playing")
@@ -177,6 +195,17 @@ fn create_now_playing_section<'a>(
     // Build a complete content column step by step
     let mut content = Column::new().push(title);
 
+    // Upcoming queue, which keeps going while other playlists are browsed
+    if !queue.upcoming().is_empty() {
+        content = content.push(create_up_next_section(queue))
+            .push(Space::with_height(10));
+    }
+
+    if !history.is_empty() {
+        content = content.push(create_recently_played_section(history, playlists))
+            .push(Space::with_height(10));
+    }
+
     if let Some(idx) = playlists.selected {
         if idx < playlists.playlists.len() {
             let playlist = &playlists.playlists[idx];
@@ -205,6 +234,18 @@ fn create_now_playing_section<'a>(
                         false
                     };
                     
+                    // Long-form audio shows how far listening got
+                    let listened: Option<Element<'a, PlaylistAction>> = if track.finished {
+                        Some(text("finished").size(12).into())
+                    } else {
+                        track.listened_fraction().map(|fraction| {
+                            progress_bar(0.0..=1.0, fraction)
+                                .width(Length::Fixed(50.0))
+                                .height(Length::Fixed(4.0))
+                                .into()
+                        })
+                    };
+                    
                     // Create track row with play button and delete button
                     let track_row = Row::new()
                         .push(
@@ -217,7 +258,9 @@ fn create_now_playing_section<'a>(
                                             track.play_count))
                                         .size(14)
                                     )
+                                    .push_maybe(listened)
                                     .spacing(5)
+                                    .align_y(Alignment::Center)
                             )
                             .padding(5)
                             .width(Length::Fill)
@@ -228,6 +271,46 @@ fn create_now_playing_section<'a>(
                             })
                             .on_press(PlaylistAction::PlayTrack(playlist.id, track_idx))
                         )
+                        // Tracks with a resume position can be marked as heard
+                        .push_maybe(track.resume_position.map(|_| {
+                            button(
+                                load_icon("ph--check-square-bold.svg")
+                                    .width(16)
+                                    .height(16)
+                            )
+                            .padding(5)
+                            .on_press(PlaylistAction::MarkFinished(playlist.id, track_idx))
+                            .style(|_theme, _| button::Style {
+                                background: None,
+                                ..Default::default()
+                            })
+                        }))
+                        .push(
+                            button(
+                                load_icon("ph--arrow-bend-down-right-bold.svg")
+                                    .width(16)
+                                    .height(16)
+                            )
+                            .padding(5)
+                            .on_press(PlaylistAction::Queue(QueueAction::PlayNext(Box::new(track.clone()))))
+                            .style(|_theme, _| button::Style {
+                                background: None,
+                                ..Default::default()
+                            })
+                        )
+                        .push(
+                            button(
+                                load_icon("ph--queue-bold.svg")
+                                    .width(16)
+                                    .height(16)
+                            )
+                            .padding(5)
+                            .on_press(PlaylistAction::Queue(QueueAction::AddToQueue(Box::new(track.clone()))))
+                            .style(|_theme, _| button::Style {
+                                background: None,
+                                ..Default::default()
+                            })
+                        )
                         .push(
                             button(
                                 load_icon("ph--x-square-bold.svg")
@@ -298,4 +381,147 @@ fn create_now_playing_section<'a>(
         .spacing(5)
         .padding(10)
         .into()
-}
\ No newline at end of file
+}
+
+// Most upcoming tracks listed in the Now Playing panel
+const UP_NEXT_LIMIT: usize = 10;
+
+fn create_up_next_section(queue: &PlayQueue) -> Element<'_, PlaylistAction> {
+    let header = Row::new()
+        .push(text(format!("Up Next ({})", queue.upcoming().len())).size(16).width(Length::Fill))
+        .push(
+            button(text("Clear").size(12))
+                .padding(5)
+                .on_press(PlaylistAction::Queue(QueueAction::Clear))
+                .style(|_theme, _| button::Style {
+                    background: None,
+                    text_color: GREEN_COLOR,
+                    ..Default::default()
+                })
+        )
+        .align_y(Alignment::Center);
+
+    let first = queue.upcoming_start();
+    let rows = queue.upcoming().iter().take(UP_NEXT_LIMIT).enumerate().fold(
+        Column::new().spacing(2),
+        |column, (offset, track)| {
+            let queue_idx = first + offset;
+            let track_title = track.title.clone().unwrap_or_else(|| track.path.clone());
+
+            let row = Row::new()
+                .push(
+                    button(text(track_title).size(14))
+                        .padding(5)
+                        .width(Length::Fill)
+                        .style(|_theme, _| button::Style {
+                            background: None,
+                            text_color: GREEN_COLOR,
+                            ..Default::default()
+                        })
+                        .on_press(PlaylistAction::Queue(QueueAction::Jump(queue_idx)))
+                )
+                .push(
+                    button(
+                        load_icon("ph--arrow-up-bold.svg")
+                            .width(16)
+                            .height(16)
+                    )
+                    .padding(5)
+                    // The first upcoming track can't move further up
+                    .on_press_maybe((offset > 0).then(|| {
+                        PlaylistAction::Queue(QueueAction::Move(queue_idx, queue_idx - 1))
+                    }))
+                    .style(|_theme, _| button::Style {
+                        background: None,
+                        ..Default::default()
+                    })
+                )
+                .push(
+                    button(
+                        load_icon("ph--x-square-bold.svg")
+                            .width(16)
+                            .height(16)
+                    )
+                    .padding(5)
+                    .on_press(PlaylistAction::Queue(QueueAction::Remove(queue_idx)))
+                    .style(|_theme, _| button::Style {
+                        background: None,
+                        ..Default::default()
+                    })
+                )
+                .spacing(5)
+                .align_y(Alignment::Center);
+
+            column.push(row)
+        }
+    );
+
+    let mut section = Column::new()
+        .spacing(5)
+        .push(header)
+        .push(horizontal_rule(1))
+        .push(rows);
+
+    let hidden = queue.upcoming().len().saturating_sub(UP_NEXT_LIMIT);
+    if hidden > 0 {
+        section = section.push(text(format!("...and {} more", hidden)).size(12));
+    }
+
+    section.into()
+}
+
+// Most history entries listed in the Now Playing panel
+const RECENTLY_PLAYED_LIMIT: usize = 5;
+
+fn create_recently_played_section<'a>(
+    history: &'a PlaybackHistory,
+    playlists: &'a PlaylistState,
+) -> Element<'a, PlaylistAction> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let rows = history.recent().take(RECENTLY_PLAYED_LIMIT).fold(
+        Column::new().spacing(2),
+        |column, entry| {
+            // The history only has paths, take the title from a playlist when there is one
+            let title = playlists.playlists.iter()
+                .flat_map(|playlist| &playlist.tracks)
+                .find(|track| track.path == entry.path)
+                .and_then(|track| track.title.clone())
+                .unwrap_or_else(|| {
+                    std::path::Path::new(&entry.path)
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or(&entry.path)
+                        .to_string()
+                });
+
+            column.push(
+                Row::new()
+                    .push(text(title).size(14).width(Length::Fill))
+                    .push(text(format_time_ago(now.saturating_sub(entry.played_at))).size(12))
+                    .spacing(5)
+                    .padding(5)
+                    .align_y(Alignment::Center)
+            )
+        }
+    );
+
+    Column::new()
+        .spacing(5)
+        .push(text("Recently Played").size(16))
+        .push(horizontal_rule(1))
+        .push(rows)
+        .into()
+}
+
+fn format_time_ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86399 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} d ago", seconds / 86400),
+    }
+}
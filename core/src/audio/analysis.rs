@@ -0,0 +1,686 @@
+// core/src/audio/analysis.rs
+// Offline EBU R128 loudness analysis (ITU-R BS.1770) for files without ReplayGain tags
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use serde::{Serialize, Deserialize};
+
+use crate::audio::decoder::AudioInput;
+use crate::audio::tags::album_key;
+use crate::playlist::Track;
+
+// Gating constants from BS.1770 / EBU Tech 3342
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+const LRA_RELATIVE_GATE_LU: f64 = -20.0;
+const LRA_LOW_PERCENTILE: f64 = 0.10;
+const LRA_HIGH_PERCENTILE: f64 = 0.95;
+
+// Blocks are built from 100ms sub-blocks: 400ms momentary, 3s short-term
+const SUBBLOCKS_PER_SECOND: u32 = 10;
+const MOMENTARY_SUBBLOCKS: usize = 4;
+const SHORT_TERM_SUBBLOCKS: usize = 30;
+
+// True peak is measured on a 4x oversampled signal
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Loudness of one track or album
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessMeasurement {
+    pub integrated_lufs: f32,
+    pub loudness_range_lu: f32,
+    // Linear, 1.0 = full scale
+    pub true_peak: f32,
+}
+
+/// Analysis result stored on a `Track`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessInfo {
+    pub track: LoudnessMeasurement,
+    #[serde(default)]
+    pub album: Option<LoudnessMeasurement>,
+}
+
+// K-weighting filter stage (direct form I biquad)
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2, x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 }
+    }
+
+    // Stage 1: high shelf modelling the acoustic effect of the head
+    fn high_shelf(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Self::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    // Stage 2: RLB high pass
+    fn high_pass(sample_rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Self::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// Polyphase interpolator used to find inter-sample peaks
+struct TruePeakDetector {
+    phases: Vec<[f64; TAPS_PER_PHASE]>,
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+    position: usize,
+    peak: f64,
+}
+
+impl TruePeakDetector {
+    fn new(channels: usize) -> Self {
+        // Hann-windowed sinc low pass at the original Nyquist frequency
+        let len = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.0;
+        let mut phases = vec![[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+
+        for m in 0..len {
+            let x = (m as f64 - center) / OVERSAMPLING as f64;
+            let sinc = if x.abs() < 1e-9 {
+                1.0
+            } else {
+                (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * m as f64 / (len - 1) as f64).cos();
+            phases[m % OVERSAMPLING][m / OVERSAMPLING] = sinc * window;
+        }
+
+        // Unity gain for every phase
+        for phase in phases.iter_mut() {
+            let sum: f64 = phase.iter().sum();
+            if sum.abs() > 1e-9 {
+                for tap in phase.iter_mut() {
+                    *tap /= sum;
+                }
+            }
+        }
+
+        Self {
+            phases,
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            position: 0,
+            peak: 0.0,
+        }
+    }
+
+    // Feed one interleaved frame
+    fn process_frame(&mut self, frame: &[f32]) {
+        for (channel, &sample) in frame.iter().enumerate() {
+            let history = &mut self.history[channel];
+            history[self.position] = sample as f64;
+            self.peak = self.peak.max((sample as f64).abs());
+
+            for phase in &self.phases {
+                let mut acc = 0.0;
+                for (k, tap) in phase.iter().enumerate() {
+                    let idx = (self.position + TAPS_PER_PHASE - k) % TAPS_PER_PHASE;
+                    acc += tap * history[idx];
+                }
+                self.peak = self.peak.max(acc.abs());
+            }
+        }
+        self.position = (self.position + 1) % TAPS_PER_PHASE;
+    }
+}
+
+/// Accumulates K-weighted energy for one stream of interleaved samples
+pub struct LoudnessMeter {
+    channels: usize,
+    channel_weights: Vec<f64>,
+    filters: Vec<(Biquad, Biquad)>,
+    subblock_frames: usize,
+    subblock_fill: usize,
+    subblock_energy: f64,
+    // Weighted mean-square energy of every completed 100ms sub-block
+    subblocks: Vec<f64>,
+    true_peak: TruePeakDetector,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let rate = sample_rate.max(1) as f64;
+
+        // Surround channels count more, LFE is ignored (5.1 order: L R C LFE Ls Rs)
+        let channel_weights = (0..channels)
+            .map(|c| match (channels, c) {
+                (6.., 3) => 0.0,
+                (6.., 4) | (6.., 5) => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+
+        Self {
+            channels,
+            channel_weights,
+            filters: vec![(Biquad::high_shelf(rate), Biquad::high_pass(rate)); channels],
+            subblock_frames: (sample_rate / SUBBLOCKS_PER_SECOND).max(1) as usize,
+            subblock_fill: 0,
+            subblock_energy: 0.0,
+            subblocks: Vec::new(),
+            true_peak: TruePeakDetector::new(channels),
+        }
+    }
+
+    /// Feed interleaved f32 samples
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                let (shelf, high_pass) = &mut self.filters[c];
+                let filtered = high_pass.process(shelf.process(sample as f64));
+                self.subblock_energy += self.channel_weights[c] * filtered * filtered;
+            }
+            self.true_peak.process_frame(frame);
+
+            self.subblock_fill += 1;
+            if self.subblock_fill == self.subblock_frames {
+                self.subblocks.push(self.subblock_energy / self.subblock_frames as f64);
+                self.subblock_fill = 0;
+                self.subblock_energy = 0.0;
+            }
+        }
+    }
+
+    fn blocks(&self, subblocks_per_block: usize) -> Vec<f64> {
+        self.subblocks
+            .windows(subblocks_per_block)
+            .map(|w| w.iter().sum::<f64>() / subblocks_per_block as f64)
+            .collect()
+    }
+
+    /// Momentary (400ms) block energies used for integrated loudness
+    pub fn momentary_blocks(&self) -> Vec<f64> {
+        self.blocks(MOMENTARY_SUBBLOCKS)
+    }
+
+    /// Short-term (3s) block energies used for loudness range
+    pub fn short_term_blocks(&self) -> Vec<f64> {
+        self.blocks(SHORT_TERM_SUBBLOCKS)
+    }
+
+    pub fn true_peak(&self) -> f32 {
+        self.true_peak.peak as f32
+    }
+
+    pub fn measurement(&self) -> LoudnessMeasurement {
+        LoudnessMeasurement {
+            integrated_lufs: integrated_loudness(&self.momentary_blocks()) as f32,
+            loudness_range_lu: loudness_range(&self.short_term_blocks()) as f32,
+            true_peak: self.true_peak(),
+        }
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    if energy <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * energy.log10()
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+// Gated integrated loudness; silence reports the absolute gate
+fn integrated_loudness(blocks: &[f64]) -> f64 {
+    let above_absolute: Vec<f64> = blocks.iter()
+        .copied()
+        .filter(|&e| energy_to_lufs(e) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute.is_empty() {
+        return ABSOLUTE_GATE_LUFS;
+    }
+
+    let relative_gate = energy_to_lufs(mean(&above_absolute)) + RELATIVE_GATE_LU;
+    let gated: Vec<f64> = above_absolute.into_iter()
+        .filter(|&e| energy_to_lufs(e) > relative_gate)
+        .collect();
+    if gated.is_empty() {
+        return ABSOLUTE_GATE_LUFS;
+    }
+
+    energy_to_lufs(mean(&gated))
+}
+
+// Spread between the 10th and 95th percentile of gated short-term loudness
+fn loudness_range(blocks: &[f64]) -> f64 {
+    let above_absolute: Vec<f64> = blocks.iter()
+        .copied()
+        .filter(|&e| energy_to_lufs(e) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute.is_empty() {
+        return 0.0;
+    }
+
+    let relative_gate = energy_to_lufs(mean(&above_absolute)) + LRA_RELATIVE_GATE_LU;
+    let mut loudness: Vec<f64> = above_absolute.into_iter()
+        .map(energy_to_lufs)
+        .filter(|&l| l > relative_gate)
+        .collect();
+    if loudness.len() < 2 {
+        return 0.0;
+    }
+
+    loudness.sort_by(|a, b| a.total_cmp(b));
+    let last = (loudness.len() - 1) as f64;
+    let low = loudness[(last * LRA_LOW_PERCENTILE).round() as usize];
+    let high = loudness[(last * LRA_HIGH_PERCENTILE).round() as usize];
+    high - low
+}
+
+// Everything needed to combine tracks into an album measurement
+struct TrackAnalysis {
+    measurement: LoudnessMeasurement,
+    momentary_blocks: Vec<f64>,
+    short_term_blocks: Vec<f64>,
+}
+
+fn analyze_file_blocks(path: &str, cancel: &AtomicBool) -> Result<TrackAnalysis> {
+    let mut input = AudioInput::open(path)?;
+    let sample_rate = input.sample_rate;
+    let channels = input.channel_count;
+
+    // Analyze at the file's own rate and layout, just converted to packed f32
+    input.init_resampler(sample_rate, channels)?;
+
+    let mut meter = LoudnessMeter::new(sample_rate, channels);
+    let mut samples: Vec<f32> = Vec::new();
+
+    while !input.is_eof() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(anyhow!("Analysis cancelled"));
+        }
+
+        samples.clear();
+        input.decode_next(&mut samples);
+        meter.process(&samples);
+    }
+
+    Ok(TrackAnalysis {
+        measurement: meter.measurement(),
+        momentary_blocks: meter.momentary_blocks(),
+        short_term_blocks: meter.short_term_blocks(),
+    })
+}
+
+/// Measure a single file
+pub fn analyze_file(path: &str) -> Result<LoudnessMeasurement> {
+    let never_cancel = AtomicBool::new(false);
+    let analysis = analyze_file_blocks(path, &never_cancel)?;
+    info!("Loudness of {}: {:.1} LUFS, LRA {:.1} LU, true peak {:.3}",
+          path,
+          analysis.measurement.integrated_lufs,
+          analysis.measurement.loudness_range_lu,
+          analysis.measurement.true_peak);
+    Ok(analysis.measurement)
+}
+
+// Album loudness pools the blocks of every track before gating
+fn album_measurement(tracks: &[(String, TrackAnalysis)]) -> LoudnessMeasurement {
+    let momentary: Vec<f64> = tracks.iter().flat_map(|(_, t)| t.momentary_blocks.iter().copied()).collect();
+    let short_term: Vec<f64> = tracks.iter().flat_map(|(_, t)| t.short_term_blocks.iter().copied()).collect();
+    let true_peak = tracks.iter().map(|(_, t)| t.measurement.true_peak).fold(0.0, f32::max);
+
+    LoudnessMeasurement {
+        integrated_lufs: integrated_loudness(&momentary) as f32,
+        loudness_range_lu: loudness_range(&short_term) as f32,
+        true_peak,
+    }
+}
+
+/// Progress of a background batch, polled by the UI
+#[derive(Clone, Debug, Default)]
+pub struct AnalysisProgress {
+    pub total: usize,
+    pub completed: usize,
+    pub current: Option<String>,
+    // Finished results not yet collected with `take_results`
+    pub results: Vec<(String, LoudnessInfo)>,
+    pub errors: Vec<(String, String)>,
+    pub finished: bool,
+}
+
+// Group paths by album (see `album_key`), keeping the playlist order; untagged tracks
+// stand alone
+fn group_by_album(tracks: Vec<Track>) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut album_index: HashMap<(String, String), usize> = HashMap::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for track in tracks {
+        if !seen.insert(track.path.clone()) {
+            continue;
+        }
+        match album_key(track.album_artist.as_deref(), track.artist.as_deref(), track.album.as_deref()) {
+            Some(key) => {
+                if let Some(&idx) = album_index.get(&key) {
+                    groups[idx].push(track.path);
+                } else {
+                    album_index.insert(key, groups.len());
+                    groups.push(vec![track.path]);
+                }
+            }
+            None => groups.push(vec![track.path]),
+        }
+    }
+    groups
+}
+
+/// Loudness analysis of a set of tracks running on its own thread.
+/// Tracks sharing an album tag are measured together so they also get album values.
+pub struct BatchAnalysis {
+    progress: Arc<Mutex<AnalysisProgress>>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl BatchAnalysis {
+    pub fn start(tracks: Vec<Track>) -> Self {
+        let groups = group_by_album(tracks);
+        let total = groups.iter().map(|g| g.len()).sum();
+        info!("Starting loudness analysis of {} tracks in {} groups", total, groups.len());
+
+        let progress = Arc::new(Mutex::new(AnalysisProgress {
+            total,
+            ..Default::default()
+        }));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let progress_thread = Arc::clone(&progress);
+        let cancel_thread = Arc::clone(&cancel);
+
+        let handle = thread::Builder::new()
+            .name("loudness_analysis".to_string())
+            .spawn(move || run_batch(groups, progress_thread, cancel_thread));
+
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                error!("Failed to create analysis thread: {}", e);
+                if let Ok(mut p) = progress.lock() {
+                    p.finished = true;
+                    p.errors.push((String::new(), format!("Thread creation failed: {}", e)));
+                }
+                None
+            }
+        };
+
+        Self { progress, cancel, handle }
+    }
+
+    /// (completed, total)
+    pub fn progress(&self) -> (usize, usize) {
+        self.progress.lock()
+            .map(|p| (p.completed, p.total))
+            .unwrap_or((0, 0))
+    }
+
+    pub fn current_track(&self) -> Option<String> {
+        self.progress.lock().ok().and_then(|p| p.current.clone())
+    }
+
+    pub fn take_results(&self) -> Vec<(String, LoudnessInfo)> {
+        self.progress.lock()
+            .map(|mut p| std::mem::take(&mut p.results))
+            .unwrap_or_default()
+    }
+
+    pub fn take_errors(&self) -> Vec<(String, String)> {
+        self.progress.lock()
+            .map(|mut p| std::mem::take(&mut p.errors))
+            .unwrap_or_default()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress.lock().map(|p| p.finished).unwrap_or(true)
+    }
+
+    pub fn cancel(&self) {
+        info!("Cancelling loudness analysis");
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for BatchAnalysis {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_batch(groups: Vec<Vec<String>>, progress: Arc<Mutex<AnalysisProgress>>, cancel: Arc<AtomicBool>) {
+    for group in groups {
+        let mut analyzed: Vec<(String, TrackAnalysis)> = Vec::new();
+
+        for path in group {
+            if cancel.load(Ordering::SeqCst) {
+                break;
+            }
+
+            if let Ok(mut p) = progress.lock() {
+                p.current = Some(path.clone());
+            }
+
+            debug!("Analyzing loudness: {}", path);
+            match analyze_file_blocks(&path, &cancel) {
+                Ok(analysis) => {
+                    info!("Loudness of {}: {:.1} LUFS, LRA {:.1} LU, true peak {:.3}",
+                          path,
+                          analysis.measurement.integrated_lufs,
+                          analysis.measurement.loudness_range_lu,
+                          analysis.measurement.true_peak);
+                    analyzed.push((path, analysis));
+                }
+                Err(e) => {
+                    warn!("Loudness analysis failed for {}: {}", path, e);
+                    if let Ok(mut p) = progress.lock() {
+                        p.errors.push((path, e.to_string()));
+                    }
+                }
+            }
+
+            if let Ok(mut p) = progress.lock() {
+                p.completed += 1;
+            }
+        }
+
+        if cancel.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // Album values only make sense for more than one track
+        let album = if analyzed.len() > 1 {
+            Some(album_measurement(&analyzed))
+        } else {
+            None
+        };
+
+        if let Ok(mut p) = progress.lock() {
+            for (path, analysis) in analyzed {
+                p.results.push((path, LoudnessInfo {
+                    track: analysis.measurement,
+                    album,
+                }));
+            }
+        }
+    }
+
+    if let Ok(mut p) = progress.lock() {
+        p.current = None;
+        p.finished = true;
+    }
+    info!("Loudness analysis finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &str, artist: Option<&str>, album_artist: Option<&str>, album: Option<&str>) -> Track {
+        Track {
+            path: path.to_string(),
+            artist: artist.map(str::to_string),
+            album_artist: album_artist.map(str::to_string),
+            album: album.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn albums_with_the_same_title_are_kept_apart() {
+        let groups = group_by_album(vec![
+            track("a1", Some("Band A"), None, Some("Greatest Hits")),
+            track("b1", Some("Band B"), None, Some("Greatest Hits")),
+            track("a2", Some("band a"), None, Some("greatest hits ")),
+            // A compilation: one album artist, different track artists
+            track("v1", Some("Singer"), Some("Various Artists"), Some("Live")),
+            track("v2", Some("Other Singer feat. Guest"), Some("Various Artists"), Some("Live")),
+            track("u1", Some("Band A"), None, None),
+            track("a1", Some("Band A"), None, Some("Greatest Hits")),
+        ]);
+        assert_eq!(groups, vec![
+            vec!["a1".to_string(), "a2".to_string()],
+            vec!["b1".to_string()],
+            vec!["v1".to_string(), "v2".to_string()],
+            vec!["u1".to_string()],
+        ]);
+    }
+
+    const RATE: u32 = 48_000;
+
+    // Stereo sine with the same signal on both channels, as in the EBU test material
+    fn sine(frequency: f64, dbfs: f64, seconds: f64, phase: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let frames = (seconds * RATE as f64) as usize;
+        (0..frames)
+            .flat_map(|n| {
+                let t = n as f64 / RATE as f64;
+                let sample = (amplitude * (2.0 * std::f64::consts::PI * frequency * t + phase).sin()) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn measure(signal: &[f32]) -> LoudnessMeasurement {
+        let mut meter = LoudnessMeter::new(RATE, 2);
+        meter.process(signal);
+        meter.measurement()
+    }
+
+    fn assert_within(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not {} +/- {}", actual, expected, tolerance);
+    }
+
+    #[test]
+    fn sine_at_minus_23_dbfs_is_minus_23_lufs() {
+        // EBU Tech 3341 cases 1 and 2 (shortened from 20s, the level is steady): a 1 kHz
+        // stereo sine reads as its level in dBFS
+        assert_within(measure(&sine(1000.0, -23.0, 5.0, 0.0)).integrated_lufs, -23.0, 0.1);
+        assert_within(measure(&sine(1000.0, -33.0, 5.0, 0.0)).integrated_lufs, -33.0, 0.1);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated_out() {
+        // EBU Tech 3341 case 3, a fifth of the length: 2s at -36, 12s at -23, 2s at
+        // -36 dBFS is still -23 LUFS
+        let mut signal = sine(1000.0, -36.0, 2.0, 0.0);
+        signal.extend(sine(1000.0, -23.0, 12.0, 0.0));
+        signal.extend(sine(1000.0, -36.0, 2.0, 0.0));
+        assert_within(measure(&signal).integrated_lufs, -23.0, 0.1);
+
+        // Silence is below the absolute gate
+        assert_eq!(measure(&vec![0.0; RATE as usize * 2]).integrated_lufs, ABSOLUTE_GATE_LUFS as f32);
+    }
+
+    #[test]
+    fn loudness_range_of_two_levels() {
+        // EBU Tech 3342 case 1, at half length: -20 dBFS then -30 dBFS is a 10 LU range
+        let mut signal = sine(1000.0, -20.0, 10.0, 0.0);
+        signal.extend(sine(1000.0, -30.0, 10.0, 0.0));
+        assert_within(measure(&signal).loudness_range_lu, 10.0, 1.0);
+        // A steady level has none
+        assert_within(measure(&sine(1000.0, -20.0, 5.0, 0.0)).loudness_range_lu, 0.0, 0.1);
+    }
+
+    #[test]
+    fn true_peak_finds_peaks_between_samples() {
+        // A full scale sine at a quarter of the sample rate, sampled 45 degrees off its
+        // peaks: every sample is at -3 dBFS, the waveform between them reaches 0 dBTP
+        let signal = sine(RATE as f64 / 4.0, 0.0, 1.0, std::f64::consts::FRAC_PI_4);
+        let sample_peak = signal.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert_within(sample_peak, std::f32::consts::FRAC_1_SQRT_2, 1e-3);
+
+        // Tech 3341 allows +0.2 / -0.4 dB
+        let true_peak_db = 20.0 * measure(&signal).true_peak.log10();
+        assert!((-0.4..=0.2).contains(&true_peak_db), "true peak {:.2} dBTP", true_peak_db);
+
+        // Without peaks between the samples, it's the sample peak
+        let aligned = sine(RATE as f64 / 4.0, -6.0, 1.0, 0.0);
+        let true_peak_db = 20.0 * measure(&aligned).true_peak.log10();
+        assert!((-6.4..=-5.8).contains(&true_peak_db), "true peak {:.2} dBTP", true_peak_db);
+    }
+}
@@ -1,12 +1,65 @@
 // core/src/audio/buffer.rs
+// Wait-free single-producer/single-consumer ring buffer between the decoder and the audio callback
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-/// Ring buffer for audio samples to allow smoother playback
-pub struct AudioRingBuffer {
-    buffer: Vec<f32>,
+/// Underrun counters, readable from either side
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RingStats {
+    // Reads that came up short while audio was expected
+    pub underruns: u64,
+    // Samples of silence filled in by those reads
+    pub underrun_samples: u64,
+}
+
+// State shared by both halves. Positions are running sample counts, so
+// `written - read` is the fill level and wrapping only happens when indexing.
+struct Shared {
+    buffer: Box<[UnsafeCell<f32>]>,
     capacity: usize,
-    write_pos: usize,
-    read_pos: usize,
-    samples_available: usize,
+    // Only stored by the producer
+    written: AtomicU64,
+    // Only stored by the consumer
+    read: AtomicU64,
+    // The producer asks the consumer to skip everything before this position
+    discard_to: AtomicU64,
+    consumer_attached: AtomicBool,
+    // Silence is expected (paused, seeking, draining), short reads aren't underruns
+    idle: AtomicBool,
+    underruns: AtomicU64,
+    underrun_samples: AtomicU64,
+}
+
+// Safety: slots between `read` and `written` are only touched by the consumer, the rest only
+// by the producer. Neither half is Clone, so there is exactly one of each.
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn slots(&self) -> *mut f32 {
+        UnsafeCell::raw_get(self.buffer.as_ptr())
+    }
+
+    // Everything before this has been played or thrown away
+    fn consumed(&self) -> u64 {
+        let read = self.read.load(Ordering::Acquire);
+        let discard_to = self.discard_to.load(Ordering::Acquire);
+        read.max(discard_to)
+    }
+
+    fn stats(&self) -> RingStats {
+        RingStats {
+            underruns: self.underruns.load(Ordering::Relaxed),
+            underrun_samples: self.underrun_samples.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Ring buffer for audio samples to allow smoother playback.
+/// This is the writing half, owned by the decoder; the audio callback gets a `RingConsumer`.
+pub struct AudioRingBuffer {
+    shared: Arc<Shared>,
 }
 
 impl AudioRingBuffer {
@@ -14,100 +67,301 @@ impl AudioRingBuffer {
     /// The actual capacity will be at least 1024 to avoid extremely small buffers.
     pub fn new(capacity: usize) -> Self {
         let capacity = capacity.max(1024);
+        let buffer: Box<[UnsafeCell<f32>]> = (0..capacity).map(|_| UnsafeCell::new(0.0)).collect();
         Self {
-            buffer: vec![0.0; capacity],
-            capacity,
-            write_pos: 0,
-            read_pos: 0,
-            samples_available: 0,
+            shared: Arc::new(Shared {
+                buffer,
+                capacity,
+                written: AtomicU64::new(0),
+                read: AtomicU64::new(0),
+                discard_to: AtomicU64::new(0),
+                consumer_attached: AtomicBool::new(false),
+                idle: AtomicBool::new(true),
+                underruns: AtomicU64::new(0),
+                underrun_samples: AtomicU64::new(0),
+            }),
         }
     }
 
+    /// The reading half. Only one can exist at a time; once it is dropped
+    /// (e.g. with a dead stream) a new one can be handed out.
+    pub fn consumer(&self) -> Option<RingConsumer> {
+        self.shared.consumer_attached
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| RingConsumer { shared: Arc::clone(&self.shared) })
+    }
+
     /// Return the capacity of this ring buffer.
     pub fn capacity(&self) -> usize {
-        self.capacity
+        self.shared.capacity
     }
 
     /// Write as many samples as possible into the buffer.
     /// Returns the number of samples actually written.
     pub fn write(&mut self, samples: &[f32]) -> usize {
-        if samples.is_empty() || self.capacity == 0 {
-            return 0;
-        }
-
-        let mut written = 0;
-        for &sample in samples {
-            if self.samples_available >= self.capacity {
-                break;
-            }
-
-            if self.write_pos < self.buffer.len() {
-                self.buffer[self.write_pos] = sample;
-                self.write_pos = (self.write_pos + 1) % self.capacity;
-                self.samples_available += 1;
-                written += 1;
-            }
-        }
-
-        written
+        self.write_limited(samples, self.shared.capacity)
     }
 
     /// Safe version that prevents buffer overflow by limiting writes to 90% of capacity
     pub fn write_safe(&mut self, samples: &[f32]) -> usize {
-        if samples.is_empty() || self.capacity == 0 {
+        self.write_limited(samples, (self.shared.capacity as f32 * 0.9) as usize)
+    }
+
+    fn write_limited(&mut self, samples: &[f32], limit: usize) -> usize {
+        if samples.is_empty() {
             return 0;
         }
 
-        // Only fill at most 90% of capacity to prevent overflow
-        let max_to_write = (self.capacity as f32 * 0.9) as usize - self.samples_available;
-        if max_to_write == 0 {
+        let shared = &self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        // Discarded samples still occupy their slots until the consumer skips them
+        let in_use = (written - shared.read.load(Ordering::Acquire)) as usize;
+        let to_write = samples.len().min(limit.min(shared.capacity).saturating_sub(in_use));
+        if to_write == 0 {
             return 0;
         }
 
-        let to_write = std::cmp::min(samples.len(), max_to_write);
-        let mut written = 0;
-
-        // Only write what we can safely fit
-        for i in 0..to_write {
-            if self.write_pos < self.buffer.len() {
-                self.buffer[self.write_pos] = samples[i];
-                self.write_pos = (self.write_pos + 1) % self.capacity;
-                self.samples_available += 1;
-                written += 1;
-            }
+        // At most two copies: up to the end of the storage, then from the start
+        let start = (written % shared.capacity as u64) as usize;
+        let first = to_write.min(shared.capacity - start);
+        unsafe {
+            let slots = shared.slots();
+            ptr::copy_nonoverlapping(samples.as_ptr(), slots.add(start), first);
+            ptr::copy_nonoverlapping(samples.as_ptr().add(first), slots, to_write - first);
         }
 
-        written
+        shared.written.store(written + to_write as u64, Ordering::Release);
+        shared.idle.store(false, Ordering::Relaxed);
+        to_write
+    }
+
+    /// Return the number of samples currently available to read from the buffer.
+    pub fn available(&self) -> usize {
+        let written = self.shared.written.load(Ordering::Relaxed);
+        written.saturating_sub(self.shared.consumed()) as usize
+    }
+
+    /// Total number of samples written since the buffer was created.
+    /// The next write lands at this position in the stream of samples.
+    pub fn total_written(&self) -> u64 {
+        self.shared.written.load(Ordering::Relaxed)
+    }
+
+    /// Total number of samples consumed since the buffer was created.
+    /// Cleared samples count as consumed, so marks taken from this never get stuck.
+    pub fn total_read(&self) -> u64 {
+        self.shared.consumed()
+    }
+
+    /// Clear the buffer. The consumer skips the old samples on its next read.
+    pub fn clear(&mut self) {
+        let written = self.shared.written.load(Ordering::Relaxed);
+        self.shared.discard_to.store(written, Ordering::Release);
+        self.set_idle();
     }
 
+    /// Silence is expected until the next write (paused, draining), so short reads
+    /// aren't counted as underruns
+    pub fn set_idle(&self) {
+        self.shared.idle.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RingStats {
+        self.shared.stats()
+    }
+}
+
+/// Reading half of an `AudioRingBuffer`, meant for the real-time audio callback.
+/// Reads never lock or allocate.
+pub struct RingConsumer {
+    shared: Arc<Shared>,
+}
+
+impl RingConsumer {
     /// Read up to `output.len()` samples from the buffer, returning how many samples were read.
     pub fn read(&mut self, output: &mut [f32]) -> usize {
-        if output.is_empty() || self.samples_available == 0 {
-            return 0;
+        let shared = &self.shared;
+        let mut read = shared.read.load(Ordering::Relaxed);
+
+        // Catch up with a clear from the producer
+        let discard_to = shared.discard_to.load(Ordering::Acquire);
+        if discard_to > read {
+            read = discard_to;
+            shared.read.store(read, Ordering::Release);
         }
 
-        let to_read = output.len().min(self.samples_available);
+        let written = shared.written.load(Ordering::Acquire);
+        let to_read = output.len().min((written - read) as usize);
 
-        for i in 0..to_read {
-            if i < output.len() && self.read_pos < self.buffer.len() {
-                output[i] = self.buffer[self.read_pos];
-                self.read_pos = (self.read_pos + 1) % self.capacity;
+        if to_read > 0 {
+            let start = (read % shared.capacity as u64) as usize;
+            let first = to_read.min(shared.capacity - start);
+            unsafe {
+                let slots = shared.slots();
+                ptr::copy_nonoverlapping(slots.add(start), output.as_mut_ptr(), first);
+                ptr::copy_nonoverlapping(slots, output.as_mut_ptr().add(first), to_read - first);
             }
+            shared.read.store(read + to_read as u64, Ordering::Release);
+        }
+
+        if to_read < output.len() && !shared.idle.load(Ordering::Relaxed) {
+            shared.underruns.fetch_add(1, Ordering::Relaxed);
+            shared.underrun_samples.fetch_add((output.len() - to_read) as u64, Ordering::Relaxed);
         }
 
-        self.samples_available = self.samples_available.saturating_sub(to_read);
         to_read
     }
 
     /// Return the number of samples currently available to read from the buffer.
     pub fn available(&self) -> usize {
-        self.samples_available
+        let written = self.shared.written.load(Ordering::Acquire);
+        written.saturating_sub(self.shared.consumed()) as usize
     }
-    
-    /// Clear the buffer
-    pub fn clear(&mut self) {
-        self.read_pos = 0;
-        self.write_pos = 0;
-        self.samples_available = 0;
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
     }
-}
\ No newline at end of file
+
+    pub fn stats(&self) -> RingStats {
+        self.shared.stats()
+    }
+}
+
+impl Drop for RingConsumer {
+    fn drop(&mut self) {
+        self.shared.consumer_attached.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(from: usize, count: usize) -> Vec<f32> {
+        (from..from + count).map(|i| i as f32).collect()
+    }
+
+    #[test]
+    fn reads_and_writes_wrap_around_the_end() {
+        let mut buffer = AudioRingBuffer::new(1024);
+        let mut consumer = buffer.consumer().unwrap();
+        let mut output = vec![0.0; 1000];
+        assert_eq!(buffer.write(&ramp(0, 1000)), 1000);
+        assert_eq!(consumer.read(&mut output), 1000);
+        assert_eq!(output, ramp(0, 1000));
+
+        // 24 slots to the end of the storage, the rest from the start
+        assert_eq!(buffer.write(&ramp(1000, 600)), 600);
+        assert_eq!(consumer.available(), 600);
+
+        // Reads split the same way: across the end, then from the middle
+        let mut first = vec![0.0; 300];
+        let mut second = vec![0.0; 300];
+        assert_eq!(consumer.read(&mut first), 300);
+        assert_eq!(consumer.read(&mut second), 300);
+        assert_eq!(first, ramp(1000, 300));
+        assert_eq!(second, ramp(1300, 300));
+        assert_eq!(buffer.total_written(), 1600);
+        assert_eq!(buffer.total_read(), 1600);
+    }
+
+    #[test]
+    fn clearing_skips_what_the_consumer_had_not_read() {
+        let mut buffer = AudioRingBuffer::new(1024);
+        let mut consumer = buffer.consumer().unwrap();
+        buffer.write(&ramp(0, 500));
+        let mut output = vec![0.0; 100];
+        assert_eq!(consumer.read(&mut output), 100);
+
+        // Cleared with 400 samples still waiting, then new audio written after them
+        buffer.clear();
+        assert_eq!(buffer.available(), 0);
+        assert_eq!(consumer.available(), 0);
+        assert_eq!(buffer.total_read(), 500);
+        buffer.write(&ramp(1000, 50));
+
+        let mut output = vec![0.0; 200];
+        assert_eq!(consumer.read(&mut output), 50);
+        assert_eq!(output[..50], ramp(1000, 50)[..]);
+        assert_eq!(buffer.total_read(), 550);
+
+        // The skipped samples' slots are free again
+        assert_eq!(buffer.write(&ramp(0, 1024)), 1024);
+    }
+
+    #[test]
+    fn write_safe_leaves_a_tenth_free() {
+        let mut buffer = AudioRingBuffer::new(2000);
+        assert_eq!(buffer.write_safe(&ramp(0, 3000)), 1800);
+        assert_eq!(buffer.write_safe(&ramp(0, 10)), 0);
+        // A plain write may fill it
+        assert_eq!(buffer.write(&ramp(0, 3000)), 200);
+        assert_eq!(buffer.available(), 2000);
+    }
+
+    #[test]
+    fn short_reads_count_as_underruns_unless_idle() {
+        let mut buffer = AudioRingBuffer::new(1024);
+        let mut consumer = buffer.consumer().unwrap();
+        let mut output = vec![0.0; 256];
+
+        // Nothing written yet, silence is expected
+        assert_eq!(consumer.read(&mut output), 0);
+        assert_eq!(consumer.stats(), RingStats::default());
+
+        buffer.write(&ramp(0, 100));
+        assert_eq!(consumer.read(&mut output), 100);
+        assert_eq!(buffer.stats(), RingStats { underruns: 1, underrun_samples: 156 });
+
+        // Paused or draining
+        buffer.set_idle();
+        assert_eq!(consumer.read(&mut output), 0);
+        assert_eq!(buffer.stats().underruns, 1);
+    }
+
+    #[test]
+    fn one_consumer_at_a_time() {
+        let buffer = AudioRingBuffer::new(1024);
+        let consumer = buffer.consumer().unwrap();
+        assert!(buffer.consumer().is_none());
+        drop(consumer);
+        assert!(buffer.consumer().is_some());
+    }
+
+    #[test]
+    fn samples_arrive_in_order_across_threads() {
+        const TOTAL: usize = 1_000_000;
+        let mut buffer = AudioRingBuffer::new(4096);
+        let mut consumer = buffer.consumer().unwrap();
+
+        let producer = std::thread::spawn(move || {
+            let mut next = 0;
+            while next < TOTAL {
+                // Odd block sizes so the copies land all over the storage
+                let block = ramp(next, 777.min(TOTAL - next));
+                let mut written = 0;
+                while written < block.len() {
+                    written += buffer.write_safe(&block[written..]);
+                    std::thread::yield_now();
+                }
+                next += block.len();
+            }
+        });
+
+        let mut expected = 0;
+        let mut output = vec![0.0; 301];
+        while expected < TOTAL {
+            let read = consumer.read(&mut output);
+            for &sample in &output[..read] {
+                assert_eq!(sample, expected as f32, "sample {} out of order", expected);
+                expected += 1;
+            }
+            if read == 0 {
+                std::thread::yield_now();
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(consumer.available(), 0);
+    }
+}
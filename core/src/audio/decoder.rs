@@ -1,57 +1,52 @@
 // core/src/audio/decoder.rs
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex,
 };
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use log::{debug, error, info, warn};
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use ffmpeg_sys_next as ffmpeg;
-use ffmpeg_sys_next::AVMediaType::AVMEDIA_TYPE_AUDIO;
-use ffmpeg_sys_next::AVSampleFormat::AV_SAMPLE_FMT_FLT;
-
-use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_int};
 use walkdir::WalkDir; // Import WalkDir for directory traversal
 
 use crate::audio::buffer::AudioRingBuffer;
-use crate::audio::position::PlaybackPosition;
+use crate::audio::device::{OutputDeviceId, StreamHealth};
+use crate::audio::sink::{OutputSink, SinkTarget};
+use crate::audio::position::{LoopRegion, PlaybackPosition, PlayheadTracker, SeekTarget};
+use crate::audio::analysis::LoudnessInfo;
+use crate::audio::dsp::DspChain;
+use crate::audio::replaygain::ReplayGainInfo;
+use crate::audio::tags::album_key;
+use crate::audio::resampler::SampleConverter;
+use crate::audio::timestretch::TimeStretcher;
+use crate::audio::source::{backends, open_source, probe_audio, AudioSource};
+#[cfg(feature = "ffmpeg")]
+use crate::audio::ffmpeg_source;
+use crate::settings::{CrossfadeSettings, NormalizationSettings};
 use crate::PlayerState;
 use crate::PlaybackStatus; // Import PlaybackStatus properly
 
-// Initialize FFmpeg only once
-static mut FFMPEG_INITIALIZED: bool = false;
-
 // Define constants for buffer safety
-const MAX_CHANNELS: usize = 8;
 const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024; // 16MB maximum buffer size
 const MAX_DIRECTORY_DEPTH: usize = 20; // Maximum directory recursion depth
+const GAPLESS_PREOPEN_SECS: f64 = 5.0; // Open the next track this long before the current one ends
+const STREAM_STALL_TIMEOUT: Duration = Duration::from_secs(2); // No callbacks for this long means the device is gone
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(1); // How often to look for a device while none is available
 
-// Helper function to convert C string to Rust string
-unsafe fn to_string(ptr: *const c_char) -> String {
-    if ptr.is_null() {
-        return String::new();
-    }
-    CStr::from_ptr(ptr).to_string_lossy().into_owned()
-}
-
+// Set up whichever decoder backends need global initialisation
 pub fn initialize_ffmpeg() -> Result<()> {
-    unsafe {
-        if !FFMPEG_INITIALIZED {
-            ffmpeg::avformat_network_init();
-            FFMPEG_INITIALIZED = true;
-            info!("FFmpeg initialized successfully");
-        }
-    }
+    #[cfg(feature = "ffmpeg")]
+    ffmpeg_source::initialize();
+    debug!("Decoder backends: {:?}", backends());
     Ok(())
 }
 
 // Get list of supported extensions for UI filtering
+#[cfg(feature = "ffmpeg")]
 pub fn get_supported_extensions() -> Vec<String> {
     // Comprehensive list of audio formats supported by FFmpeg
     let common_extensions = [
@@ -81,7 +76,20 @@ pub fn get_supported_extensions() -> Vec<String> {
     common_extensions.iter().map(|&s| s.to_string()).collect()
 }
 
-// Check if a file is supported by FFmpeg
+// Without FFmpeg, only what Symphonia decodes
+#[cfg(not(feature = "ffmpeg"))]
+pub fn get_supported_extensions() -> Vec<String> {
+    let common_extensions = [
+        "mp3", "wav", "flac", "ogg", "oga", "m4a", "m4b", "aac", "mp4", "mka", "mkv",
+        "webm", "caf", "aiff", "aif",
+        "MP3", "WAV", "FLAC", "OGG", "OGA", "M4A", "M4B", "AAC", "MP4", "MKA", "CAF",
+        "AIFF", "AIF"
+    ];
+
+    common_extensions.iter().map(|&s| s.to_string()).collect()
+}
+
+// Check if a file is supported by one of the decoder backends
 pub fn is_supported_audio_format(path: &str) -> bool {
     // Initialize FFmpeg if needed
     if let Err(_) = initialize_ffmpeg() {
@@ -111,51 +119,8 @@ pub fn is_supported_audio_format(path: &str) -> bool {
         return false;
     }
     
-    // Try to open the file with FFmpeg
-    unsafe {
-        let c_path = match CString::new(normalized_path) {
-            Ok(s) => s,
-            Err(_) => return false,
-        };
-        
-        let mut format_ctx: *mut ffmpeg::AVFormatContext = std::ptr::null_mut();
-        
-        // Try to open the file
-        let ret = ffmpeg::avformat_open_input(
-            &mut format_ctx,
-            c_path.as_ptr(),
-            std::ptr::null_mut(),
-            std::ptr::null_mut(),
-        );
-        
-        if ret < 0 || format_ctx.is_null() {
-            return false;
-        }
-        
-        // Find stream info
-        let ret = ffmpeg::avformat_find_stream_info(format_ctx, std::ptr::null_mut());
-        if ret < 0 {
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return false;
-        }
-        
-        // Check for audio stream
-        let mut has_audio = false;
-        for i in 0..(*format_ctx).nb_streams {
-            let stream = *(*format_ctx).streams.offset(i as isize);
-            let codec_type = (*(*stream).codecpar).codec_type;
-            
-            if codec_type == AVMEDIA_TYPE_AUDIO {
-                has_audio = true;
-                break;
-            }
-        }
-        
-        // Clean up
-        ffmpeg::avformat_close_input(&mut format_ctx);
-        
-        has_audio
-    }
+    // Try to open the file with the decoder backends
+    probe_audio(&normalized_path)
 }
 
 // Identify network paths
@@ -166,7 +131,7 @@ pub fn is_network_path(path: &str) -> bool {
 }
 
 // Normal path handling
-fn normalize_path_for_check(path: &str) -> String {
+pub(crate) fn normalize_path_for_check(path: &str) -> String {
     if path.starts_with("\\\\?\\UNC\\") {
         return format!("\\\\{}", &path[8..]);
     } else if path.starts_with("\\\\?\\") {
@@ -175,784 +140,1150 @@ fn normalize_path_for_check(path: &str) -> String {
     path.to_string()
 }
 
-// Safe audio frame processing for regular playback
-unsafe fn process_audio_frame_safe(
-    frame: *mut ffmpeg::AVFrame,
-    swr_ctx: *mut ffmpeg::SwrContext,
-    channel_count: usize,
-    output_sample_rate: u32,
-    file_sample_rate: u32,
-    volume_arc: Arc<Mutex<f32>>,
-    ring_buffer: Arc<Mutex<AudioRingBuffer>>,
-    needs_data: &Arc<AtomicBool>,
-    last_buffer_warn: &mut std::time::Instant
-) -> u64 {
-    if frame.is_null() {
-        return 0;
-    }
-    
-    let nb_samples = (*frame).nb_samples;
-    if nb_samples <= 0 {
-        warn!("Skipping empty frame: {} samples", nb_samples);
-        return 0;
-    }
-    
-    // Calculate output samples directly with the correct ratio
-    let ratio = output_sample_rate as f64 / file_sample_rate as f64;
-    let output_samples_calc = (nb_samples as f64 * ratio).ceil() as i32;
-    
-    // Fix for av_samples_get_buffer_size function call
-    let mut dest_linesize = 0;
-    let dest_data_size = ffmpeg::av_samples_get_buffer_size(
-        &mut dest_linesize as *mut i32, // Fixed to be mutable
-        channel_count as i32,
-        output_samples_calc,  // Use calculated output samples
-        AV_SAMPLE_FMT_FLT,
-        1
-    );
-    
-    if dest_data_size <= 0 {
-        error!("Invalid destination buffer size calculation: {}", dest_data_size);
-        return 0;
-    }
-    
-    // Safely allocate the right sized buffer on the heap
-    let mut output = vec![0u8; dest_data_size as usize];
-    let mut output_ptr = output.as_mut_ptr();
-    
-    // Create a vector of pointers to each channel's data
-    let mut output_ptrs: Vec<*mut u8> = Vec::with_capacity(channel_count);
-    for _ in 0..channel_count {
-        output_ptrs.push(output_ptr);
-        // Point to the next channel's position
-        output_ptr = output_ptr.add(output_samples_calc as usize * std::mem::size_of::<f32>());
-    }
-    
-    // Perform the resampling
-    let out_samples = ffmpeg::swr_convert(
-        swr_ctx,
-        output_ptrs.as_mut_ptr(),
-        output_samples_calc,
-        (*frame).extended_data as *mut *const u8,
-        nb_samples
-    );
-    
-    if out_samples <= 0 {
-        error!("Resampling failed: {}", out_samples);
-        return 0;
+// Scale samples in place by a normalization gain
+fn apply_gain(samples: &mut [f32], gain: f32) {
+    if (gain - 1.0).abs() > f32::EPSILON {
+        for sample in samples.iter_mut() {
+            *sample *= gain;
+        }
     }
-    
-    // Convert the raw pointer to our output buffer to a slice of f32 samples
-    let output_samples = std::slice::from_raw_parts(
-        output.as_ptr() as *const f32,
-        (out_samples as usize) * channel_count
-    );
-    
-    // Apply volume
+}
+
+// Apply volume and push samples into the ring buffer. What doesn't fit waits for the
+// device to make room: nothing decoded may go missing, the gapless boundary marks count
+// every sample. Only stopping, or a stream that stopped reading, gives up on the rest.
+fn write_to_ring_buffer(
+    samples: &mut [f32],
+    volume_arc: &Arc<Mutex<f32>>,
+    ring_buffer: &mut AudioRingBuffer,
+    needs_data: &Arc<AtomicBool>,
+    stop_flag: &AtomicBool,
+    stream_health: &StreamHealth,
+) {
     let volume = match volume_arc.lock() {
         Ok(v) => *v,
         Err(_) => 1.0,
     };
-    
-    // Create a new volume-adjusted buffer
-    let mut volume_adjusted = Vec::with_capacity(output_samples.len());
-    for &sample in output_samples {
-        volume_adjusted.push(sample * volume);
-    }
-    
-    // Write to ring buffer
-    let buffer_health = {
-        if let Ok(mut rb) = ring_buffer.lock() {
-            let _written = rb.write_safe(&volume_adjusted);
-            
-            let available = rb.available();
-            let buffer_size = rb.capacity();
-            if buffer_size > 0 {
-                (available as f32) / (buffer_size as f32)
-            } else {
-                0.0
-            }
-        } else {
-            0.0
+
+    // Scale in place, the samples aren't used after this
+    apply_gain(samples, volume);
+    let mut written = ring_buffer.write_safe(samples);
+    while written < samples.len() {
+        if stop_flag.load(Ordering::SeqCst) {
+            return;
         }
-    };
-    
+        if let Some(reason) = stream_health.failure(STREAM_STALL_TIMEOUT) {
+            warn!("Dropping {} samples, the output stream isn't reading ({})", samples.len() - written, reason);
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+        written += ring_buffer.write_safe(&samples[written..]);
+    }
+
+    let buffer_health = ring_buffer.available() as f32 / ring_buffer.capacity() as f32;
+
     // Reset needs_data flag if we've processed enough data
     if buffer_health > 0.5 {
         needs_data.store(false, Ordering::Release);
     }
-    
-    *last_buffer_warn = std::time::Instant::now();
-    
-    // Return the number of frames decoded
-    nb_samples as u64
 }
 
-pub fn play_audio_file(
-    path: &str,
-    pause_flag: Arc<AtomicBool>,
-    stop_flag: Arc<AtomicBool>,
-    state_arc: Arc<Mutex<PlayerState>>,
-    playback_position: Arc<Mutex<PlaybackPosition>>,
-    volume_arc: Arc<Mutex<f32>>,
-) -> Result<()> {
-    info!("Attempting to play file: {}", path);
-    
-    // Initialize FFmpeg
-    initialize_ffmpeg()?;
-    
-    // Check if file exists for local files
-    let is_network = is_network_path(path);
-    if !is_network {
-        let norm_path = normalize_path_for_check(path);
-        if !Path::new(&norm_path).exists() {
-            error!("File not found: {}", norm_path);
-            return Err(anyhow!("File not found: {}", norm_path));
+// Run a block through the time-stretcher when playing at another speed. Returns the source
+// frame of the first sample left in `samples`, given the source frame of the block that went in.
+fn stretch_block(
+    stretcher: &mut TimeStretcher,
+    samples: &mut Vec<f32>,
+    scratch: &mut Vec<f32>,
+    first_frame: u64,
+    source_frames_per_frame: f64,
+    end_of_track: bool,
+) -> u64 {
+    if stretcher.is_bypassed() {
+        return first_frame;
+    }
+
+    let fed_before = stretcher.input_frames() as f64;
+    scratch.clear();
+    let mut first_position = stretcher.process(samples, scratch);
+
+    // Back at normal speed, or nothing more is coming: hand out what's held and pass blocks through again
+    if stretcher.speed() == 1.0 || end_of_track {
+        let produced = scratch.len();
+        let flushed_position = stretcher.flush(scratch);
+        if produced == 0 {
+            first_position = flushed_position;
         }
     }
-    
-    // Set streaming mode
-    if let Ok(mut state) = state_arc.lock() {
-        state.network_buffering = false;
-        state.buffer_progress = 1.0;
+
+    std::mem::swap(samples, scratch);
+    (first_frame as f64 + (first_position - fed_before) * source_frames_per_frame).max(0.0) as u64
+}
+
+/// An opened audio file: its decoder backend plus conversion to the output format.
+/// Kept together so the next track can be opened while the current one is still playing.
+pub(crate) struct AudioInput {
+    pub(crate) path: String,
+    source: Box<dyn AudioSource>,
+    converter: Option<SampleConverter>,
+    // Decoded samples before conversion, reused between packets
+    decoded: Vec<f32>,
+    // Decoded past a seek target, waiting to be converted by the next `decode_next`
+    carry: Vec<f32>,
+    // Source frame of the next sample from the backend, kept in step with packet timestamps
+    next_frame: u64,
+    // Source frame the converter's output starts at (the open or seek position)
+    output_base: u64,
+    pub(crate) sample_rate: u32,
+    pub(crate) channel_count: usize,
+    // None for live streams and files that don't say
+    pub(crate) duration_secs: Option<f64>,
+    pub(crate) seekable: bool,
+    output_channels: usize,
+    album: Option<(String, String)>,
+    replaygain: ReplayGainInfo,
+}
+
+impl AudioInput {
+    /// Open a file and its decoder. The resampler is set up separately with
+    /// `init_resampler` once the output format is known.
+    pub(crate) fn open(path: &str) -> Result<Self> {
+        initialize_ffmpeg()?;
+
+        // Check if file exists for local files
+        if !is_network_path(path) {
+            let norm_path = normalize_path_for_check(path);
+            if !Path::new(&norm_path).exists() {
+                error!("File not found: {}", norm_path);
+                return Err(anyhow!("File not found: {}", norm_path));
+            }
+        }
+
+        let source = open_source(path)?;
+        let info = source.info().clone();
+        info!("Opened {} with the {} backend (codec {}, {} channels, {} Hz)",
+              path, source.backend(), info.codec, info.channels, info.sample_rate);
+
+        Ok(AudioInput {
+            path: path.to_string(),
+            source,
+            converter: None,
+            decoded: Vec::new(),
+            carry: Vec::new(),
+            next_frame: 0,
+            output_base: 0,
+            sample_rate: info.sample_rate,
+            channel_count: info.channels,
+            duration_secs: info.duration_secs,
+            seekable: info.seekable,
+            output_channels: 2,
+            album: album_key(info.tags.album_artist.as_deref(), info.tags.artist.as_deref(), info.tags.album.as_deref()),
+            replaygain: info.replaygain,
+        })
     }
-    
-    // Variables for metadata
-    let mut track_duration_secs = 300.0; // Default
-    let mut channel_count = 2; // Default
-    let mut sample_rate = 44100; // Default
-
-    // Track completion flag for completion detection
-    let track_completed_flag = Arc::new(AtomicBool::new(false));
-
-    unsafe {
-        // Create a C-string from the path
-        let c_path = match CString::new(path) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Invalid path string: {}", e);
-                return Err(anyhow!("Invalid path string: {}", e));
+
+    /// Set up conversion from the decoder's format to the output rate and channel count
+    pub(crate) fn init_resampler(&mut self, output_sample_rate: u32, output_channels: usize) -> Result<()> {
+        // Reinitialising for a new output format replaces the old converter
+        self.converter = Some(SampleConverter::new(
+            self.sample_rate,
+            self.channel_count,
+            output_sample_rate,
+            output_channels,
+        )?);
+        self.output_channels = output_channels;
+        self.output_base = self.next_frame;
+        Ok(())
+    }
+
+    /// Decode the next audio packet and append the converted samples to `output`.
+    /// Returns the number of source frames decoded; at end of file returns 0 and sets `is_eof`.
+    pub(crate) fn decode_next(&mut self, output: &mut Vec<f32>) -> u64 {
+        if self.is_eof() {
+            return 0;
+        }
+
+        let converter = match self.converter.as_mut() {
+            Some(converter) => converter,
+            None => {
+                error!("Resampler not initialized for {}", self.path);
+                return 0;
             }
         };
-        
-        // Create format context
-        let mut format_ctx: *mut ffmpeg::AVFormatContext = std::ptr::null_mut();
-        
-        // For network files, create format options with longer timeouts
-        let mut options: *mut ffmpeg::AVDictionary = std::ptr::null_mut();
-        if is_network {
-            // Set timeout values
-            let timeout_key = CString::new("timeout").unwrap();
-            let timeout_val = CString::new("10000000").unwrap(); // 10 seconds in microseconds
-            
-            ffmpeg::av_dict_set(&mut options, timeout_key.as_ptr(), timeout_val.as_ptr(), 0);
+
+        self.decoded.clear();
+        let frames_decoded = if !self.carry.is_empty() {
+            // Left over from a seek, already counted in next_frame
+            std::mem::swap(&mut self.decoded, &mut self.carry);
+            (self.decoded.len() / self.channel_count.max(1)) as u64
+        } else {
+            let frames = self.source.next_frames(&mut self.decoded);
+            if frames > 0 {
+                // Timestamps win over counting, they stay right across gaps in the stream
+                let start = self.source.frame_position().unwrap_or(self.next_frame);
+                self.next_frame = start + frames;
+            }
+            frames
+        };
+
+        if let Err(e) = converter.process(&self.decoded, output) {
+            warn!("{}", e);
         }
-        
-        // Open input
-        let ret = ffmpeg::avformat_open_input(
-            &mut format_ctx,
-            c_path.as_ptr(),
-            std::ptr::null_mut(),
-            &mut options
-        );
-        
-        // Free options dictionary
-        if !options.is_null() {
-            ffmpeg::av_dict_free(&mut options);
+
+        // The resampler holds back a little audio, push it out with the last packet
+        if self.source.is_eof() {
+            if let Err(e) = converter.flush(output) {
+                warn!("{}", e);
+            }
         }
-        
-        if ret < 0 || format_ctx.is_null() {
-            let error_buf = [0i8; 1024];
-            ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-            let error_msg = to_string(error_buf.as_ptr());
-            error!("Could not open input file: {} ({})", error_msg, ret);
-            return Err(anyhow!("Could not open input file: {}", error_msg));
+
+        frames_decoded
+    }
+
+    /// Resolve a seek request to seconds. Fractions need a known duration.
+    fn seek_target_secs(&self, target: SeekTarget) -> Option<f64> {
+        match target {
+            SeekTarget::Fraction(fraction) => self.duration_secs.map(|d| fraction.clamp(0.0, 1.0) as f64 * d),
+            SeekTarget::Time(position) => Some(position.as_secs_f64()),
         }
-        
-        // Find stream info
-        let ret = ffmpeg::avformat_find_stream_info(format_ctx, std::ptr::null_mut());
-        if ret < 0 {
-            let error_buf = [0i8; 1024];
-            ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-            let error_msg = to_string(error_buf.as_ptr());
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            error!("Could not find stream information: {} ({})", error_msg, ret);
-            return Err(anyhow!("Could not find stream information: {}", error_msg));
+    }
+
+    /// Seek to a time in seconds. Returns the new position in source frames,
+    /// which is the exact target unless the target lies past the end.
+    fn seek(&mut self, target_time_seconds: f64) -> Result<u64> {
+        if !self.seekable {
+            return Err(anyhow!("{} is not seekable", self.path));
         }
-        
-        // Find audio stream
-        let mut audio_stream_idx: i32 = -1;
-        
-        for i in 0..(*format_ctx).nb_streams {
-            let stream = *(*format_ctx).streams.offset(i as isize);
-            let codec_params = (*stream).codecpar;
-            
-            if (*codec_params).codec_type == AVMEDIA_TYPE_AUDIO {
-                audio_stream_idx = i as i32;
-                channel_count = (*codec_params).ch_layout.nb_channels as usize;
-                sample_rate = (*codec_params).sample_rate as u32;
-                break;
+        let target_time_seconds = match self.duration_secs {
+            Some(duration) => target_time_seconds.clamp(0.0, duration),
+            None => target_time_seconds.max(0.0),
+        };
+        let target_frame = (target_time_seconds * self.sample_rate as f64).round() as u64;
+        let landed_frame = self.source.seek(target_time_seconds)?;
+        self.next_frame = landed_frame;
+
+        // The backend lands on a packet (or keyframe) at or before the target.
+        // Decode from there and drop everything before the target sample.
+        let channels = self.channel_count.max(1);
+        let mut discarded: u64 = 0;
+        self.carry.clear();
+        while !self.source.is_eof() {
+            self.carry.clear();
+            let frames = self.source.next_frames(&mut self.carry);
+            if frames == 0 {
+                continue;
             }
+
+            let start = self.source.frame_position().unwrap_or(self.next_frame);
+            self.next_frame = start + frames;
+            if self.next_frame <= target_frame {
+                discarded += frames;
+                continue;
+            }
+
+            let skip = target_frame.saturating_sub(start).min(frames);
+            self.carry.drain(..(skip as usize * channels).min(self.carry.len()));
+            discarded += skip;
+            break;
         }
-        
-        if audio_stream_idx == -1 {
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Could not find any audio stream in the file"));
+        if self.source.is_eof() && self.next_frame <= target_frame {
+            self.carry.clear();
         }
-        
-        // Limit channel count for safety
-        let safe_channel_count = std::cmp::min(channel_count, MAX_CHANNELS);
-        if channel_count > MAX_CHANNELS {
-            warn!("Limiting channels from {} to {} for safety", channel_count, safe_channel_count);
-            channel_count = safe_channel_count;
+
+        let position = self.next_frame - (self.carry.len() / channels) as u64;
+        debug!("Seek to frame {} landed at {}, discarded {} frames, resuming at {}",
+               target_frame, landed_frame, discarded, position);
+
+        // Drop audio queued from before the seek
+        if let Some(converter) = self.converter.as_mut() {
+            converter.reset();
         }
-        
-        info!("Found audio stream: {} channels, {} Hz", channel_count, sample_rate);
-        
-        // Get the stream for codec info
-        let stream = *(*format_ctx).streams.offset(audio_stream_idx as isize);
-        let codec_params = (*stream).codecpar;
-        
-        // Find decoder
-        let codec = ffmpeg::avcodec_find_decoder((*codec_params).codec_id);
-        if codec.is_null() {
-            error!("Unsupported codec ID: {:?}", (*codec_params).codec_id);
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Unsupported codec"));
+        self.output_base = position;
+        Ok(position)
+    }
+
+    /// Source frame of the next sample `decode_next` will output, after conversion
+    fn output_position(&self) -> u64 {
+        match self.converter.as_ref() {
+            Some(converter) => {
+                let out_rate = converter.output_rate().max(1) as f64;
+                self.output_base + (converter.frames_out() as f64 * self.sample_rate as f64 / out_rate) as u64
+            }
+            None => self.output_base,
         }
-        
-        info!("Using codec: {}", CStr::from_ptr((*codec).name).to_string_lossy());
-        
-        // Create codec context
-        let codec_ctx = ffmpeg::avcodec_alloc_context3(codec);
-        if codec_ctx.is_null() {
-            error!("Failed to allocate codec context");
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Could not allocate codec context"));
+    }
+
+    // Source frames covered by a number of output frames
+    fn output_to_source_frames(&self, output_frames: usize, output_rate: u32) -> u64 {
+        (output_frames as f64 * self.sample_rate as f64 / output_rate.max(1) as f64) as u64
+    }
+
+    /// Source frame decoding has reached, from packet timestamps
+    fn position(&self) -> u64 {
+        self.next_frame - (self.carry.len() / self.channel_count.max(1)) as u64
+    }
+
+    // Linear gain for the current normalization settings
+    fn gain_factor(&self, settings: &NormalizationSettings) -> f32 {
+        self.replaygain.gain_factor(settings)
+    }
+
+    // Consecutive tracks of the same album play through without crossfading
+    fn same_album_as(&self, other: &AudioInput) -> bool {
+        self.album.is_some() && self.album == other.album
+    }
+
+    pub(crate) fn is_eof(&self) -> bool {
+        self.source.is_eof() && self.carry.is_empty()
+    }
+
+    // Total interleaved samples, as tracked by PlaybackPosition (0 when the length is unknown)
+    fn total_samples(&self) -> u64 {
+        self.duration_secs
+            .map(|d| (d * self.sample_rate as f64) as u64 * self.channel_count as u64)
+            .unwrap_or(0)
+    }
+
+    // Without a known length the end is never near, so nothing is preopened or crossfaded by time
+    fn remaining_secs(&self, current_frames: u64) -> f64 {
+        match self.duration_secs {
+            Some(duration) => duration - current_frames as f64 / self.sample_rate.max(1) as f64,
+            None => f64::INFINITY,
         }
-        
-        // Copy codec parameters to context
-        if ffmpeg::avcodec_parameters_to_context(codec_ctx, codec_params) < 0 {
-            error!("Failed to copy codec parameters to context");
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Could not copy codec parameters to context"));
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration_secs.map(Duration::from_secs_f64)
+    }
+}
+
+/// Shared handles the decode loop reads while a file is playing
+#[derive(Clone)]
+pub struct PlaybackOptions {
+    // Track to continue into when the current one ends
+    pub next_track: Arc<Mutex<Option<String>>>,
+    pub crossfade: Arc<Mutex<CrossfadeSettings>>,
+    pub normalization: Arc<Mutex<NormalizationSettings>>,
+    // Analyzer results by path, for files without loudness tags
+    pub loudness_hints: Arc<Mutex<HashMap<String, LoudnessInfo>>>,
+    // Processing stages between resampling and the ring buffer
+    pub dsp: Arc<Mutex<DspChain>>,
+    // None plays on the host's default device
+    pub output_device: Arc<Mutex<Option<OutputDeviceId>>>,
+    // Device output, or a headless sink for running without sound hardware
+    pub sink: Arc<Mutex<OutputSink>>,
+    // Playback speed, 1.0 is normal. Pitch is kept when it isn't.
+    pub speed: Arc<Mutex<f32>>,
+    // A–B loop on the current track, if one is set
+    pub ab_loop: Arc<Mutex<Option<LoopRegion>>>,
+}
+
+impl PlaybackOptions {
+    pub fn new() -> Self {
+        Self {
+            next_track: Arc::new(Mutex::new(None)),
+            crossfade: Arc::new(Mutex::new(CrossfadeSettings::new())),
+            normalization: Arc::new(Mutex::new(NormalizationSettings::new())),
+            loudness_hints: Arc::new(Mutex::new(HashMap::new())),
+            dsp: Arc::new(Mutex::new(DspChain::new())),
+            output_device: Arc::new(Mutex::new(None)),
+            sink: Arc::new(Mutex::new(OutputSink::Device)),
+            speed: Arc::new(Mutex::new(1.0)),
+            ab_loop: Arc::new(Mutex::new(None)),
         }
-        
-        // Force safe channel count in codec context
-        (*codec_ctx).ch_layout.nb_channels = safe_channel_count as c_int;
-        
-        // Log codec parameters for debugging
-        info!("Codec parameters: sample_fmt={:?}, sample_rate={}, channels={}", 
-            (*codec_ctx).sample_fmt,
-            (*codec_ctx).sample_rate,
-            (*codec_ctx).ch_layout.nb_channels);
-        
-        // Open codec
-        if ffmpeg::avcodec_open2(codec_ctx, codec, std::ptr::null_mut()) < 0 {
-            error!("Could not open codec");
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Could not open codec"));
+    }
+
+    // Run the DSP chain over a block of output samples
+    fn process_dsp(&self, samples: &mut [f32]) {
+        if samples.is_empty() {
+            return;
         }
-        
-        // Calculate duration from format context
-        if (*format_ctx).duration > 0 {
-            track_duration_secs = (*format_ctx).duration as f64 / ffmpeg::AV_TIME_BASE as f64;
+        if let Ok(mut chain) = self.dsp.lock() {
+            chain.process(samples);
         }
-        
-        let track_duration = Duration::from_secs_f64(track_duration_secs);
-        info!("Track duration: {:?}", track_duration);
-        
-        // Update player state with duration
-        if let Ok(mut state) = state_arc.lock() {
-            state.duration = Some(track_duration);
+    }
+
+    // Fall back to analyzed loudness when the file carries no tags
+    fn apply_loudness_hint(&self, input: &mut AudioInput) {
+        if !input.replaygain.is_empty() {
+            return;
         }
-        
-        // Set up audio output with cpal
-        info!("Setting up audio output with CPAL...");
-        let host = cpal::default_host();
-        info!("Audio host: {}", host.id().name());
-        
-        let device = match host.default_output_device() {
-            Some(device) => {
-                info!("Using output device: {}", device.name().unwrap_or_else(|_| String::from("Unknown")));
-                device
-            },
-            None => {
-                error!("No output audio device available!");
-                return Err(anyhow!("No output audio device available"));
+
+        let hint = self.loudness_hints.lock()
+            .ok()
+            .and_then(|hints| hints.get(&input.path).copied());
+        if let Some(loudness) = hint {
+            input.replaygain = ReplayGainInfo::from_loudness(&loudness);
+            info!("Using analyzed loudness for {}: {:?}", input.path, input.replaygain);
+        }
+    }
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The incoming track while it is being faded in over the end of the current one
+struct ActiveCrossfade {
+    input: AudioInput,
+    // Decoded samples of the incoming track not mixed yet
+    pending: Vec<f32>,
+    // Source frames decoded from the incoming track so far
+    frames_decoded: u64,
+    // Output frames mixed so far and the length of the fade
+    position: usize,
+    length: usize,
+    settings: CrossfadeSettings,
+}
+
+impl ActiveCrossfade {
+    // `remaining_secs` is what's left of the outgoing track. A track shorter than the fade,
+    // or one seeked into its last seconds, gets a shorter fade that still ends at its end,
+    // instead of cutting off part way with the incoming track not at full volume yet.
+    fn new(input: AudioInput, settings: CrossfadeSettings, output_sample_rate: u32, remaining_secs: f64) -> Self {
+        let fade_secs = (settings.duration_secs as f64).min(remaining_secs.max(0.0));
+        let length = (fade_secs * output_sample_rate as f64) as usize;
+        Self {
+            input,
+            pending: Vec::new(),
+            frames_decoded: 0,
+            position: 0,
+            length: length.max(1),
+            settings,
+        }
+    }
+
+    // Mix the incoming track, scaled by its own gain, into a block of the outgoing one
+    fn mix_into(&mut self, samples: &mut [f32], gain: f32) {
+        let channels = self.input.output_channels.max(1);
+
+        // Make sure there is enough of the incoming track decoded
+        while self.pending.len() < samples.len() && !self.input.is_eof() {
+            self.frames_decoded += self.input.decode_next(&mut self.pending);
+        }
+        if self.pending.len() < samples.len() {
+            self.pending.resize(samples.len(), 0.0);
+        }
+
+        for (i, frame) in samples.chunks_mut(channels).enumerate() {
+            let t = (self.position + i) as f32 / self.length as f32;
+            let (gain_out, fade_in) = self.settings.gains(t);
+            let gain_in = fade_in * gain;
+            for (c, sample) in frame.iter_mut().enumerate() {
+                *sample = *sample * gain_out + self.pending[i * channels + c] * gain_in;
             }
-        };
-    
-        let mut config_range = device
-            .supported_output_configs()
-            .map_err(|e| {
-                error!("Failed to get device configs: {}", e);
-                anyhow!("Failed to get device configs: {}", e)
-            })?
-            .filter(|c| c.channels() >= channel_count as u16)
-            .collect::<Vec<_>>();
-        
-        if config_range.is_empty() {
-            error!("No suitable output config found for device (needed {} channels)", channel_count);
-            return Err(anyhow!("No suitable output config found for device"));
         }
-        
-        config_range.sort_by_key(|c| c.min_sample_rate().0);
-        let desired_sample_rates = [sample_rate, 48000, 44100, 96000, 192000];
-        let mut selected_config = None;
-    
-        // Try to find a config that supports our desired sample rates
-        for &rate in &desired_sample_rates {
-            for c in &config_range {
-                if rate >= c.min_sample_rate().0 && rate <= c.max_sample_rate().0 {
-                    selected_config = Some(c.with_sample_rate(cpal::SampleRate(rate)));
-                    info!("Selected output config: {} channels, {} Hz", 
-                          c.channels(), rate);
-                    break;
+
+        self.position += samples.len() / channels;
+        self.pending.drain(..samples.len());
+    }
+}
+
+// Open the queued track and prepare it for the running output stream.
+// Failures are only logged - they just mean there is no gapless switch.
+fn open_next_input(
+    path: &str,
+    output_sample_rate: u32,
+    output_channels: usize,
+    options: &PlaybackOptions,
+) -> Option<AudioInput> {
+    info!("Opening next track for gapless playback: {}", path);
+
+    let mut input = match AudioInput::open(path) {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("Could not open next track {}: {}", path, e);
+            return None;
+        }
+    };
+
+    if let Err(e) = input.init_resampler(output_sample_rate, output_channels) {
+        warn!("Could not prepare next track {}: {}", path, e);
+        return None;
+    }
+    options.apply_loudness_hint(&mut input);
+
+    Some(input)
+}
+
+// The loop's in point, opened and seeked ahead of time so jumping back leaves no gap.
+// `input` is None when that failed, the jump then seeks the playing input instead.
+struct LoopStart {
+    region: LoopRegion,
+    input: Option<AudioInput>,
+}
+
+impl LoopStart {
+    fn prepare(region: LoopRegion, output_sample_rate: u32, output_channels: usize, options: &PlaybackOptions) -> Self {
+        info!("Preparing loop start at {:.2}s of {}", region.start.as_secs_f64(), region.path);
+
+        let input = open_next_input(&region.path, output_sample_rate, output_channels, options)
+            .and_then(|mut input| match input.seek(region.start.as_secs_f64()) {
+                Ok(_) => Some(input),
+                Err(e) => {
+                    warn!("Could not seek to the loop start: {}", e);
+                    None
+                }
+            });
+
+        Self { region, input }
+    }
+}
+
+// Point the playback position at a newly started input
+fn reset_position_for_input(playback_position: &Arc<Mutex<PlaybackPosition>>, input: &AudioInput) {
+    if let Ok(mut pos) = playback_position.lock() {
+        pos.reset();
+        pos.set_total_samples(input.total_samples());
+        pos.set_channel_count(input.channel_count);
+        pos.sample_rate = input.sample_rate;
+    }
+}
+
+// Publish a gapless track change once the device has actually played past the boundary
+fn announce_track_change(
+    state_arc: &Arc<Mutex<PlayerState>>,
+    playback_position: &Arc<Mutex<PlaybackPosition>>,
+    input: &AudioInput,
+    finished_track: String,
+    played_frame: u64,
+) {
+    info!("Track boundary reached, now playing: {}", input.path);
+
+    reset_position_for_input(playback_position, input);
+    if let Ok(pos) = playback_position.lock() {
+        pos.update_played_frame(played_frame);
+    }
+
+    if let Ok(mut state) = state_arc.lock() {
+        state.finished_tracks.push(finished_track);
+        state.current_track = Some(input.path.clone());
+        state.duration = input.duration();
+        state.seekable = input.seekable;
+        state.progress = 0.0;
+    }
+}
+
+// Pick the configured sink: the selected output device (or the default) with a config
+// for the track, or a headless one
+fn open_output(
+    options: &PlaybackOptions,
+    sample_rate: u32,
+    channel_count: usize,
+) -> Result<SinkTarget> {
+    let sink = options.sink.lock().map(|s| s.clone()).unwrap_or_default();
+    let selected_device = options.output_device.lock().ok().and_then(|d| d.clone());
+    SinkTarget::open(&sink, selected_device.as_ref(), sample_rate, channel_count)
+}
+
+fn ring_buffer_size_for(output_sample_rate: u32, output_channels: usize) -> usize {
+    let desired_buffer_frames = (output_sample_rate as usize * output_channels * 4) / 5; // 800ms buffer
+    let max_buffer_frames = MAX_BUFFER_SIZE / (output_channels * std::mem::size_of::<f32>());
+    std::cmp::min(desired_buffer_frames, max_buffer_frames)
+}
+
+fn push_device_event(state_arc: &Arc<Mutex<PlayerState>>, event: String) {
+    info!("{}", event);
+    if let Ok(mut state) = state_arc.lock() {
+        state.device_events.push(event);
+    }
+}
+
+pub fn play_audio_file(
+    path: &str,
+    pause_flag: Arc<AtomicBool>,
+    stop_flag: Arc<AtomicBool>,
+    state_arc: Arc<Mutex<PlayerState>>,
+    playback_position: Arc<Mutex<PlaybackPosition>>,
+    volume_arc: Arc<Mutex<f32>>,
+    options: PlaybackOptions,
+) -> Result<()> {
+    info!("Attempting to play file: {}", path);
+
+    // Set streaming mode
+    if let Ok(mut state) = state_arc.lock() {
+        state.network_buffering = false;
+        state.buffer_progress = 1.0;
+    }
+
+    let mut input = AudioInput::open(path)?;
+    options.apply_loudness_hint(&mut input);
+    let channel_count = input.channel_count;
+    let sample_rate = input.sample_rate;
+
+    let track_duration = input.duration();
+    info!("Track duration: {:?} (seekable: {})", track_duration, input.seekable);
+
+    // Update player state with duration
+    if let Ok(mut state) = state_arc.lock() {
+        state.duration = track_duration;
+        state.seekable = input.seekable;
+    }
+
+    // Set up audio output with cpal on the selected device (or the default), or a headless sink
+    info!("Setting up audio output...");
+    let target = open_output(&options, sample_rate, channel_count)?;
+
+    if let Ok(mut state) = state_arc.lock() {
+        state.output_device = Some(target.name());
+        state.device_lost = false;
+    }
+
+    // Both can change if the stream has to be reopened on another device.
+    // Tracks are converted to the stream's layout so later tracks can reuse it
+    let (mut output_sample_rate, mut output_channels) = target.format();
+    info!("Output config: {} channels, {} Hz", output_channels, output_sample_rate);
+
+    reset_position_for_input(&playback_position, &input);
+
+    // Set up ring buffer for audio output - make it larger for safety but with limits
+    let ring_buffer_size = ring_buffer_size_for(output_sample_rate, output_channels);
+
+    info!("Creating ring buffer with {} samples ({:.2} MB)",
+          ring_buffer_size,
+          (ring_buffer_size * std::mem::size_of::<f32>()) as f32 / (1024.0 * 1024.0));
+
+    let mut ring_buffer = AudioRingBuffer::new(ring_buffer_size);
+    let needs_data = Arc::new(AtomicBool::new(true));
+    let mut playhead = PlayheadTracker::new(output_sample_rate, output_channels);
+    playhead.restart(0, 0, input.sample_rate);
+
+    // Debug the buffer
+    info!("Ring buffer initialized: capacity={}, available={}",
+          ring_buffer.capacity(), ring_buffer.available());
+
+    let consumer = ring_buffer.consumer()
+        .ok_or_else(|| anyhow!("Ring buffer already has a reader"))?;
+    let mut stream_health = StreamHealth::new();
+    let mut audio_stream = Some(target.start(
+        consumer,
+        Arc::clone(&needs_data),
+        Arc::clone(&stream_health),
+    )?);
+
+    input.init_resampler(output_sample_rate, output_channels)?;
+
+    // DSP stages run at the output format, with fresh filter state for the new stream
+    if let Ok(mut chain) = options.dsp.lock() {
+        chain.configure(output_sample_rate, output_channels);
+        chain.reset();
+    }
+
+    info!("Beginning decode loop for file: {}", path);
+
+    // Main decoding loop
+    let mut current_frames: u64 = 0;
+    let mut last_progress_log = std::time::Instant::now();
+    let mut samples: Vec<f32> = Vec::new();
+    // Speed changes keep the pitch, applied between mixing and the DSP chain
+    let mut stretcher = TimeStretcher::new(output_sample_rate, output_channels);
+    let mut stretched: Vec<f32> = Vec::new();
+    let mut loop_start: Option<LoopStart> = None;
+
+    // Next track, opened shortly before the current one ends
+    let mut preopened: Option<AudioInput> = None;
+    let mut preopen_attempted = false;
+    // Next track being faded in, and whether this track was already considered for a fade
+    let mut crossfade: Option<ActiveCrossfade> = None;
+    let mut crossfade_checked = false;
+    // A gapless switch whose old samples are still in the ring buffer:
+    // (ring buffer read count at the boundary, path of the track that finished)
+    let mut pending_boundary: Option<(u64, String)> = None;
+    // Set while the stream is gone and we're looking for a device to reopen it on
+    let mut device_retry_at: Option<Instant> = None;
+    // Whether the pause came from losing the device (and should end when one returns)
+    let mut paused_for_device = false;
+
+    while !stop_flag.load(Ordering::SeqCst) {
+        // Announce a gapless switch once the device has played the old track's last sample
+        if let Some((boundary_mark, _)) = &pending_boundary {
+            if ring_buffer.total_read() >= *boundary_mark {
+                if let Some((_, finished_track)) = pending_boundary.take() {
+                    let played_frame = playhead.played_frame(ring_buffer.total_read());
+                    announce_track_change(&state_arc, &playback_position, &input, finished_track, played_frame);
                 }
-            }
-            if selected_config.is_some() {
-                break;
             }
         }
-    
-        let device_config = selected_config.unwrap_or_else(|| {
-            let config = &config_range[0];
-            
-            // Choose a safe sample rate close to the original
-            let mut target_rate = sample_rate;
-            
-            // If the file rate is extremely low or high, use a standard rate instead
-            if sample_rate < 8000 || sample_rate > 192000 {
-                target_rate = 44100; // Use a standard rate
-                warn!("File has unusual sample rate ({}Hz). Using standard 44.1kHz output instead.", 
-                     sample_rate);
-            }
-            
-            let sample_rate = if target_rate <= config.min_sample_rate().0 {
-                config.min_sample_rate().0
-            } else if target_rate >= config.max_sample_rate().0 {
-                config.max_sample_rate().0
-            } else {
-                target_rate
-            };
-            
-            info!("Using output sample rate: {} Hz", sample_rate);
-            config.clone().with_sample_rate(cpal::SampleRate(sample_rate))
-        });
-        
-        let config = device_config.config();
-        let output_sample_rate = config.sample_rate.0;
-        info!("Output config: {} channels, {} Hz", config.channels, output_sample_rate);
-    
-        // Calculate total samples based on duration
-        let total_samples = (track_duration_secs * sample_rate as f64) as u64 * channel_count as u64;
-        
-        if let Ok(mut pos) = playback_position.lock() {
-            pos.set_total_samples(total_samples);
-            pos.set_channel_count(channel_count);
-            pos.sample_rate = sample_rate;
+
+        // Report what the device has actually played, not what has been decoded
+        let played_frame = playhead.played_frame(ring_buffer.total_read());
+        if let Ok(pos) = playback_position.lock() {
+            pos.update_played_frame(played_frame);
         }
-        
-        // Set up ring buffer for audio output - make it larger for safety but with limits
-        let desired_buffer_frames = (output_sample_rate as usize * channel_count * 4) / 5; // 800ms buffer
-        let max_buffer_frames = MAX_BUFFER_SIZE / (channel_count * std::mem::size_of::<f32>());
-        let ring_buffer_size = std::cmp::min(desired_buffer_frames, max_buffer_frames);
-        
-        info!("Creating ring buffer with {} samples ({:.2} MB)", 
-              ring_buffer_size,
-              (ring_buffer_size * std::mem::size_of::<f32>()) as f32 / (1024.0 * 1024.0));
-              
-        let ring_buffer = Arc::new(Mutex::new(AudioRingBuffer::new(ring_buffer_size)));
-        let ring_buffer_stream = Arc::clone(&ring_buffer);
-        
-        let needs_data = Arc::new(AtomicBool::new(true));
-        let needs_data_stream = Arc::clone(&needs_data);
-        
-        // Debug the buffer
-        if let Ok(rb) = ring_buffer.lock() {
-            info!("Ring buffer initialized: capacity={}, available={}",
-                  rb.capacity(), rb.available());
+
+        // A stream error or a callback that stopped firing means the device went away
+        let stream_failure = audio_stream.as_ref().and_then(|_| stream_health.failure(STREAM_STALL_TIMEOUT));
+        if let Some(reason) = stream_failure {
+            warn!("Output stream failed: {}", reason);
+            audio_stream = None;
+            device_retry_at = Some(Instant::now());
+            let lost_device = state_arc.lock().ok().and_then(|state| state.output_device.clone());
+            push_device_event(&state_arc, format!("Lost output device {}",
+                                                  lost_device.unwrap_or_else(|| "(unknown)".to_string())));
         }
-    
-        // Set up audio output stream
-        info!("Building audio output stream...");
-        let stream_result = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _info| {
-                // This callback is run by the audio system when it needs more samples
-                let start_time = std::time::Instant::now();
-                
-                // Read from our ring buffer with additional safety
-                let mut samples_read = 0;
-                if let Ok(mut rb) = ring_buffer_stream.lock() {
-                    // Only read what fits in the output buffer
-                    samples_read = rb.read(data);
-                    
-                    // Explicitly fill the rest with silence to avoid using uninitialized memory
-                    if samples_read < data.len() {
-                        for s in &mut data[samples_read..] {
-                            *s = 0.0;
+
+        // Reopen the stream on the selected device, or whatever is available now
+        if device_retry_at.is_some_and(|at| Instant::now() >= at) {
+            let reopened = open_output(&options, input.sample_rate, input.channel_count)
+                .and_then(|target| {
+                    let health = StreamHealth::new();
+                    let new_format = target.format();
+
+                    // Keep the buffered audio if the format matches and the dead stream let go of it,
+                    // otherwise start over with a fresh buffer
+                    let reused = if new_format == (output_sample_rate, output_channels) {
+                        ring_buffer.consumer()
+                    } else {
+                        None
+                    };
+                    let (consumer, new_ring_buffer) = match reused {
+                        Some(consumer) => (consumer, None),
+                        None => {
+                            let fresh = AudioRingBuffer::new(ring_buffer_size_for(new_format.0, new_format.1));
+                            let consumer = fresh.consumer()
+                                .ok_or_else(|| anyhow!("Ring buffer already has a reader"))?;
+                            (consumer, Some(fresh))
+                        }
+                    };
+
+                    let stream = target.start(
+                        consumer,
+                        Arc::clone(&needs_data),
+                        Arc::clone(&health),
+                    )?;
+                    Ok((stream, health, new_format, new_ring_buffer))
+                });
+
+            match reopened {
+                Ok((stream, health, new_format, new_ring_buffer)) => {
+                    if let Some(new_ring_buffer) = new_ring_buffer {
+                        info!("Output is now {} Hz, {} channels, restarting decode at the played position",
+                              new_format.0, new_format.1);
+
+                        // Everything still buffered was never heard, so go back to where the old stream stopped.
+                        // With a boundary still pending nothing of the current track was heard yet.
+                        let heard_frames = if let Some((_, finished_track)) = pending_boundary.take() {
+                            announce_track_change(&state_arc, &playback_position, &input, finished_track, 0);
+                            0
+                        } else {
+                            playhead.played_frame(ring_buffer.total_read())
+                        };
+                        let heard_secs = heard_frames as f64 / input.sample_rate.max(1) as f64;
+
+                        output_sample_rate = new_format.0;
+                        output_channels = new_format.1;
+                        ring_buffer = new_ring_buffer;
+
+                        // Anything opened for the old format has the wrong resampler
+                        crossfade = None;
+                        preopened = None;
+                        preopen_attempted = false;
+                        crossfade_checked = false;
+
+                        input.init_resampler(output_sample_rate, output_channels)?;
+                        if let Ok(new_frames) = input.seek(heard_secs) {
+                            current_frames = new_frames;
+                            if let Ok(pos) = playback_position.lock() {
+                                pos.set_current_frame(current_frames as usize);
+                            }
+                        }
+                        playhead.set_output_format(output_sample_rate, output_channels);
+                        playhead.restart(0, current_frames, input.sample_rate);
+                        stretcher.configure(output_sample_rate, output_channels);
+                        loop_start = None;
+
+                        if let Ok(mut chain) = options.dsp.lock() {
+                            chain.configure(output_sample_rate, output_channels);
+                            chain.reset();
                         }
+                        needs_data.store(true, Ordering::Release);
                     }
-                    
-                    // Signal that we need more data if the buffer is getting low
-                    if rb.available() < ring_buffer_size / 4 {
-                        needs_data_stream.store(true, Ordering::Release);
+
+                    let output_name = stream.name().to_string();
+                    audio_stream = Some(stream);
+                    stream_health = health;
+                    device_retry_at = None;
+
+                    if let Ok(mut state) = state_arc.lock() {
+                        state.output_device = Some(output_name.clone());
+                        state.device_lost = false;
                     }
-                } else {
-                    // On error, ensure the entire buffer is zeroed
-                    for s in data.iter_mut() {
-                        *s = 0.0;
+                    push_device_event(&state_arc, format!("Playing on {}", output_name));
+
+                    // Only resume if we were the ones who paused
+                    if paused_for_device {
+                        paused_for_device = false;
+                        pause_flag.store(false, Ordering::SeqCst);
+                        if let Ok(mut state) = state_arc.lock() {
+                            state.status = PlaybackStatus::Playing;
+                        }
                     }
-                    error!("Failed to lock ring buffer in audio callback");
                 }
-                
-                // Log details to help diagnose problems
-                if samples_read == 0 {
-                    debug!("Audio callback: no samples available - outputting silence");
+                Err(e) => {
+                    debug!("No output device available yet: {}", e);
+                    device_retry_at = Some(Instant::now() + DEVICE_RETRY_INTERVAL);
+
+                    let already_lost = state_arc.lock().map(|state| state.device_lost).unwrap_or(false);
+                    if !already_lost {
+                        if let Ok(mut state) = state_arc.lock() {
+                            state.device_lost = true;
+                        }
+                        push_device_event(&state_arc, "No output device available, playback paused".to_string());
+                    }
+
+                    // Hold the position until a device comes back
+                    if !pause_flag.load(Ordering::SeqCst) {
+                        paused_for_device = true;
+                        pause_flag.store(true, Ordering::SeqCst);
+                        if let Ok(mut state) = state_arc.lock() {
+                            state.status = PlaybackStatus::Paused;
+                        }
+                    }
                 }
-                
-                // Periodically log statistics about the audio callback
-                static LAST_LOG: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs();
-                    
-                let last = LAST_LOG.load(std::sync::atomic::Ordering::Relaxed);
-                if now > last + 5 {
-                    LAST_LOG.store(now, std::sync::atomic::Ordering::Relaxed);
-                    
-                    // Calculate callback duration
-                    let elapsed = start_time.elapsed();
-                    
-                    // Count non-zero samples as audio presence check
-                    let non_zero_samples = data.iter().filter(|&&s| s.abs() > 0.001).count();
-                    
-                    debug!(
-                        "Audio callback: {} samples ({}ms), read {}, non-zero: {} ({}%)",
-                        data.len(),
-                        elapsed.as_micros() as f64 / 1000.0,
-                        samples_read,
-                        non_zero_samples,
-                        non_zero_samples as f64 * 100.0 / data.len() as f64
-                    );
+            }
+        }
+
+        // Handle seek requests
+        let mut seek_requested = false;
+        let mut seek_target = SeekTarget::Fraction(0.0);
+
+        if let Ok(pos_lock) = playback_position.lock() {
+            if let Some(req_flag) = &pos_lock.seek_requested {
+                if req_flag.swap(false, Ordering::SeqCst) {
+                    seek_requested = true;
+                    if let Some(tgt) = &pos_lock.seek_target {
+                        if let Ok(tgt_val) = tgt.lock() {
+                            seek_target = *tgt_val;
+                        }
+                    }
                 }
-            },
-            |err| {
-                error!("Audio output error: {}", err);
-            },
-            None,
-        );
-        
-        let audio_stream = match stream_result {
-            Ok(stream) => stream,
-            Err(e) => {
-                error!("Failed to build audio stream: {}", e);
-                return Err(anyhow!("Failed to start audio: {}", e));
             }
+        }
+
+        if seek_requested {
+            info!("Seek requested to {:?}", seek_target);
+
+            let Some(target_secs) = input.seek_target_secs(seek_target).filter(|_| input.seekable) else {
+                warn!("Ignoring seek, {} can't be seeked or has no known length", input.path);
+                continue;
+            };
+
+            // Seeking away from the tail abandons a running crossfade
+            if crossfade.take().is_some() {
+                info!("Crossfade cancelled by seek");
+                preopen_attempted = false;
+            }
+            crossfade_checked = false;
+
+            if let Ok(new_frames) = input.seek(target_secs) {
+                // Update current position
+                current_frames = new_frames;
+
+                // A pending boundary is completed by the buffer clear below and sets the position itself
+                if pending_boundary.is_none() {
+                    if let Ok(pos) = playback_position.lock() {
+                        pos.set_current_frame(current_frames as usize);
+                    }
+                }
+            }
+
+            // Clear the ring buffer
+            debug!("Clearing ring buffer during seek");
+            ring_buffer.clear();
+            playhead.restart(ring_buffer.total_written(), current_frames, input.sample_rate);
+            stretcher.reset();
+
+            // Filter history from before the seek would smear into the new position
+            if let Ok(mut chain) = options.dsp.lock() {
+                chain.reset();
+            }
+
+            needs_data.store(true, Ordering::Release);
+            continue;
+        }
+
+        // Handle pause state (after seeks, so a paused track can still be moved)
+        if pause_flag.load(Ordering::SeqCst) {
+            ring_buffer.set_idle();
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+
+        // Nothing would play what we decode, wait for the stream to come back
+        if audio_stream.is_none() {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+
+        if !needs_data.load(Ordering::Acquire) {
+            // If we don't need data yet, sleep briefly
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        // A loop only applies to the track it was set on
+        let active_loop = options.ab_loop.lock()
+            .ok()
+            .and_then(|l| l.clone())
+            .filter(|region| region.path == input.path && region.end > region.start);
+        match active_loop.as_ref() {
+            Some(region) if loop_start.as_ref().map(|l| &l.region) != Some(region) => {
+                loop_start = Some(LoopStart::prepare(region.clone(), output_sample_rate, output_channels, &options));
+            }
+            None => loop_start = None,
+            _ => {}
+        }
+
+        let crossfade_settings = options.crossfade.lock()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        let remaining_secs = input.remaining_secs(current_frames);
+
+        // Open the queued track ahead of time so switching to it is seamless
+        let preopen_secs = if crossfade_settings.enabled {
+            GAPLESS_PREOPEN_SECS.max(crossfade_settings.duration_secs as f64 + 1.0)
+        } else {
+            GAPLESS_PREOPEN_SECS
         };
-        
-        match audio_stream.play() {
-            Ok(_) => info!("Started audio playback stream"),
-            Err(e) => {
-                error!("Failed to start audio stream: {}", e);
-                return Err(anyhow!("Failed to start audio: {}", e));
+        if preopened.is_none() && crossfade.is_none() && !preopen_attempted
+            && remaining_secs <= preopen_secs
+        {
+            preopen_attempted = true;
+            let queued = options.next_track.lock().ok().and_then(|next| next.clone());
+            if let Some(next_path) = queued {
+                preopened = open_next_input(&next_path, output_sample_rate, output_channels, &options);
             }
         }
-        
-        // Create SwrContext for resampling
-        let mut swr_ctx: *mut ffmpeg::SwrContext = ffmpeg::swr_alloc();
-        
-        // Create a new channel layout structure for input
-        let mut in_ch_layout = std::mem::zeroed::<ffmpeg::AVChannelLayout>();
-        ffmpeg::av_channel_layout_default(&mut in_ch_layout, channel_count as c_int);
-        
-        // Create a new channel layout structure for output
-        let mut out_ch_layout = std::mem::zeroed::<ffmpeg::AVChannelLayout>();
-        ffmpeg::av_channel_layout_default(&mut out_ch_layout, channel_count as c_int);
-        
-        // Set up resampler with new API
-        if swr_ctx.is_null() {
-            error!("Failed to allocate resampler context");
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Failed to allocate resampler context"));
+
+        // Start fading in the next track once the current one is within the fade length
+        if crossfade_settings.enabled && !crossfade_checked && crossfade.is_none() && active_loop.is_none()
+            && crossfade_settings.duration_secs > 0.0
+            && remaining_secs <= crossfade_settings.duration_secs as f64
+        {
+            if let Some(ready) = preopened.take() {
+                crossfade_checked = true;
+                let still_queued = options.next_track.lock()
+                    .map(|next| next.as_deref() == Some(ready.path.as_str()))
+                    .unwrap_or(false);
+
+                if !still_queued {
+                    // The queue changed, the EOF switch will open the right track
+                    debug!("Queued track changed, dropping pre-opened {}", ready.path);
+                } else if input.same_album_as(&ready) {
+                    info!("Skipping crossfade between tracks of the same album");
+                    preopened = Some(ready);
+                } else {
+                    info!("Starting {:.1}s {:?} crossfade into {}",
+                          crossfade_settings.duration_secs.min(remaining_secs as f32), crossfade_settings.curve, ready.path);
+                    crossfade = Some(ActiveCrossfade::new(ready, crossfade_settings, output_sample_rate, remaining_secs));
+                }
+            }
         }
-        
-        // Fix the swr_alloc_set_opts2 function call with proper handling
-        let swr_result = ffmpeg::swr_alloc_set_opts2(
-            &mut swr_ctx,
-            &out_ch_layout,
-            AV_SAMPLE_FMT_FLT,
-            output_sample_rate as i32,
-            &in_ch_layout,
-            (*codec_ctx).sample_fmt,
-            sample_rate as i32,
-            0,
-            std::ptr::null_mut(),
-        );
-
-        if swr_result < 0 {
-            let error_buf = [0i8; 1024];
-            ffmpeg::av_strerror(swr_result, error_buf.as_ptr() as *mut i8, 1024);
-            let error_msg = to_string(error_buf.as_ptr());
-            error!("Failed to set SwrContext options: {} ({})", error_msg, swr_result);
-            ffmpeg::swr_free(&mut swr_ctx);
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Failed to set SwrContext options: {}", error_msg));
+
+        let normalization = options.normalization.lock()
+            .map(|n| n.clone())
+            .unwrap_or_default();
+        let speed = options.speed.lock().map(|s| *s).unwrap_or(1.0);
+        stretcher.set_speed(speed);
+        playhead.set_speed(stretcher.speed());
+        let source_frames_per_frame = input.sample_rate as f64 / output_sample_rate.max(1) as f64;
+
+        samples.clear();
+        let output_frame = input.output_position();
+        let frames_decoded = input.decode_next(&mut samples);
+        apply_gain(&mut samples, input.gain_factor(&normalization));
+
+        if let Some(fade) = crossfade.as_mut() {
+            let incoming_gain = fade.input.gain_factor(&normalization);
+            fade.mix_into(&mut samples, incoming_gain);
         }
-        
-        // Initialize the resampler
-        let swr_init_result = ffmpeg::swr_init(swr_ctx);
-        if swr_init_result < 0 {
-            let error_buf = [0i8; 1024];
-            ffmpeg::av_strerror(swr_init_result, error_buf.as_ptr() as *mut i8, 1024);
-            let error_msg = to_string(error_buf.as_ptr());
-            error!("Failed to initialize resampler: {} ({})", error_msg, swr_init_result);
-            ffmpeg::swr_free(&mut swr_ctx);
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Failed to initialize resampler: {}", error_msg));
+
+        // Cut the block at the loop's out point, the jump back happens once it is written
+        let mut loop_jump = false;
+        if let Some(region) = active_loop.as_ref() {
+            let end_frame = region.end_frame(input.sample_rate);
+            let block_frames = input.output_to_source_frames(samples.len() / output_channels.max(1), output_sample_rate);
+            if output_frame < end_frame && (output_frame + block_frames >= end_frame || input.is_eof()) {
+                let keep_frames = ((end_frame - output_frame) as f64 / source_frames_per_frame) as usize;
+                samples.truncate((keep_frames * output_channels).min(samples.len()));
+                loop_jump = true;
+            }
         }
-        
-        // Allocate packet and frame
-        let packet = ffmpeg::av_packet_alloc();
-        if packet.is_null() {
-            error!("Failed to allocate packet");
-            ffmpeg::swr_free(&mut swr_ctx);
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Failed to allocate packet"));
+
+        let output_frame = stretch_block(&mut stretcher, &mut samples, &mut stretched,
+                                         output_frame, source_frames_per_frame, false);
+
+        if !samples.is_empty() {
+            options.process_dsp(&mut samples);
+            playhead.mark(ring_buffer.total_written(), output_frame, input.sample_rate);
+            write_to_ring_buffer(&mut samples, &volume_arc, &mut ring_buffer, &needs_data,
+                                 &stop_flag, &stream_health);
         }
-        
-        let frame = ffmpeg::av_frame_alloc();
-        if frame.is_null() {
-            error!("Failed to allocate frame");
-            ffmpeg::av_packet_free(&mut (packet as *mut _));
-            ffmpeg::swr_free(&mut swr_ctx);
-            ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-            ffmpeg::avformat_close_input(&mut format_ctx);
-            return Err(anyhow!("Failed to allocate frame"));
+
+        // Decode position, from packet timestamps. The reported position follows the playhead.
+        if frames_decoded > 0 {
+            current_frames = input.position();
         }
-        
-        info!("Beginning decode loop for file: {}", path);
-        
-        // Main decoding loop
-        let mut current_frames: u64 = 0;
-        let mut is_eof = false;
-        let mut last_progress_log = std::time::Instant::now();
-        let mut last_buffer_warn = std::time::Instant::now();
-        
-        while !is_eof && !stop_flag.load(Ordering::SeqCst) {
-            // Handle pause state
-            if pause_flag.load(Ordering::SeqCst) {
-                thread::sleep(Duration::from_millis(10));
-                continue;
+
+        if let (true, Some(region)) = (loop_jump, active_loop.as_ref()) {
+            info!("Loop end reached at {:.2}s, back to {:.2}s", region.end.as_secs_f64(), region.start.as_secs_f64());
+
+            if crossfade.take().is_some() {
+                info!("Crossfade cancelled by loop");
             }
-            
-            // Handle seek requests
-            let mut seek_requested = false;
-            let mut target_fraction = 0.0;
-            
-            if let Ok(pos_lock) = playback_position.lock() {
-                if let Some(req_flag) = &pos_lock.seek_requested {
-                    if req_flag.swap(false, Ordering::SeqCst) {
-                        seek_requested = true;
-                        if let Some(tgt) = &pos_lock.seek_target {
-                            if let Ok(tgt_val) = tgt.lock() {
-                                target_fraction = *tgt_val;
-                            }
-                        }
+            preopen_attempted = false;
+            crossfade_checked = false;
+
+            match loop_start.take().and_then(|prepared| prepared.input) {
+                Some(prepared) => input = prepared,
+                None => {
+                    // Nothing prepared, seek instead. The ring buffer covers the time it takes.
+                    if let Err(e) = input.seek(region.start.as_secs_f64()) {
+                        warn!("Could not jump back to the loop start: {}", e);
                     }
                 }
             }
-    
-            if seek_requested {
-                info!("Seek requested to position {:.4}", target_fraction);
-                
-                // Calculate seek position in seconds and convert to stream timebase
-                let target_time_seconds = target_fraction * track_duration_secs as f32;
-                let timestamp = (target_time_seconds * (*stream).time_base.den as f32 / 
-                                (*stream).time_base.num as f32) as i64;
-                
-                // Flush buffers
-                ffmpeg::avcodec_flush_buffers(codec_ctx);
-                
-                // Perform seek
-                let seek_flags = ffmpeg::AVSEEK_FLAG_BACKWARD;
-                let ret = ffmpeg::av_seek_frame(
-                    format_ctx, 
-                    audio_stream_idx,
-                    timestamp,
-                    seek_flags
-                );
-                
-                if ret < 0 {
-                    let error_buf = [0i8; 1024];
-                    ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-                    let error_msg = to_string(error_buf.as_ptr());
-                    warn!("Seeking failed: {} ({})", error_msg, ret);
-                } else {
-                    info!("Seek successful");
-                    
-                    // Update current position
-                    let new_frames = (target_fraction * total_samples as f32 / channel_count as f32) as u64;
-                    current_frames = new_frames;
-                    
-                    if let Ok(pos) = playback_position.lock() {
-                        let frame_pos = current_frames as usize;
-                        pos.set_current_frame(frame_pos);
-                    }
-                }
-                
-                // Clear the ring buffer
-                if let Ok(mut rb) = ring_buffer.lock() {
-                    debug!("Clearing ring buffer during seek");
-                    rb.clear();
+            current_frames = input.position();
+            continue;
+        }
+
+        if input.is_eof() {
+            // The end of the track may still be held by the stretcher
+            if !stretcher.is_bypassed() {
+                samples.clear();
+                let output_frame = stretch_block(&mut stretcher, &mut samples, &mut stretched,
+                                                 input.output_position(), source_frames_per_frame, true);
+                if !samples.is_empty() {
+                    options.process_dsp(&mut samples);
+                    playhead.mark(ring_buffer.total_written(), output_frame, input.sample_rate);
+                    write_to_ring_buffer(&mut samples, &volume_arc, &mut ring_buffer, &needs_data,
+                                         &stop_flag, &stream_health);
                 }
-                
-                needs_data.store(true, Ordering::Release);
-                continue;
             }
-    
-            if !needs_data.load(Ordering::Acquire) {
-                // If we don't need data yet, sleep briefly
-                thread::sleep(Duration::from_millis(1));
-                continue;
+
+            // Continue straight into the queued track on the same output stream
+            let queued = options.next_track.lock().ok().and_then(|mut next| next.take());
+
+            // A crossfade already started the next track, keep what it decoded ahead
+            let mut carried_over: Vec<f32> = Vec::new();
+            let mut next_frames: u64 = 0;
+            let next_input = if let Some(fade) = crossfade.take() {
+                carried_over = fade.pending;
+                next_frames = fade.frames_decoded;
+                Some(fade.input)
+            } else {
+                match (queued, preopened.take()) {
+                    (Some(next_path), Some(ready)) if ready.path == next_path => Some(ready),
+                    (Some(next_path), _) => open_next_input(&next_path, output_sample_rate, output_channels, &options),
+                    (None, _) => None,
+                }
+            };
+
+            let Some(next_input) = next_input else {
+                break;
+            };
+
+            info!("Gapless transition: {} -> {}", input.path, next_input.path);
+
+            // A very short track can end before the previous boundary was played
+            if let Some((_, finished_track)) = pending_boundary.take() {
+                announce_track_change(&state_arc, &playback_position, &input, finished_track, 0);
             }
-            
-            // Read packet
-            let ret = ffmpeg::av_read_frame(format_ctx, packet);
-            if ret < 0 {
-                if ret == ffmpeg::AVERROR_EOF || ret == ffmpeg::AVERROR(libc::EAGAIN) {
-                    info!("End of file reached");
-                    is_eof = true;
-                    
-                    // Signal track completion using our track_completed_flag
-                    track_completed_flag.store(true, Ordering::SeqCst);
-                    
-                    // Update player state
-                    if let Ok(mut state) = state_arc.lock() {
-                        state.track_completed = true;
-                        state.status = PlaybackStatus::Stopped; // Set to stopped on completion
-                    }
-                } else {
-                    let error_buf = [0i8; 1024];
-                    ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-                    let error_msg = to_string(error_buf.as_ptr());
-                    warn!("Error reading frame: {} ({})", error_msg, ret);
-                    
-                    // Update player state on error
-                    if let Ok(mut state) = state_arc.lock() {
-                        state.status = PlaybackStatus::Stopped;
-                    }
+
+            let boundary_mark = ring_buffer.total_read() + ring_buffer.available() as u64;
+            let finished = std::mem::replace(&mut input, next_input);
+            pending_boundary = Some((boundary_mark, finished.path.clone()));
+
+            if !carried_over.is_empty() {
+                apply_gain(&mut carried_over, input.gain_factor(&normalization));
+                let carried_frames = input.output_to_source_frames(carried_over.len() / output_channels.max(1), output_sample_rate);
+                let carried_start = input.output_position().saturating_sub(carried_frames);
+                let source_frames_per_frame = input.sample_rate as f64 / output_sample_rate.max(1) as f64;
+                let carried_start = stretch_block(&mut stretcher, &mut carried_over, &mut stretched,
+                                                  carried_start, source_frames_per_frame, false);
+                if !carried_over.is_empty() {
+                    options.process_dsp(&mut carried_over);
+                    playhead.mark(ring_buffer.total_written(), carried_start, input.sample_rate);
+                    write_to_ring_buffer(&mut carried_over, &volume_arc, &mut ring_buffer, &needs_data,
+                                         &stop_flag, &stream_health);
                 }
-                continue;
             }
-            
-            // Skip non-audio packets
-            if (*packet).stream_index != audio_stream_idx {
-                ffmpeg::av_packet_unref(packet);
-                continue;
+
+            current_frames = next_frames;
+            preopen_attempted = false;
+            crossfade_checked = false;
+            continue;
+        }
+
+        // Periodically log progress
+        if last_progress_log.elapsed() >= Duration::from_secs(1) {
+            let cur_seconds = current_frames as f64 / input.sample_rate as f64;
+            match input.duration_secs {
+                Some(duration) => debug!("Playback progress: {:.1}s / {:.1}s ({:.1}%)",
+                                         cur_seconds, duration, (cur_seconds / duration) * 100.0),
+                None => debug!("Playback progress: {:.1}s (unknown length)", cur_seconds),
             }
-            
-            // Send packet to decoder
-            let ret = ffmpeg::avcodec_send_packet(codec_ctx, packet);
-            ffmpeg::av_packet_unref(packet);
-            
-            if ret < 0 {
-                let error_buf = [0i8; 1024];
-                ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-                let error_msg = to_string(error_buf.as_ptr());
-                warn!("Error sending packet to decoder: {} ({})", error_msg, ret);
-                continue;
+
+            // Check ring buffer stats
+            let stats = ring_buffer.stats();
+            debug!("Ring buffer: {}/{} samples available ({:.1}%), {} underruns ({} samples)",
+                   ring_buffer.available(),
+                   ring_buffer.capacity(),
+                   ring_buffer.available() as f64 * 100.0 / ring_buffer.capacity() as f64,
+                   stats.underruns,
+                   stats.underrun_samples);
+            if let Ok(mut state) = state_arc.lock() {
+                state.buffer_underruns = stats.underruns;
             }
-            
-            // Process decoded frames
-            let mut got_frame = false;
-            
-            // Limit the number of frames processed per iteration to avoid stack overflow
-            let max_frames_per_packet = 4; // Lower this value for safer processing
-            let mut frames_processed = 0;
-            
-            while !got_frame && frames_processed < max_frames_per_packet {
-                frames_processed += 1;
-                
-                let ret = ffmpeg::avcodec_receive_frame(codec_ctx, frame);
-                
-                if ret == ffmpeg::AVERROR(libc::EAGAIN) || ret == ffmpeg::AVERROR_EOF {
-                    break;
-                } else if ret < 0 {
-                    let error_buf = [0i8; 1024];
-                    ffmpeg::av_strerror(ret, error_buf.as_ptr() as *mut i8, 1024);
-                    let error_msg = to_string(error_buf.as_ptr());
-                    warn!("Error receiving frame from decoder: {} ({})", error_msg, ret);
-                    break;
-                }
-                
-                got_frame = true;
-                
-                // Process the audio frame with enhanced safety
-                let frames_decoded = process_audio_frame_safe(
-                    frame,
-                    swr_ctx, 
-                    channel_count,
-                    output_sample_rate,
-                    sample_rate,
-                    volume_arc.clone(),
-                    ring_buffer.clone(),
-                    &needs_data,
-                    &mut last_buffer_warn
-                );
-                
-                // Update position
-                if frames_decoded > 0 {
-                    current_frames += frames_decoded;
-                    if let Ok(pos) = playback_position.lock() {
-                        pos.update_current_sample(frames_decoded as usize);
+
+            last_progress_log = std::time::Instant::now();
+        }
+
+        // Sleep a tiny bit to avoid busy-waiting and reduce CPU usage
+        thread::sleep(Duration::from_micros(100));
+    }
+
+    if input.is_eof() && !stop_flag.load(Ordering::SeqCst) {
+        // Let the device play out what is still buffered before the stream is dropped
+        ring_buffer.set_idle();
+        while !stop_flag.load(Ordering::SeqCst) {
+            if let Some((boundary_mark, _)) = &pending_boundary {
+                if ring_buffer.total_read() >= *boundary_mark {
+                    if let Some((_, finished_track)) = pending_boundary.take() {
+                        let played_frame = playhead.played_frame(ring_buffer.total_read());
+                        announce_track_change(&state_arc, &playback_position, &input, finished_track, played_frame);
                     }
                 }
-                
-                ffmpeg::av_frame_unref(frame);
             }
-            
-            // Periodically log progress
-            if last_progress_log.elapsed() >= Duration::from_secs(1) {
-                let cur_seconds = current_frames as f64 / sample_rate as f64;
-                debug!("Playback progress: {:.1}s / {:.1}s ({:.1}%)",
-                       cur_seconds,
-                       track_duration_secs,
-                       (cur_seconds / track_duration_secs) * 100.0);
-                       
-                // Check ring buffer stats
-                if let Ok(rb) = ring_buffer.lock() {
-                    debug!("Ring buffer: {}/{} samples available ({:.1}%)",
-                           rb.available(),
-                           rb.capacity(),
-                           rb.available() as f64 * 100.0 / rb.capacity() as f64);
-                }
-                       
-                last_progress_log = std::time::Instant::now();
+
+            let played_frame = playhead.played_frame(ring_buffer.total_read());
+            if let Ok(pos) = playback_position.lock() {
+                pos.update_played_frame(played_frame);
             }
-            
-            // Sleep a tiny bit to avoid busy-waiting and reduce CPU usage
-            thread::sleep(Duration::from_micros(100));
+
+            let buffered = ring_buffer.available();
+            // A dead stream will never drain the buffer
+            if buffered == 0 || stream_health.failure(STREAM_STALL_TIMEOUT).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Update player state
+        if let Ok(mut state) = state_arc.lock() {
+            state.track_completed = true;
+            state.status = PlaybackStatus::Stopped; // Set to stopped on completion
         }
-        
-        // Cleanup
-        info!("Playback complete, cleaning up resources");
-        ffmpeg::av_frame_free(&mut (frame as *mut _));
-        ffmpeg::av_packet_free(&mut (packet as *mut _));
-        ffmpeg::swr_free(&mut swr_ctx);
-        ffmpeg::avcodec_free_context(&mut (codec_ctx as *mut _));
-        ffmpeg::avformat_close_input(&mut format_ctx);
     }
-    
+
+    // Cleanup
+    info!("Playback complete, cleaning up resources");
+    drop(audio_stream);
+    drop(crossfade);
+    drop(preopened);
+    drop(input);
+
     Ok(())
 }
 
@@ -964,15 +1295,16 @@ pub fn play_audio_file_enhanced(
     state_arc: Arc<Mutex<PlayerState>>,
     playback_position: Arc<Mutex<PlaybackPosition>>,
     volume_arc: Arc<Mutex<f32>>,
-    prefetch_mode: bool,  
-    buffer_size: Option<usize>,  
+    options: PlaybackOptions,
+    prefetch_mode: bool,
+    buffer_size: Option<usize>,
 ) -> Result<()> {
-    info!("Playing audio file with enhanced mode - prefetch={}, buffer_size={:?}", 
+    info!("Playing audio file with enhanced mode - prefetch={}, buffer_size={:?}",
           prefetch_mode, buffer_size);
-          
+
     // For network paths, we'll apply special handling
     let is_network = is_network_path(path);
-    
+
     if is_network {
         info!("Using network-optimized playback settings for {}", path);
         // Adjust timeout and buffer settings in playerState if needed
@@ -981,9 +1313,9 @@ pub fn play_audio_file_enhanced(
             state.buffer_progress = 0.2; // Start with some initial progress
         }
     }
-    
+
     // All files now use the same implementation with internal optimizations
-    play_audio_file(path, pause_flag, stop_flag, state_arc, playback_position, volume_arc)
+    play_audio_file(path, pause_flag, stop_flag, state_arc, playback_position, volume_arc, options)
 }
 
 // Helper function to scan directories with depth limit
@@ -1,15 +1,312 @@
 // core/src/audio/device.rs
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}, Mutex};
+use std::time::{Duration, Instant};
 use anyhow::{Result, anyhow};
-use log::{info, error};
+use log::{info, warn, error};
+use serde::{Serialize, Deserialize};
 // We need StreamTrait for the stream.play() method
-use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 
-use crate::audio::buffer::AudioRingBuffer;
+use crate::audio::buffer::{AudioRingBuffer, RingConsumer};
 use crate::audio::position::PlaybackPosition;
 
+/// Identifies an output device across restarts. cpal has no stable ids, so host + name it is.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutputDeviceId {
+    pub host: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputConfigInfo {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputDeviceInfo {
+    pub id: OutputDeviceId,
+    pub is_default: bool,
+    pub configs: Vec<OutputConfigInfo>,
+}
+
+/// Names of the audio hosts available on this platform (WASAPI, ASIO, ALSA, ...)
+pub fn list_hosts() -> Vec<String> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name().to_string())
+        .collect()
+}
+
+fn device_configs(device: &cpal::Device) -> Vec<OutputConfigInfo> {
+    match device.supported_output_configs() {
+        Ok(configs) => configs
+            .map(|c| OutputConfigInfo {
+                channels: c.channels(),
+                min_sample_rate: c.min_sample_rate().0,
+                max_sample_rate: c.max_sample_rate().0,
+                sample_format: format!("{:?}", c.sample_format()),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Error getting supported configs: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Every output device on every host, with its supported configs
+pub fn list_output_devices() -> Vec<OutputDeviceInfo> {
+    let mut result = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                warn!("Audio host {} unavailable: {}", host_id.name(), e);
+                continue;
+            }
+        };
+
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("Error getting output devices for {}: {}", host_id.name(), e);
+                continue;
+            }
+        };
+
+        for device in devices {
+            let name = device.name().unwrap_or_else(|_| "Unknown".into());
+            result.push(OutputDeviceInfo {
+                is_default: default_name.as_deref() == Some(name.as_str()),
+                id: OutputDeviceId { host: host_id.name().to_string(), name },
+                configs: device_configs(&device),
+            });
+        }
+    }
+
+    result
+}
+
+fn default_output_device() -> Result<(cpal::Device, OutputDeviceId)> {
+    let host = cpal::default_host();
+    info!("Audio host: {}", host.id().name());
+
+    match host.default_output_device() {
+        Some(device) => {
+            let name = device.name().unwrap_or_else(|_| String::from("Unknown"));
+            info!("Using output device: {}", name);
+            Ok((device, OutputDeviceId { host: host.id().name().to_string(), name }))
+        },
+        None => {
+            error!("No output audio device available!");
+            Err(anyhow!("No output audio device available"))
+        }
+    }
+}
+
+/// Open the requested device, falling back to the default one when it is gone.
+/// Returns the device actually used.
+pub fn find_output_device(requested: Option<&OutputDeviceId>) -> Result<(cpal::Device, OutputDeviceId)> {
+    let Some(requested) = requested else {
+        return default_output_device();
+    };
+
+    let host_id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name() == requested.host);
+
+    let device = host_id
+        .and_then(|id| cpal::host_from_id(id).ok())
+        .and_then(|host| host.output_devices().ok())
+        .and_then(|mut devices| devices.find(|d| d.name().map(|n| n == requested.name).unwrap_or(false)));
+
+    match device {
+        Some(device) => {
+            info!("Using output device: {} ({})", requested.name, requested.host);
+            Ok((device, requested.clone()))
+        },
+        None => {
+            warn!("Output device '{}' ({}) not found, falling back to default", requested.name, requested.host);
+            default_output_device()
+        }
+    }
+}
+
+/// Pick a stream config that fits the file: enough channels, preferably the file's own rate
+pub fn select_output_config(
+    device: &cpal::Device,
+    sample_rate: u32,
+    channel_count: usize,
+) -> Result<cpal::SupportedStreamConfig> {
+    let mut config_range = device
+        .supported_output_configs()
+        .map_err(|e| {
+            error!("Failed to get device configs: {}", e);
+            anyhow!("Failed to get device configs: {}", e)
+        })?
+        .filter(|c| c.channels() >= channel_count as u16)
+        .collect::<Vec<_>>();
+
+    if config_range.is_empty() {
+        error!("No suitable output config found for device (needed {} channels)", channel_count);
+        return Err(anyhow!("No suitable output config found for device"));
+    }
+
+    config_range.sort_by_key(|c| c.min_sample_rate().0);
+    let desired_sample_rates = [sample_rate, 48000, 44100, 96000, 192000];
+
+    // Try to find a config that supports our desired sample rates
+    for &rate in &desired_sample_rates {
+        for c in &config_range {
+            if rate >= c.min_sample_rate().0 && rate <= c.max_sample_rate().0 {
+                info!("Selected output config: {} channels, {} Hz",
+                      c.channels(), rate);
+                return Ok(c.with_sample_rate(cpal::SampleRate(rate)));
+            }
+        }
+    }
+
+    let config = &config_range[0];
+
+    // Choose a safe sample rate close to the original
+    let mut target_rate = sample_rate;
+
+    // If the file rate is extremely low or high, use a standard rate instead
+    if !(8000..=192000).contains(&sample_rate) {
+        target_rate = 44100; // Use a standard rate
+        warn!("File has unusual sample rate ({}Hz). Using standard 44.1kHz output instead.",
+             sample_rate);
+    }
+
+    let sample_rate = if target_rate <= config.min_sample_rate().0 {
+        config.min_sample_rate().0
+    } else if target_rate >= config.max_sample_rate().0 {
+        config.max_sample_rate().0
+    } else {
+        target_rate
+    };
+
+    info!("Using output sample rate: {} Hz", sample_rate);
+    Ok(config.with_sample_rate(cpal::SampleRate(sample_rate)))
+}
+
+/// Lets the decode loop notice a dead stream: errors reported by cpal,
+/// or a callback that simply stops being called (some backends never report removal)
+pub struct StreamHealth {
+    error: Mutex<Option<String>>,
+    created: Instant,
+    // Milliseconds since `created` of the last output callback
+    last_callback_ms: AtomicU64,
+}
+
+impl StreamHealth {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            error: Mutex::new(None),
+            created: Instant::now(),
+            last_callback_ms: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn mark_callback(&self) {
+        let now = self.created.elapsed().as_millis() as u64;
+        self.last_callback_ms.store(now, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report_error(&self, message: String) {
+        if let Ok(mut error) = self.error.lock() {
+            // Keep the first error, later ones are usually consequences
+            if error.is_none() {
+                *error = Some(message);
+            }
+        }
+    }
+
+    /// Why the stream should be considered dead, if it should
+    pub fn failure(&self, stall_timeout: Duration) -> Option<String> {
+        if let Ok(error) = self.error.lock() {
+            if let Some(message) = error.as_ref() {
+                return Some(message.clone());
+            }
+        }
+
+        let last = Duration::from_millis(self.last_callback_ms.load(Ordering::Relaxed));
+        let silent_for = self.created.elapsed().saturating_sub(last);
+        if silent_for > stall_timeout {
+            return Some(format!("Output stream stalled for {:.1}s", silent_for.as_secs_f32()));
+        }
+
+        None
+    }
+}
+
+/// Build and start an output stream that plays whatever is in the ring buffer.
+/// `needs_data` is raised when the buffer drops below a quarter full.
+/// The callback never locks or allocates; underruns are counted by the buffer.
+pub fn build_ring_buffer_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut consumer: RingConsumer,
+    needs_data: Arc<AtomicBool>,
+    health: Arc<StreamHealth>,
+) -> Result<cpal::Stream> {
+    let low_water_mark = consumer.capacity() / 4;
+    let health_callback = Arc::clone(&health);
+    let health_error = health;
+
+    // Set up audio output stream
+    info!("Building audio output stream...");
+    let stream_result = device.build_output_stream(
+        config,
+        move |data: &mut [f32], _info| {
+            // This callback is run by the audio system when it needs more samples
+            health_callback.mark_callback();
+
+            let samples_read = consumer.read(data);
+
+            // Explicitly fill the rest with silence to avoid using uninitialized memory
+            data[samples_read..].fill(0.0);
+
+            // Signal that we need more data if the buffer is getting low
+            if consumer.available() < low_water_mark {
+                needs_data.store(true, Ordering::Release);
+            }
+        },
+        move |err| {
+            error!("Audio output error: {}", err);
+            health_error.report_error(err.to_string());
+        },
+        None,
+    );
+
+    let stream = match stream_result {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("Failed to build audio stream: {}", e);
+            return Err(anyhow!("Failed to start audio: {}", e));
+        }
+    };
+
+    match stream.play() {
+        Ok(_) => info!("Started audio playback stream"),
+        Err(e) => {
+            error!("Failed to start audio stream: {}", e);
+            return Err(anyhow!("Failed to start audio: {}", e));
+        }
+    }
+
+    Ok(stream)
+}
+
 /// Sets up audio output device and creates a stream for playback
 pub fn setup_audio_device(
+    device_id: Option<&OutputDeviceId>,
     file_sample_rate: u32,
     channel_count: usize,
     _pause_flag: Arc<AtomicBool>,
@@ -17,18 +314,7 @@ pub fn setup_audio_device(
     _playback_position: Arc<Mutex<PlaybackPosition>>,
     _volume_arc: Arc<Mutex<f32>>
 ) -> Result<(u32, cpal::Stream)> {
-    let host = cpal::default_host();
-    info!("Using audio host: {}", host.id().name());
-    
-    let device = match host.default_output_device() {
-        Some(d) => d,
-        None => {
-            error!("No output audio device available");
-            return Err(anyhow!("No output audio device available"));
-        }
-    };
-    
-    info!("Using output device: {}", device.name().unwrap_or_else(|_| "Unknown".to_string()));
+    let (device, _) = find_output_device(device_id)?;
     
     let config_range = match device.supported_output_configs() {
         Ok(configs) => configs.filter(|c| c.channels() >= channel_count as u16).collect::<Vec<_>>(),
@@ -93,10 +379,12 @@ pub fn setup_audio_device(
     
     info!("Output device config: {:?}", config);
     
-    // Create a ring buffer for audio samples with explicit type annotation
-    let buffer_size = ((output_sample_rate as usize * channel_count as usize) / 10).max(1024);
-    let ring_buffer = Arc::new(Mutex::new(AudioRingBuffer::new(buffer_size * 4)));
-    let ring_buffer_stream: Arc<Mutex<AudioRingBuffer>> = Arc::clone(&ring_buffer);
+    // Create a ring buffer for audio samples
+    let buffer_size = ((output_sample_rate as usize * channel_count) / 10).max(1024);
+    let ring_buffer = AudioRingBuffer::new(buffer_size * 4);
+    let mut consumer = ring_buffer
+        .consumer()
+        .ok_or_else(|| anyhow!("Ring buffer already has a reader"))?;
     
     // Flag to signal when audio needs more data
     let needs_data = Arc::new(AtomicBool::new(true));
@@ -106,56 +394,14 @@ pub fn setup_audio_device(
     let stream = match device.build_output_stream(
         &config,
         move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            // This method returns a Vec of samples and the count as owned values
-            let get_samples = || -> (Vec<f32>, usize) {
-                // Try to get the ring buffer lock
-                if let Ok(mut buffer) = ring_buffer_stream.lock() {
-                    // Signal that we need more data if the buffer is low
-                    if buffer.available() < buffer_size / 2 {
-                        needs_data_stream.store(true, Ordering::Release);
-                    }
-                    
-                    // Prepare a buffer to hold samples
-                    let mut samples = vec![0.0; data.len()];
-                    // Read samples from the ring buffer into our local buffer
-                    let count = buffer.read(&mut samples);
-                    
-                    // Return owned values, not references
-                    (samples, count)
-                } else {
-                    // If we couldn't get the lock, return empty data
-                    (Vec::new(), 0)
-                }
-            };
-            
-            // Use catch_unwind ONLY around the function that returns owned values
-            let result = std::panic::catch_unwind(|| {
-                get_samples()
-            });
-            
-            // Process the result safely outside the unwind boundary
-            match result {
-                Ok((samples, count)) => {
-                    // Handle the results safely
-                    if count > 0 && count <= data.len() && count <= samples.len() {
-                        // Copy the samples to the output buffer
-                        data[..count].copy_from_slice(&samples[..count]);
-                    }
-                    
-                    // Fill the rest with silence
-                    if count < data.len() {
-                        for sample in &mut data[count..] {
-                            *sample = 0.0;
-                        }
-                    }
-                },
-                Err(_) => {
-                    // If there was a panic, fill with silence
-                    for sample in data.iter_mut() {
-                        *sample = 0.0;
-                    }
-                }
+            // Signal that we need more data if the buffer is low
+            if consumer.available() < buffer_size / 2 {
+                needs_data_stream.store(true, Ordering::Release);
             }
+            
+            // Read straight into the device buffer, then fill the rest with silence
+            let count = consumer.read(data);
+            data[count..].fill(0.0);
         },
         |err| { error!("Audio output error: {}", err); },
         None,
@@ -8,53 +8,42 @@ use std::fs::File;
 use std::io::Write;
 
 use crate::audio::buffer::AudioRingBuffer;
+use crate::audio::device::{list_hosts, list_output_devices};
 
 /// Logs detailed information about audio devices
 pub fn log_audio_devices() {
     info!("================ AUDIO DEVICE DIAGNOSTICS ================");
     
-    let host = cpal::default_host();
-    info!("Host: {}", host.id().name());
+    info!("Hosts: {}", list_hosts().join(", "));
     
-    // Check all available output devices
-    match host.output_devices() {
-        Ok(devices) => {
-            let devices: Vec<_> = devices.collect();
-            info!("Found {} output devices:", devices.len());
-            
-            for (i, device) in devices.iter().enumerate() {
-                let name = device.name().unwrap_or_else(|_| "Unknown".into());
-                info!("  Device {}: {}", i, name);
-                
-                // Log supported output configs
-                match device.supported_output_configs() {
-                    Ok(configs) => {
-                        let configs: Vec<_> = configs.collect();
-                        info!("    Supported configs: {}", configs.len());
-                        
-                        for (j, config) in configs.iter().enumerate() {
-                            info!("    Config {}: channels={}, min_rate={}Hz, max_rate={}Hz, sample_format={:?}",
-                                  j, 
-                                  config.channels(),
-                                  config.min_sample_rate().0,
-                                  config.max_sample_rate().0,
-                                  config.sample_format());
-                        }
-                    },
-                    Err(e) => error!("    Error getting supported configs: {}", e),
-                }
-            }
-            
-            // Check default output device
-            match host.default_output_device() {
-                Some(device) => {
-                    let name = device.name().unwrap_or_else(|_| "Unknown".into());
-                    info!("Default output device: {}", name);
-                },
-                None => error!("No default output device available!"),
-            }
+    // Check all available output devices on every host
+    let devices = list_output_devices();
+    info!("Found {} output devices:", devices.len());
+    
+    for (i, device) in devices.iter().enumerate() {
+        info!("  Device {}: {} ({}){}", i, device.id.name, device.id.host,
+              if device.is_default { " [default]" } else { "" });
+        
+        // Log supported output configs
+        info!("    Supported configs: {}", device.configs.len());
+        for (j, config) in device.configs.iter().enumerate() {
+            info!("    Config {}: channels={}, min_rate={}Hz, max_rate={}Hz, sample_format={}",
+                  j, 
+                  config.channels,
+                  config.min_sample_rate,
+                  config.max_sample_rate,
+                  config.sample_format);
+        }
+    }
+    
+    // Check default output device
+    let host = cpal::default_host();
+    match host.default_output_device() {
+        Some(device) => {
+            let name = device.name().unwrap_or_else(|_| "Unknown".into());
+            info!("Default output device: {}", name);
         },
-        Err(e) => error!("Error getting output devices: {}", e),
+        None => error!("No default output device available!"),
     }
     
     info!("==========================================================");
@@ -156,6 +145,10 @@ pub fn test_audio_buffer() {
     
     // Create a small buffer for testing
     let mut buffer = AudioRingBuffer::new(1024);
+    let Some(mut consumer) = buffer.consumer() else {
+        error!("Could not get a reader for the test buffer");
+        return;
+    };
     
     // Test writing to the buffer
     let test_samples = vec![0.5f32; 512];
@@ -165,7 +158,7 @@ pub fn test_audio_buffer() {
     
     // Test reading from the buffer
     let mut output = vec![0.0f32; 256];
-    let read = buffer.read(&mut output);
+    let read = consumer.read(&mut output);
     info!("Read {} samples from buffer", read);
     info!("Buffer status: {}/{} samples available", buffer.available(), buffer.capacity());
     
@@ -173,6 +166,15 @@ pub fn test_audio_buffer() {
     let all_match = output.iter().take(read).all(|&s| (s - 0.5).abs() < 0.0001);
     info!("Data integrity test: {}", if all_match { "PASSED" } else { "FAILED" });
     
+    // Wrap around the end of the storage and check order is kept
+    let ramp: Vec<f32> = (0..900).map(|i| i as f32).collect();
+    let _ = consumer.read(&mut output); // drain the rest of the first write
+    let written = buffer.write(&ramp);
+    let mut wrapped = vec![0.0f32; 900];
+    let read = consumer.read(&mut wrapped);
+    let in_order = written == read && wrapped.iter().zip(ramp.iter()).all(|(a, b)| a == b);
+    info!("Wrap-around test: {}", if in_order { "PASSED" } else { "FAILED" });
+    
     info!("=================================================");
 }
 
@@ -186,6 +188,7 @@ pub fn dump_buffer_stats(buffer: &AudioRingBuffer) {
 }
 
 /// Save debug info about an audio file
+#[cfg(feature = "ffmpeg")]
 pub fn dump_file_info(path: &str) {
     use ffmpeg_sys_next as ffmpeg;
     use std::ffi::CString;
@@ -405,6 +408,7 @@ pub fn create_diagnostic_stream() -> Option<cpal::Stream> {
 }
 
 // FFmpeg error helper function
+#[cfg(feature = "ffmpeg")]
 pub fn ffmpeg_error_string(error_code: i32) -> String {
     unsafe {
         use ffmpeg_sys_next as ffmpeg;
@@ -414,4 +418,134 @@ pub fn ffmpeg_error_string(error_code: i32) -> String {
             .to_string_lossy()
             .into_owned()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // The ring buffer as it was before going lock-free: behind a mutex, copied sample by sample
+    struct LockedRingBuffer {
+        buffer: Vec<f32>,
+        write_pos: usize,
+        read_pos: usize,
+        available: usize,
+    }
+
+    impl LockedRingBuffer {
+        fn write(&mut self, samples: &[f32]) -> usize {
+            let capacity = self.buffer.len();
+            let to_write = samples.len().min(capacity - self.available);
+            for &sample in &samples[..to_write] {
+                self.buffer[self.write_pos] = sample;
+                self.write_pos = (self.write_pos + 1) % capacity;
+            }
+            self.available += to_write;
+            to_write
+        }
+
+        fn read(&mut self, output: &mut [f32]) -> usize {
+            let capacity = self.buffer.len();
+            let to_read = output.len().min(self.available);
+            for sample in output[..to_read].iter_mut() {
+                *sample = self.buffer[self.read_pos];
+                self.read_pos = (self.read_pos + 1) % capacity;
+            }
+            self.available -= to_read;
+            to_read
+        }
+    }
+
+    // Average and worst callback time in nanoseconds
+    fn callback_timings(times: &[u128]) -> (u128, u128) {
+        let total: u128 = times.iter().sum();
+        let average = total / times.len().max(1) as u128;
+        let worst = times.iter().copied().max().unwrap_or(0);
+        (average, worst)
+    }
+
+    // Time the audio callback's buffer handling with the old mutex-based buffer
+    // and the lock-free one, with a decoder thread writing at the same time.
+    // Run with: cargo test -p core benchmark_ring_buffer -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_ring_buffer() {
+        const CALLBACKS: usize = 2000;
+        const CALLBACK_SAMPLES: usize = 1024; // 512 stereo frames
+        const CAPACITY: usize = 48000 * 2;
+
+        let chunk = vec![0.25f32; 4096];
+
+        // Before: lock per callback, allocate a scratch Vec, copy sample by sample
+        let locked = Arc::new(Mutex::new(LockedRingBuffer {
+            buffer: vec![0.0; CAPACITY],
+            write_pos: 0,
+            read_pos: 0,
+            available: 0,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let locked = Arc::clone(&locked);
+            let stop = Arc::clone(&stop);
+            let chunk = chunk.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let written = locked.lock().map(|mut rb| rb.write(&chunk)).unwrap_or(0);
+                    if written == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        let mut data = vec![0.0f32; CALLBACK_SAMPLES];
+        let mut before = Vec::with_capacity(CALLBACKS);
+        for _ in 0..CALLBACKS {
+            let start = Instant::now();
+            let mut scratch = vec![0.0f32; data.len()];
+            let count = locked.lock().map(|mut rb| rb.read(&mut scratch)).unwrap_or(0);
+            data[..count].copy_from_slice(&scratch[..count]);
+            data[count..].fill(0.0);
+            before.push(start.elapsed().as_nanos());
+            std::thread::yield_now();
+        }
+        stop.store(true, Ordering::Relaxed);
+        let _ = producer.join();
+
+        // After: wait-free reads straight into the device buffer
+        let mut ring_buffer = AudioRingBuffer::new(CAPACITY);
+        let mut consumer = ring_buffer.consumer().expect("benchmark buffer has a reader");
+        let stop = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if ring_buffer.write(&chunk) == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        let mut after = Vec::with_capacity(CALLBACKS);
+        for _ in 0..CALLBACKS {
+            let start = Instant::now();
+            let count = consumer.read(&mut data);
+            data[count..].fill(0.0);
+            after.push(start.elapsed().as_nanos());
+            std::thread::yield_now();
+        }
+        stop.store(true, Ordering::Relaxed);
+        let _ = producer.join();
+
+        let (before_avg, before_max) = callback_timings(&before);
+        let (after_avg, after_max) = callback_timings(&after);
+        println!("{} callbacks of {} samples each", CALLBACKS, CALLBACK_SAMPLES);
+        println!("Mutex ring buffer:     avg {} ns, worst {} ns", before_avg, before_max);
+        println!("Lock-free ring buffer: avg {} ns, worst {} ns", after_avg, after_max);
+        if after_avg > 0 {
+            println!("Average speedup: {:.1}x", before_avg as f64 / after_avg as f64);
+        }
+    }
+}
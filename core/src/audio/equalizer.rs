@@ -0,0 +1,269 @@
+// core/src/audio/equalizer.rs
+// Parametric biquad equalizer (RBJ cookbook filters), the first built-in DSP stage
+use std::any::Any;
+use log::{debug, info};
+use serde::{Serialize, Deserialize};
+
+use crate::audio::dsp::Dsp;
+
+pub const EQUALIZER_STAGE: &str = "equalizer";
+
+// Centre frequencies of the default 10-band layout
+const GRAPHIC_FREQUENCIES: [f32; 10] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+// One octave wide bands
+const GRAPHIC_Q: f32 = 1.41;
+const MAX_BAND_GAIN_DB: f32 = 24.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EqBandType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub band_type: EqBandType,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl EqBand {
+    pub fn peaking(frequency: f32, gain_db: f32, q: f32) -> Self {
+        Self { band_type: EqBandType::Peaking, frequency, gain_db, q }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EqSettings {
+    pub enabled: bool,
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+}
+
+impl EqSettings {
+    /// Flat 10-band graphic layout
+    pub fn new() -> Self {
+        Self::graphic([0.0; 10])
+    }
+
+    pub fn graphic(gains_db: [f32; 10]) -> Self {
+        Self {
+            enabled: false,
+            preamp_db: 0.0,
+            bands: GRAPHIC_FREQUENCIES
+                .iter()
+                .zip(gains_db.iter())
+                .map(|(&f, &g)| EqBand::peaking(f, g, GRAPHIC_Q))
+                .collect(),
+        }
+    }
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub name: String,
+    pub settings: EqSettings,
+}
+
+impl EqPreset {
+    fn graphic(name: &str, gains_db: [f32; 10]) -> Self {
+        let mut settings = EqSettings::graphic(gains_db);
+        settings.enabled = true;
+        Self { name: name.to_string(), settings }
+    }
+
+    /// Presets that ship with the player; user presets live in the settings file
+    pub fn builtin() -> Vec<EqPreset> {
+        vec![
+            Self::graphic("Flat", [0.0; 10]),
+            Self::graphic("Bass Boost", [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            Self::graphic("Treble Boost", [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 3.5, 5.0, 6.0]),
+            Self::graphic("Vocal", [-2.0, -2.0, -1.0, 0.5, 2.0, 3.0, 3.0, 1.5, 0.0, -1.0]),
+            Self::graphic("Loudness", [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0, 4.0, 5.0]),
+        ]
+    }
+}
+
+// Normalized biquad coefficients
+#[derive(Clone, Copy, Debug)]
+struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    const IDENTITY: Coefficients = Coefficients { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0 };
+
+    fn for_band(band: &EqBand, sample_rate: u32) -> Self {
+        let fs = sample_rate.max(1) as f64;
+        let frequency = band.frequency as f64;
+
+        // Bands at or above Nyquist can't be realised, leave them out
+        if frequency <= 0.0 || frequency >= fs * 0.49 || band.gain_db.abs() < 0.01 {
+            return Self::IDENTITY;
+        }
+
+        let gain_db = band.gain_db.clamp(-MAX_BAND_GAIN_DB, MAX_BAND_GAIN_DB) as f64;
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency / fs;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * (band.q.max(0.05) as f64));
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.band_type {
+            EqBandType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            EqBandType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            EqBandType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+// Transposed direct form II state for one channel of one band
+#[derive(Clone, Copy, Default)]
+struct BiquadState {
+    z1: f64,
+    z2: f64,
+}
+
+impl BiquadState {
+    fn process(&mut self, c: &Coefficients, x: f64) -> f64 {
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+pub struct Equalizer {
+    settings: EqSettings,
+    sample_rate: u32,
+    channels: usize,
+    coefficients: Vec<Coefficients>,
+    // [band][channel]
+    state: Vec<Vec<BiquadState>>,
+    preamp: f32,
+}
+
+impl Equalizer {
+    pub fn new(settings: EqSettings) -> Self {
+        let mut eq = Self {
+            settings: EqSettings::new(),
+            sample_rate: 44100,
+            channels: 2,
+            coefficients: Vec::new(),
+            state: Vec::new(),
+            preamp: 1.0,
+        };
+        eq.set_settings(settings);
+        eq
+    }
+
+    pub fn settings(&self) -> &EqSettings {
+        &self.settings
+    }
+
+    /// Change bands while playing. Filter history is kept when the band layout is
+    /// unchanged, so dragging a gain doesn't click.
+    pub fn set_settings(&mut self, settings: EqSettings) {
+        debug!("Equalizer settings: enabled={}, preamp={:.1} dB, {} bands",
+               settings.enabled, settings.preamp_db, settings.bands.len());
+        let layout_changed = settings.bands.len() != self.settings.bands.len();
+        self.settings = settings;
+        self.update_filters(layout_changed);
+    }
+
+    fn update_filters(&mut self, reset_state: bool) {
+        self.coefficients = self.settings.bands
+            .iter()
+            .map(|band| Coefficients::for_band(band, self.sample_rate))
+            .collect();
+
+        if reset_state || self.state.len() != self.settings.bands.len() {
+            self.state = vec![vec![BiquadState::default(); self.channels]; self.settings.bands.len()];
+        }
+
+        self.preamp = 10f32.powf(self.settings.preamp_db / 20.0);
+    }
+}
+
+impl Dsp for Equalizer {
+    fn name(&self) -> &str {
+        EQUALIZER_STAGE
+    }
+
+    fn configure(&mut self, sample_rate: u32, channels: usize) {
+        info!("Equalizer configured for {} Hz, {} channels", sample_rate, channels);
+        self.sample_rate = sample_rate;
+        self.channels = channels.max(1);
+        self.update_filters(true);
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.channels;
+        for frame in samples.chunks_exact_mut(channels) {
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let mut value = (*sample * self.preamp) as f64;
+                for (coefficients, state) in self.coefficients.iter().zip(self.state.iter_mut()) {
+                    value = state[c].process(coefficients, value);
+                }
+                *sample = value as f32;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for band in self.state.iter_mut() {
+            for channel in band.iter_mut() {
+                *channel = BiquadState::default();
+            }
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,585 @@
+// core/src/audio/ffmpeg_source.rs
+// AudioSource backed by the system FFmpeg libraries (raw ffmpeg_sys_next)
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+use std::sync::Once;
+
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+
+use ffmpeg_sys_next as ffmpeg;
+use ffmpeg_sys_next::AVMediaType::AVMEDIA_TYPE_AUDIO;
+use ffmpeg_sys_next::AVSampleFormat::AV_SAMPLE_FMT_FLT;
+
+use crate::audio::decoder::{is_network_path, normalize_path_for_check};
+use crate::audio::diagnostics::ffmpeg_error_string;
+use crate::audio::replaygain::ReplayGainInfo;
+use crate::audio::tags::TrackTags;
+use crate::audio::source::{AudioSource, StreamInfo};
+
+// Define constants for buffer safety
+const MAX_CHANNELS: usize = 8;
+
+// Initialize FFmpeg only once
+static FFMPEG_INIT: Once = Once::new();
+
+pub fn initialize() {
+    FFMPEG_INIT.call_once(|| {
+        unsafe {
+            ffmpeg::avformat_network_init();
+        }
+        info!("FFmpeg initialized successfully");
+    });
+}
+
+// Helper function to convert C string to Rust string
+unsafe fn to_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+// Convert one decoded frame to packed f32 and append it to `output`
+unsafe fn convert_frame(
+    frame: *mut ffmpeg::AVFrame,
+    swr_ctx: *mut ffmpeg::SwrContext,
+    channel_count: usize,
+    output: &mut Vec<f32>,
+) -> u64 {
+    if frame.is_null() {
+        return 0;
+    }
+
+    let nb_samples = (*frame).nb_samples;
+    if nb_samples <= 0 {
+        warn!("Skipping empty frame: {} samples", nb_samples);
+        return 0;
+    }
+
+    // Upper bound on output samples, including anything the converter still has buffered
+    let max_out_samples = ffmpeg::swr_get_out_samples(swr_ctx, nb_samples);
+    if max_out_samples <= 0 {
+        error!("Invalid converter output size: {}", max_out_samples);
+        return 0;
+    }
+
+    // Convert straight into the tail of the output vector (packed float has a single plane)
+    let start = output.len();
+    output.resize(start + max_out_samples as usize * channel_count, 0.0);
+    let output_ptr = output.as_mut_ptr().add(start) as *mut u8;
+
+    let out_samples = ffmpeg::swr_convert(
+        swr_ctx,
+        &output_ptr,
+        max_out_samples,
+        (*frame).extended_data as *mut *const u8,
+        nb_samples
+    );
+
+    if out_samples < 0 {
+        error!("Sample format conversion failed: {}", out_samples);
+        output.truncate(start);
+        return 0;
+    }
+
+    output.truncate(start + out_samples as usize * channel_count);
+    out_samples as u64
+}
+
+// Look up a tag in an FFmpeg metadata dictionary (case-insensitive key)
+unsafe fn read_metadata_tag(dict: *mut ffmpeg::AVDictionary, key: &str) -> Option<String> {
+    if dict.is_null() {
+        return None;
+    }
+
+    let c_key = CString::new(key).ok()?;
+    let entry = ffmpeg::av_dict_get(dict, c_key.as_ptr(), std::ptr::null(), 0);
+    if entry.is_null() {
+        return None;
+    }
+
+    let value = to_string((*entry).value);
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Demuxer and decoder for the first audio stream of a file. The swr context only
+/// converts the sample format; rate and layout are left to the caller.
+pub struct FfmpegSource {
+    path: String,
+    format_ctx: *mut ffmpeg::AVFormatContext,
+    codec_ctx: *mut ffmpeg::AVCodecContext,
+    swr_ctx: *mut ffmpeg::SwrContext,
+    packet: *mut ffmpeg::AVPacket,
+    frame: *mut ffmpeg::AVFrame,
+    stream_idx: i32,
+    time_base: ffmpeg::AVRational,
+    // Stream timestamp of the first sample, timestamps are relative to this
+    start_pts: i64,
+    // Timestamp of the first frame of the last decoded packet, in frames
+    last_position: Option<u64>,
+    info: StreamInfo,
+    eof: bool,
+}
+
+// The contexts are owned exclusively by this struct and never shared, so moving
+// it to another thread (e.g. the decode thread) is fine.
+unsafe impl Send for FfmpegSource {}
+
+impl FfmpegSource {
+    pub fn open(path: &str) -> Result<Self> {
+        initialize();
+
+        // Check if file exists for local files
+        let is_network = is_network_path(path);
+        if !is_network {
+            let norm_path = normalize_path_for_check(path);
+            if !Path::new(&norm_path).exists() {
+                error!("File not found: {}", norm_path);
+                return Err(anyhow!("File not found: {}", norm_path));
+            }
+        }
+
+        // Create a C-string from the path
+        let c_path = match CString::new(path) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Invalid path string: {}", e);
+                return Err(anyhow!("Invalid path string: {}", e));
+            }
+        };
+
+        // Build the source up front so Drop releases whatever was allocated if we bail out
+        let mut source = FfmpegSource {
+            path: path.to_string(),
+            format_ctx: std::ptr::null_mut(),
+            codec_ctx: std::ptr::null_mut(),
+            swr_ctx: std::ptr::null_mut(),
+            packet: std::ptr::null_mut(),
+            frame: std::ptr::null_mut(),
+            stream_idx: -1,
+            time_base: ffmpeg::AVRational { num: 1, den: 1 },
+            start_pts: 0,
+            last_position: None,
+            info: StreamInfo {
+                sample_rate: 44100, // Default
+                channels: 2, // Default
+                duration_secs: None,
+                seekable: false,
+                ..Default::default()
+            },
+            eof: false,
+        };
+
+        unsafe {
+            // For network files, create format options with longer timeouts
+            let mut options: *mut ffmpeg::AVDictionary = std::ptr::null_mut();
+            if is_network {
+                // Set timeout values
+                let timeout_key = CString::new("timeout").unwrap();
+                let timeout_val = CString::new("10000000").unwrap(); // 10 seconds in microseconds
+
+                ffmpeg::av_dict_set(&mut options, timeout_key.as_ptr(), timeout_val.as_ptr(), 0);
+            }
+
+            // Open input
+            let ret = ffmpeg::avformat_open_input(
+                &mut source.format_ctx,
+                c_path.as_ptr(),
+                std::ptr::null_mut(),
+                &mut options
+            );
+
+            // Free options dictionary
+            if !options.is_null() {
+                ffmpeg::av_dict_free(&mut options);
+            }
+
+            if ret < 0 || source.format_ctx.is_null() {
+                let error_msg = ffmpeg_error_string(ret);
+                error!("Could not open input file: {} ({})", error_msg, ret);
+                return Err(anyhow!("Could not open input file: {}", error_msg));
+            }
+
+            let format_ctx = source.format_ctx;
+
+            // Find stream info
+            let ret = ffmpeg::avformat_find_stream_info(format_ctx, std::ptr::null_mut());
+            if ret < 0 {
+                let error_msg = ffmpeg_error_string(ret);
+                error!("Could not find stream information: {} ({})", error_msg, ret);
+                return Err(anyhow!("Could not find stream information: {}", error_msg));
+            }
+
+            // Find audio stream
+            for i in 0..(*format_ctx).nb_streams {
+                let stream = *(*format_ctx).streams.offset(i as isize);
+                let codec_params = (*stream).codecpar;
+
+                if (*codec_params).codec_type == AVMEDIA_TYPE_AUDIO {
+                    source.stream_idx = i as i32;
+                    source.info.channels = (*codec_params).ch_layout.nb_channels as usize;
+                    source.info.sample_rate = (*codec_params).sample_rate as u32;
+                    break;
+                }
+            }
+
+            if source.stream_idx == -1 {
+                return Err(anyhow!("Could not find any audio stream in the file"));
+            }
+
+            // Limit channel count for safety
+            if source.info.channels > MAX_CHANNELS {
+                warn!("Limiting channels from {} to {} for safety", source.info.channels, MAX_CHANNELS);
+                source.info.channels = MAX_CHANNELS;
+            }
+
+            info!("Found audio stream: {} channels, {} Hz", source.info.channels, source.info.sample_rate);
+
+            // Get the stream for codec info
+            let stream = *(*format_ctx).streams.offset(source.stream_idx as isize);
+            let codec_params = (*stream).codecpar;
+            source.time_base = (*stream).time_base;
+            if (*stream).start_time != ffmpeg::AV_NOPTS_VALUE {
+                source.start_pts = (*stream).start_time;
+            }
+
+            // Find decoder
+            let codec = ffmpeg::avcodec_find_decoder((*codec_params).codec_id);
+            if codec.is_null() {
+                error!("Unsupported codec ID: {:?}", (*codec_params).codec_id);
+                return Err(anyhow!("Unsupported codec"));
+            }
+
+            source.info.codec = to_string((*codec).name);
+            // The stream's own bitrate, or the container's overall one
+            let bit_rate = if (*codec_params).bit_rate > 0 { (*codec_params).bit_rate } else { (*format_ctx).bit_rate };
+            source.info.bitrate = u32::try_from(bit_rate).ok().filter(|b| *b > 0);
+            info!("Using codec: {}", source.info.codec);
+
+            // Create codec context
+            source.codec_ctx = ffmpeg::avcodec_alloc_context3(codec);
+            if source.codec_ctx.is_null() {
+                error!("Failed to allocate codec context");
+                return Err(anyhow!("Could not allocate codec context"));
+            }
+            let codec_ctx = source.codec_ctx;
+
+            // Copy codec parameters to context
+            if ffmpeg::avcodec_parameters_to_context(codec_ctx, codec_params) < 0 {
+                error!("Failed to copy codec parameters to context");
+                return Err(anyhow!("Could not copy codec parameters to context"));
+            }
+
+            // Force safe channel count in codec context
+            (*codec_ctx).ch_layout.nb_channels = source.info.channels as c_int;
+
+            // Log codec parameters for debugging
+            info!("Codec parameters: sample_fmt={:?}, sample_rate={}, channels={}",
+                (*codec_ctx).sample_fmt,
+                (*codec_ctx).sample_rate,
+                (*codec_ctx).ch_layout.nb_channels);
+
+            // Open codec
+            if ffmpeg::avcodec_open2(codec_ctx, codec, std::ptr::null_mut()) < 0 {
+                error!("Could not open codec");
+                return Err(anyhow!("Could not open codec"));
+            }
+
+            // Calculate duration from format context
+            if (*format_ctx).duration > 0 {
+                source.info.duration_secs = Some((*format_ctx).duration as f64 / ffmpeg::AV_TIME_BASE as f64);
+            }
+
+            // Live streams have neither a seekable transport nor a duration
+            let pb = (*format_ctx).pb;
+            let transport_seekable = pb.is_null() || ((*pb).seekable & ffmpeg::AVIO_SEEKABLE_NORMAL) != 0;
+            source.info.seekable = transport_seekable && source.info.duration_secs.is_some();
+            if !source.info.seekable {
+                info!("Stream is not seekable (duration {:?})", source.info.duration_secs);
+            }
+
+            // Tags live on the container for most formats, on the stream for Ogg
+            let format_metadata = (*format_ctx).metadata;
+            let stream_metadata = (*stream).metadata;
+            source.info.tags = TrackTags::from_tags(|key| {
+                read_metadata_tag(format_metadata, key)
+                    .or_else(|| read_metadata_tag(stream_metadata, key))
+            });
+
+            // Loudness tags, same lookup order
+            source.info.replaygain = ReplayGainInfo::from_tags(|key| {
+                read_metadata_tag(format_metadata, key)
+                    .or_else(|| read_metadata_tag(stream_metadata, key))
+            });
+            if source.info.replaygain.is_empty() {
+                debug!("No ReplayGain/R128 tags found");
+            } else {
+                info!("ReplayGain: {:?}", source.info.replaygain);
+            }
+
+            // Allocate packet and frame
+            source.packet = ffmpeg::av_packet_alloc();
+            if source.packet.is_null() {
+                error!("Failed to allocate packet");
+                return Err(anyhow!("Failed to allocate packet"));
+            }
+
+            source.frame = ffmpeg::av_frame_alloc();
+            if source.frame.is_null() {
+                error!("Failed to allocate frame");
+                return Err(anyhow!("Failed to allocate frame"));
+            }
+        }
+
+        source.init_converter()?;
+        Ok(source)
+    }
+
+    // Decoder's sample format -> packed f32, same rate and layout
+    fn init_converter(&mut self) -> Result<()> {
+        unsafe {
+            // Create SwrContext for format conversion
+            self.swr_ctx = ffmpeg::swr_alloc();
+            if self.swr_ctx.is_null() {
+                error!("Failed to allocate resampler context");
+                return Err(anyhow!("Failed to allocate resampler context"));
+            }
+
+            // Same default layout on both sides
+            let mut ch_layout = std::mem::zeroed::<ffmpeg::AVChannelLayout>();
+            ffmpeg::av_channel_layout_default(&mut ch_layout, self.info.channels as c_int);
+
+            let swr_result = ffmpeg::swr_alloc_set_opts2(
+                &mut self.swr_ctx,
+                &ch_layout,
+                AV_SAMPLE_FMT_FLT,
+                self.info.sample_rate as i32,
+                &ch_layout,
+                (*self.codec_ctx).sample_fmt,
+                self.info.sample_rate as i32,
+                0,
+                std::ptr::null_mut(),
+            );
+
+            if swr_result < 0 {
+                let error_msg = ffmpeg_error_string(swr_result);
+                error!("Failed to set SwrContext options: {} ({})", error_msg, swr_result);
+                return Err(anyhow!("Failed to set SwrContext options: {}", error_msg));
+            }
+
+            // Initialize the converter
+            let swr_init_result = ffmpeg::swr_init(self.swr_ctx);
+            if swr_init_result < 0 {
+                let error_msg = ffmpeg_error_string(swr_init_result);
+                error!("Failed to initialize resampler: {} ({})", error_msg, swr_init_result);
+                return Err(anyhow!("Failed to initialize resampler: {}", error_msg));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Stream timestamp to a frame index, None for frames without a timestamp
+    fn pts_to_frames(&self, pts: i64) -> Option<u64> {
+        if pts == ffmpeg::AV_NOPTS_VALUE || self.time_base.den == 0 {
+            return None;
+        }
+        let seconds = (pts - self.start_pts) as f64 * self.time_base.num as f64 / self.time_base.den as f64;
+        Some((seconds.max(0.0) * self.info.sample_rate as f64).round() as u64)
+    }
+
+    /// Cheap check for an audio stream, without opening a decoder
+    pub fn probe(path: &str) -> bool {
+        initialize();
+
+        unsafe {
+            let c_path = match CString::new(path) {
+                Ok(s) => s,
+                Err(_) => return false,
+            };
+
+            let mut format_ctx: *mut ffmpeg::AVFormatContext = std::ptr::null_mut();
+
+            // Try to open the file
+            let ret = ffmpeg::avformat_open_input(
+                &mut format_ctx,
+                c_path.as_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+
+            if ret < 0 || format_ctx.is_null() {
+                return false;
+            }
+
+            // Find stream info
+            let ret = ffmpeg::avformat_find_stream_info(format_ctx, std::ptr::null_mut());
+            if ret < 0 {
+                ffmpeg::avformat_close_input(&mut format_ctx);
+                return false;
+            }
+
+            // Check for audio stream
+            let mut has_audio = false;
+            for i in 0..(*format_ctx).nb_streams {
+                let stream = *(*format_ctx).streams.offset(i as isize);
+                let codec_type = (*(*stream).codecpar).codec_type;
+
+                if codec_type == AVMEDIA_TYPE_AUDIO {
+                    has_audio = true;
+                    break;
+                }
+            }
+
+            // Clean up
+            ffmpeg::avformat_close_input(&mut format_ctx);
+
+            has_audio
+        }
+    }
+}
+
+impl AudioSource for FfmpegSource {
+    fn backend(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    fn next_frames(&mut self, output: &mut Vec<f32>) -> u64 {
+        if self.eof {
+            return 0;
+        }
+
+        unsafe {
+            loop {
+                // Read packet
+                let ret = ffmpeg::av_read_frame(self.format_ctx, self.packet);
+                if ret < 0 {
+                    if ret == ffmpeg::AVERROR_EOF || ret == ffmpeg::AVERROR(libc::EAGAIN) {
+                        info!("End of file reached: {}", self.path);
+                    } else {
+                        let error_msg = ffmpeg_error_string(ret);
+                        warn!("Error reading frame: {} ({}), treating as end of stream", error_msg, ret);
+                    }
+                    self.eof = true;
+                    return 0;
+                }
+
+                // Skip non-audio packets
+                if (*self.packet).stream_index != self.stream_idx {
+                    ffmpeg::av_packet_unref(self.packet);
+                    continue;
+                }
+
+                // Send packet to decoder
+                let ret = ffmpeg::avcodec_send_packet(self.codec_ctx, self.packet);
+                ffmpeg::av_packet_unref(self.packet);
+
+                if ret < 0 {
+                    let error_msg = ffmpeg_error_string(ret);
+                    warn!("Error sending packet to decoder: {} ({})", error_msg, ret);
+                    return 0;
+                }
+
+                // Process every frame the packet produced
+                let mut frames_decoded = 0;
+                self.last_position = None;
+                loop {
+                    let ret = ffmpeg::avcodec_receive_frame(self.codec_ctx, self.frame);
+
+                    if ret == ffmpeg::AVERROR(libc::EAGAIN) || ret == ffmpeg::AVERROR_EOF {
+                        break;
+                    } else if ret < 0 {
+                        let error_msg = ffmpeg_error_string(ret);
+                        warn!("Error receiving frame from decoder: {} ({})", error_msg, ret);
+                        break;
+                    }
+
+                    if frames_decoded == 0 {
+                        self.last_position = self.pts_to_frames((*self.frame).best_effort_timestamp);
+                    }
+
+                    frames_decoded += convert_frame(
+                        self.frame,
+                        self.swr_ctx,
+                        self.info.channels,
+                        output
+                    );
+
+                    ffmpeg::av_frame_unref(self.frame);
+                }
+
+                return frames_decoded;
+            }
+        }
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<u64> {
+        // Convert to stream timebase
+        let timestamp = self.start_pts + (seconds * self.time_base.den as f64 /
+                        self.time_base.num as f64) as i64;
+
+        unsafe {
+            // Flush buffers
+            ffmpeg::avcodec_flush_buffers(self.codec_ctx);
+
+            // Perform seek
+            let ret = ffmpeg::av_seek_frame(
+                self.format_ctx,
+                self.stream_idx,
+                timestamp,
+                ffmpeg::AVSEEK_FLAG_BACKWARD
+            );
+
+            if ret < 0 {
+                let error_msg = ffmpeg_error_string(ret);
+                warn!("Seeking failed: {} ({})", error_msg, ret);
+                return Err(anyhow!("Seeking failed: {}", error_msg));
+            }
+        }
+
+        info!("Seek successful");
+        self.eof = false;
+        self.last_position = None;
+        Ok((seconds * self.info.sample_rate as f64) as u64)
+    }
+
+    fn frame_position(&self) -> Option<u64> {
+        self.last_position
+    }
+
+    fn is_eof(&self) -> bool {
+        self.eof
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.frame.is_null() {
+                ffmpeg::av_frame_free(&mut self.frame);
+            }
+            if !self.packet.is_null() {
+                ffmpeg::av_packet_free(&mut self.packet);
+            }
+            if !self.swr_ctx.is_null() {
+                ffmpeg::swr_free(&mut self.swr_ctx);
+            }
+            if !self.codec_ctx.is_null() {
+                ffmpeg::avcodec_free_context(&mut self.codec_ctx);
+            }
+            if !self.format_ctx.is_null() {
+                ffmpeg::avformat_close_input(&mut self.format_ctx);
+            }
+        }
+    }
+}
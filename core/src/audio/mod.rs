@@ -1,12 +1,29 @@
 // core/src/audio/mod.rs
 pub mod buffer;
 pub mod decoder;
+pub mod source;
+#[cfg(feature = "ffmpeg")]
+pub mod ffmpeg_source;
+#[cfg(feature = "symphonia")]
+pub mod symphonia_source;
 pub mod device;
+pub mod sink;
 pub mod position;
 pub mod resampler;
+pub mod replaygain;
+pub mod tags;
+pub mod analysis;
+pub mod dsp;
+pub mod equalizer;
+pub mod timestretch;
 pub mod diagnostics;  // Add this line to include the diagnostics module
 
 // Re-export key types
-pub use buffer::AudioRingBuffer;
-pub use position::PlaybackPosition;
+pub use buffer::{AudioRingBuffer, RingConsumer, RingStats};
+pub use position::{LoopRegion, PlaybackPosition, SeekTarget};
+pub use dsp::{Dsp, DspChain};
+pub use sink::{AudioSink, OutputSink};
+pub use timestretch::TimeStretcher;
+pub use source::{open_source, AudioSource, StreamInfo};
+pub use tags::TrackTags;
 pub use decoder::{initialize_ffmpeg, get_supported_extensions, is_supported_audio_format};
\ No newline at end of file
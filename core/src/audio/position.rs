@@ -1,16 +1,40 @@
 // core/src/audio/position.rs
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use log::{debug, info};
 
+/// Where a requested seek should land
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeekTarget {
+    // 0.0 to 1.0 of the track, only meaningful when the duration is known
+    Fraction(f32),
+    // Absolute time from the start of the track
+    Time(Duration),
+}
+
+/// A–B loop on one track: playback jumps back to `start` whenever it reaches `end`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopRegion {
+    pub path: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl LoopRegion {
+    pub fn end_frame(&self, sample_rate: u32) -> u64 {
+        (self.end.as_secs_f64() * sample_rate as f64).round() as u64
+    }
+}
+
 pub struct PlaybackPosition {
     pub total_samples: u64,
     pub current_sample: Arc<AtomicUsize>,
     pub sample_rate: u32,
     pub channel_count: usize,
     pub seek_requested: Option<Arc<AtomicBool>>,
-    pub seek_target: Option<Arc<Mutex<f32>>>,
+    pub seek_target: Option<Arc<Mutex<SeekTarget>>>,
     pub buffer_health: Option<f32>,
     pub clear_buffers: bool,
     // Use a proper mutex for log timestamps
@@ -25,7 +49,7 @@ impl PlaybackPosition {
             sample_rate,
             channel_count: 2,
             seek_requested: Some(Arc::new(AtomicBool::new(false))),
-            seek_target: Some(Arc::new(Mutex::new(0.0))),
+            seek_target: Some(Arc::new(Mutex::new(SeekTarget::Fraction(0.0)))),
             buffer_health: None,
             clear_buffers: false,
             last_progress_log: Arc::new(Mutex::new(Instant::now())),
@@ -138,29 +162,116 @@ impl PlaybackPosition {
              (current_frame as f64 / total_frames as f64) * 100.0,
              target_frame, total_frames, frac * 100.0);
         
-        if let Some(flag) = &self.seek_requested {
-            let previous = flag.swap(true, Ordering::SeqCst);
-            if previous {
-                debug!("Note: Overwriting a previous seek request that was not yet processed");
-            }
-        } else {
-            debug!("seek_requested flag is not initialized");
-        }
-        
+        self.set_seek_target(SeekTarget::Fraction(frac));
+    }
+
+    /// Seek to an absolute time, works without a known duration
+    pub fn request_seek_to(&mut self, position: Duration) {
+        info!("Seek requested to {:.2}s", position.as_secs_f64());
+        self.set_seek_target(SeekTarget::Time(position));
+    }
+
+    fn set_seek_target(&mut self, seek_target: SeekTarget) {
+        // Store the target before raising the flag, so the decode loop never sees a stale one
         if let Some(target) = &self.seek_target {
             if let Ok(mut tgt_lock) = target.lock() {
-                *tgt_lock = frac;
-                debug!("Set seek target to {:.4}", frac);
+                *tgt_lock = seek_target;
+                debug!("Set seek target to {:?}", seek_target);
             } else {
                 debug!("Failed to acquire lock for seek target");
             }
         } else {
             debug!("seek_target is not initialized");
         }
+
+        if let Some(flag) = &self.seek_requested {
+            let previous = flag.swap(true, Ordering::SeqCst);
+            if previous {
+                debug!("Note: Overwriting a previous seek request that was not yet processed");
+            }
+        } else {
+            debug!("seek_requested flag is not initialized");
+        }
     }
 
     pub fn set_current_frame(&self, frame_index: usize) {
         self.current_sample.store(frame_index, Ordering::SeqCst);
         debug!("Set current_frame to {}", frame_index);
     }
+
+    // Same without the log line, for the decode loop's continuous updates
+    pub fn update_played_frame(&self, frame_index: u64) {
+        self.current_sample.store(frame_index as usize, Ordering::Relaxed);
+    }
+}
+
+/// Maps samples in the ring buffer back to source frames, so the reported position is what
+/// the device has played rather than what has been decoded into the buffer.
+pub struct PlayheadTracker {
+    // (ring buffer sample index, source frame of that sample, source frames per buffered sample),
+    // oldest first. The front one is the last the device has reached.
+    anchors: VecDeque<(u64, u64, f64)>,
+    output_rate: u32,
+    output_channels: usize,
+    // Media time per second of output, above 1.0 when playing faster
+    speed: f64,
+}
+
+impl PlayheadTracker {
+    pub fn new(output_rate: u32, output_channels: usize) -> Self {
+        Self {
+            anchors: VecDeque::new(),
+            output_rate: output_rate.max(1),
+            output_channels: output_channels.max(1),
+            speed: 1.0,
+        }
+    }
+
+    pub fn set_output_format(&mut self, output_rate: u32, output_channels: usize) {
+        self.output_rate = output_rate.max(1);
+        self.output_channels = output_channels.max(1);
+    }
+
+    /// Applies to samples marked from now on, the ones already buffered keep their speed
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    fn frames_per_sample(&self, source_rate: u32) -> f64 {
+        source_rate as f64 / self.output_rate as f64 / self.output_channels as f64 * self.speed
+    }
+
+    /// Forget everything buffered (seek, new buffer); the device continues at `ring_index`
+    /// with `source_frame` of a track at `source_rate`
+    pub fn restart(&mut self, ring_index: u64, source_frame: u64, source_rate: u32) {
+        self.anchors.clear();
+        self.anchors.push_back((ring_index, source_frame, self.frames_per_sample(source_rate)));
+    }
+
+    /// Samples written from `ring_index` on start at `source_frame` of a track at `source_rate`
+    pub fn mark(&mut self, ring_index: u64, source_frame: u64, source_rate: u32) {
+        let rate = self.frames_per_sample(source_rate);
+
+        // Most packets just continue the previous one, no need to remember those
+        if let Some(&(last_index, last_frame, last_rate)) = self.anchors.back() {
+            let predicted = last_frame as f64 + (ring_index.saturating_sub(last_index)) as f64 * last_rate;
+            if (last_rate - rate).abs() < f64::EPSILON && (predicted - source_frame as f64).abs() < 1.0 {
+                return;
+            }
+        }
+
+        self.anchors.push_back((ring_index, source_frame, rate));
+    }
+
+    /// Source frame the device has reached, given how many samples it has read from the buffer
+    pub fn played_frame(&mut self, ring_read: u64) -> u64 {
+        while self.anchors.len() > 1 && self.anchors[1].0 <= ring_read {
+            self.anchors.pop_front();
+        }
+
+        match self.anchors.front() {
+            Some(&(index, frame, rate)) => frame + (ring_read.saturating_sub(index) as f64 * rate) as u64,
+            None => 0,
+        }
+    }
 }
\ No newline at end of file
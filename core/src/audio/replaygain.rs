@@ -0,0 +1,211 @@
+// core/src/audio/replaygain.rs
+// ReplayGain / R128 tag parsing and gain calculation
+use log::debug;
+
+use crate::audio::analysis::LoudnessInfo;
+use crate::settings::{NormalizationMode, NormalizationSettings};
+
+// R128 gains are relative to -23 LUFS, ReplayGain to roughly -18 LUFS
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+// ReplayGain 2.0 reference level, used to turn measured loudness into gains
+const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+// Limit how far a bad tag can push the level
+const MAX_GAIN_DB: f32 = 24.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGainInfo {
+    pub track_gain_db: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGainInfo {
+    // Build from a tag lookup (key -> value). REPLAYGAIN_* tags win over R128_* tags.
+    pub fn from_tags<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let track_gain_db = lookup("REPLAYGAIN_TRACK_GAIN")
+            .and_then(|v| parse_gain_db(&v))
+            .or_else(|| lookup("R128_TRACK_GAIN").and_then(|v| parse_r128_gain(&v)));
+        let album_gain_db = lookup("REPLAYGAIN_ALBUM_GAIN")
+            .and_then(|v| parse_gain_db(&v))
+            .or_else(|| lookup("R128_ALBUM_GAIN").and_then(|v| parse_r128_gain(&v)));
+
+        Self {
+            track_gain_db,
+            track_peak: lookup("REPLAYGAIN_TRACK_PEAK").and_then(|v| parse_peak(&v)),
+            album_gain_db,
+            album_peak: lookup("REPLAYGAIN_ALBUM_PEAK").and_then(|v| parse_peak(&v)),
+        }
+    }
+
+    // Gains derived from our own loudness analysis
+    pub fn from_loudness(loudness: &LoudnessInfo) -> Self {
+        Self {
+            track_gain_db: Some(REPLAYGAIN_REFERENCE_LUFS - loudness.track.integrated_lufs),
+            track_peak: Some(loudness.track.true_peak),
+            album_gain_db: loudness.album.map(|a| REPLAYGAIN_REFERENCE_LUFS - a.integrated_lufs),
+            album_peak: loudness.album.map(|a| a.true_peak),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track_gain_db.is_none() && self.album_gain_db.is_none()
+    }
+
+    // Linear gain factor to apply for the given settings.
+    // Album mode falls back to track gain and vice versa; untagged files play at unity.
+    pub fn gain_factor(&self, settings: &NormalizationSettings) -> f32 {
+        let (gain_db, peak) = match settings.mode {
+            NormalizationMode::Off => return 1.0,
+            NormalizationMode::Track => match self.track_gain_db {
+                Some(gain) => (gain, self.track_peak),
+                None => (match self.album_gain_db {
+                    Some(gain) => gain,
+                    None => return 1.0,
+                }, self.album_peak),
+            },
+            NormalizationMode::Album => match self.album_gain_db {
+                Some(gain) => (gain, self.album_peak),
+                None => (match self.track_gain_db {
+                    Some(gain) => gain,
+                    None => return 1.0,
+                }, self.track_peak),
+            },
+        };
+
+        let total_db = (gain_db + settings.preamp_db).clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+        let mut factor = 10f32.powf(total_db / 20.0);
+
+        // Don't let the gain push the loudest sample past full scale
+        if settings.prevent_clipping {
+            if let Some(peak) = peak {
+                if peak > 0.0 && factor * peak > 1.0 {
+                    debug!("Limiting gain {:.2} dB to avoid clipping (peak {:.4})", total_db, peak);
+                    factor = 1.0 / peak;
+                }
+            }
+        }
+
+        factor
+    }
+}
+
+// "-6.54 dB", "+1.2dB", "-6.54"
+fn parse_gain_db(value: &str) -> Option<f32> {
+    let trimmed = value.trim();
+    let number = trimmed
+        .strip_suffix("dB")
+        .or_else(|| trimmed.strip_suffix("db"))
+        .or_else(|| trimmed.strip_suffix("DB"))
+        .unwrap_or(trimmed)
+        .trim();
+    number.parse::<f32>().ok().filter(|g| g.is_finite())
+}
+
+fn parse_peak(value: &str) -> Option<f32> {
+    value.trim().parse::<f32>().ok().filter(|p| p.is_finite() && *p > 0.0)
+}
+
+// Opus R128 gains are Q7.8 fixed point integers
+fn parse_r128_gain(value: &str) -> Option<f32> {
+    let q78 = value.trim().parse::<i32>().ok()?;
+    Some(q78 as f32 / 256.0 + R128_TO_REPLAYGAIN_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn tags(pairs: &[(&str, &str)]) -> ReplayGainInfo {
+        let tags: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ReplayGainInfo::from_tags(|key| tags.get(key).cloned())
+    }
+
+    fn settings(mode: NormalizationMode, prevent_clipping: bool) -> NormalizationSettings {
+        NormalizationSettings { mode, preamp_db: 0.0, prevent_clipping }
+    }
+
+    fn db(gain_db: f32) -> f32 {
+        10f32.powf(gain_db / 20.0)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn gains_parse_with_and_without_units() {
+        assert_eq!(parse_gain_db("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_gain_db("+1.2dB"), Some(1.2));
+        assert_eq!(parse_gain_db(" -3.5 "), Some(-3.5));
+        assert_eq!(parse_gain_db("loud"), None);
+        assert_eq!(parse_gain_db("NaN dB"), None);
+    }
+
+    #[test]
+    fn r128_gains_are_q78_relative_to_minus_23_lufs() {
+        // -512 / 256 = -2 dB against -23 LUFS, +3 dB against ReplayGain's reference
+        assert_eq!(parse_r128_gain("-512"), Some(3.0));
+        assert_eq!(parse_r128_gain("384"), Some(6.5));
+        assert_eq!(parse_r128_gain("-1.5"), None);
+    }
+
+    #[test]
+    fn replaygain_tags_win_over_r128() {
+        let info = tags(&[
+            ("REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+            ("R128_TRACK_GAIN", "-512"),
+            ("R128_ALBUM_GAIN", "256"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.988"),
+        ]);
+        assert_eq!(info.track_gain_db, Some(-6.54));
+        assert_eq!(info.album_gain_db, Some(6.0));
+        assert_eq!(info.track_peak, Some(0.988));
+        assert_eq!(info.album_peak, None);
+        assert!(tags(&[]).is_empty());
+    }
+
+    #[test]
+    fn each_mode_falls_back_to_the_other_gain() {
+        let track_only = tags(&[("REPLAYGAIN_TRACK_GAIN", "-6 dB")]);
+        let album_only = tags(&[("REPLAYGAIN_ALBUM_GAIN", "-3 dB")]);
+        let both = tags(&[("REPLAYGAIN_TRACK_GAIN", "-6 dB"), ("REPLAYGAIN_ALBUM_GAIN", "-3 dB")]);
+
+        let track = settings(NormalizationMode::Track, false);
+        let album = settings(NormalizationMode::Album, false);
+        assert_close(both.gain_factor(&track), db(-6.0));
+        assert_close(both.gain_factor(&album), db(-3.0));
+        assert_close(album_only.gain_factor(&track), db(-3.0));
+        assert_close(track_only.gain_factor(&album), db(-6.0));
+
+        assert_eq!(tags(&[]).gain_factor(&track), 1.0);
+        assert_eq!(both.gain_factor(&settings(NormalizationMode::Off, false)), 1.0);
+
+        let preamp = NormalizationSettings { preamp_db: 2.0, ..track };
+        assert_close(both.gain_factor(&preamp), db(-4.0));
+    }
+
+    #[test]
+    fn clipping_prevention_holds_the_peak_at_full_scale() {
+        // +6 dB on a 0.8 peak would reach 1.6
+        let info = tags(&[("REPLAYGAIN_TRACK_GAIN", "+6 dB"), ("REPLAYGAIN_TRACK_PEAK", "0.8")]);
+        assert_close(info.gain_factor(&settings(NormalizationMode::Track, true)), 1.0 / 0.8);
+        assert_close(info.gain_factor(&settings(NormalizationMode::Track, false)), db(6.0));
+
+        // The album's peak limits album gain, even when the track would have been fine
+        let info = tags(&[
+            ("REPLAYGAIN_ALBUM_GAIN", "+6 dB"),
+            ("REPLAYGAIN_ALBUM_PEAK", "0.9"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.1"),
+        ]);
+        assert_close(info.gain_factor(&settings(NormalizationMode::Album, true)), 1.0 / 0.9);
+
+        // Gains that keep the peak below full scale are left alone
+        let quiet = tags(&[("REPLAYGAIN_TRACK_GAIN", "-6 dB"), ("REPLAYGAIN_TRACK_PEAK", "1.0")]);
+        assert_close(quiet.gain_factor(&settings(NormalizationMode::Track, true)), db(-6.0));
+    }
+}
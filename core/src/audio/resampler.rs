@@ -1,10 +1,218 @@
 // core/src/audio/resampler.rs
+#[cfg(feature = "ffmpeg")]
 extern crate ffmpeg_next as ffmpeg;
 
-use anyhow::Result;
-use log::debug;
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use rubato::{FftFixedIn, Resampler};
+
+// Input frames per resampler pass
+const CHUNK_FRAMES: usize = 1024;
+
+/// Streaming conversion from a decoder's rate and channel count to the output stream's.
+/// Pure Rust, so it works with any backend; input and output are interleaved f32.
+pub struct SampleConverter {
+    input_rate: u32,
+    output_rate: u32,
+    input_channels: usize,
+    output_channels: usize,
+    // None when the rates match and only channels need remixing
+    resampler: Option<FftFixedIn<f32>>,
+    // Remixed input waiting for a full chunk, one Vec per output channel
+    pending: Vec<Vec<f32>>,
+    resampled: Vec<Vec<f32>>,
+    // Leading frames of resampler delay still to drop
+    delay_frames: usize,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl SampleConverter {
+    pub fn new(input_rate: u32, input_channels: usize, output_rate: u32, output_channels: usize) -> Result<Self> {
+        if input_rate == 0 || output_rate == 0 || input_channels == 0 || output_channels == 0 {
+            return Err(anyhow!("Invalid conversion {}Hz {}ch -> {}Hz {}ch",
+                               input_rate, input_channels, output_rate, output_channels));
+        }
+
+        let resampler = if input_rate != output_rate {
+            let resampler = FftFixedIn::<f32>::new(
+                input_rate as usize,
+                output_rate as usize,
+                CHUNK_FRAMES,
+                2,
+                output_channels,
+            ).map_err(|e| anyhow!("Failed to create resampler: {}", e))?;
+            Some(resampler)
+        } else {
+            None
+        };
+
+        let mut converter = Self {
+            input_rate,
+            output_rate,
+            input_channels,
+            output_channels,
+            pending: vec![Vec::with_capacity(CHUNK_FRAMES * 2); output_channels],
+            resampled: resampler
+                .as_ref()
+                .map(|r| r.output_buffer_allocate(true))
+                .unwrap_or_default(),
+            resampler,
+            delay_frames: 0,
+            frames_in: 0,
+            frames_out: 0,
+        };
+        converter.reset();
+
+        info!("Converting {}Hz {}ch -> {}Hz {}ch", input_rate, input_channels, output_rate, output_channels);
+        Ok(converter)
+    }
+
+    /// Convert interleaved input and append whatever output is ready to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<()> {
+        if self.resampler.is_none() {
+            self.remix_into(input, output);
+            let frames = (input.len() / self.input_channels) as u64;
+            self.frames_in += frames;
+            self.frames_out += frames;
+            return Ok(());
+        }
+
+        // Remix straight into the planar queue
+        for frame in input.chunks_exact(self.input_channels) {
+            for (channel, pending) in self.pending.iter_mut().enumerate() {
+                pending.push(remix_sample(frame, channel, self.output_channels));
+            }
+        }
+        self.frames_in += (input.len() / self.input_channels) as u64;
+
+        while let Some(resampler) = self.resampler.as_mut() {
+            if self.pending[0].len() < resampler.input_frames_next() {
+                break;
+            }
+            let (used, produced) = resampler
+                .process_into_buffer(&self.pending, &mut self.resampled, None)
+                .map_err(|e| anyhow!("Resampling failed: {}", e))?;
+            for pending in self.pending.iter_mut() {
+                pending.drain(..used);
+            }
+            self.emit(produced, output);
+        }
+
+        Ok(())
+    }
+
+    /// At the end of the stream: push out the queued input and the resampler's tail
+    pub fn flush(&mut self, output: &mut Vec<f32>) -> Result<()> {
+        if self.resampler.is_none() {
+            return Ok(());
+        }
+
+        // Exactly as many frames as the input was long, so the track doesn't grow silence
+        let expected = (self.frames_in as f64 * self.output_rate as f64 / self.input_rate as f64).round() as u64;
+
+        let mut passes = 0;
+        while let Some(resampler) = self.resampler.as_mut() {
+            if self.frames_out >= expected || passes >= 8 {
+                break;
+            }
+            let queued = !self.pending[0].is_empty();
+            let (used, produced) = if queued {
+                resampler.process_partial_into_buffer(Some(&self.pending), &mut self.resampled, None)
+            } else {
+                resampler.process_partial_into_buffer(None::<&[Vec<f32>]>, &mut self.resampled, None)
+            }.map_err(|e| anyhow!("Resampling failed: {}", e))?;
+
+            for pending in self.pending.iter_mut() {
+                pending.drain(..used.min(pending.len()));
+            }
+            let produced = produced.min((expected - self.frames_out) as usize + self.delay_frames);
+            self.emit(produced, output);
+            passes += 1;
+        }
+
+        debug!("Flushed resampler: {} frames in, {} frames out", self.frames_in, self.frames_out);
+        Ok(())
+    }
+
+    /// Forget queued audio, e.g. after a seek
+    pub fn reset(&mut self) {
+        for pending in self.pending.iter_mut() {
+            pending.clear();
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.reset();
+            self.delay_frames = resampler.output_delay();
+        }
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.output_channels
+    }
+
+    /// Frames handed out since creation or the last reset
+    pub fn frames_out(&self) -> u64 {
+        self.frames_out
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    // Interleave resampled frames into the output, dropping the resampler's leading delay
+    fn emit(&mut self, produced: usize, output: &mut Vec<f32>) {
+        let skip = self.delay_frames.min(produced);
+        self.delay_frames -= skip;
+
+        output.reserve((produced - skip) * self.output_channels);
+        for frame in skip..produced {
+            for channel in &self.resampled {
+                output.push(channel[frame]);
+            }
+        }
+        self.frames_out += (produced - skip) as u64;
+    }
+
+    fn remix_into(&self, input: &[f32], output: &mut Vec<f32>) {
+        if self.input_channels == self.output_channels {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        output.reserve(input.len() / self.input_channels * self.output_channels);
+        for frame in input.chunks_exact(self.input_channels) {
+            for channel in 0..self.output_channels {
+                output.push(remix_sample(frame, channel, self.output_channels));
+            }
+        }
+    }
+}
+
+// One output channel of a frame: mono is copied to every channel, extra input
+// channels are folded down and averaged, missing ones stay silent
+fn remix_sample(frame: &[f32], channel: usize, output_channels: usize) -> f32 {
+    let input_channels = frame.len();
+    if input_channels == output_channels {
+        frame[channel]
+    } else if input_channels == 1 {
+        frame[0]
+    } else if input_channels > output_channels {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for sample in frame.iter().skip(channel).step_by(output_channels) {
+            sum += sample;
+            count += 1;
+        }
+        sum / count.max(1) as f32
+    } else {
+        frame.get(channel).copied().unwrap_or(0.0)
+    }
+}
 
 // For simple sample rate conversion
+#[cfg(feature = "ffmpeg")]
 pub fn resample(
     input: &[f32],
     input_rate: u32,
@@ -27,6 +235,7 @@ pub fn resample(
 }
 
 // More complete resampling function
+#[cfg(feature = "ffmpeg")]
 pub fn resample_buffer(
     input: &[f32],
     input_rate: u32,
@@ -0,0 +1,457 @@
+// core/src/audio/sink.rs
+// Where the decode loop's output goes: the sound card, or a headless sink that
+// drains the ring buffer on its own clock (no audio hardware needed)
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+
+use crate::audio::buffer::RingConsumer;
+use crate::audio::device::{build_ring_buffer_stream, find_output_device, select_output_config, OutputDeviceId, StreamHealth};
+
+/// Which kind of output to render into
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum OutputSink {
+    /// The selected output device (or the default one)
+    #[default]
+    Device,
+    /// Throw the samples away. `speed` is a multiple of real time (1.0 = wall clock).
+    Null { speed: f32 },
+    /// Write everything that is played to a 32-bit float WAV file, at `speed` times real time.
+    /// Only samples the decoder produced are written, pauses and underruns leave no silence.
+    WavFile { path: PathBuf, speed: f32 },
+}
+
+impl OutputSink {
+    pub fn null() -> Self {
+        OutputSink::Null { speed: 1.0 }
+    }
+
+    pub fn wav_file(path: impl Into<PathBuf>) -> Self {
+        OutputSink::WavFile { path: path.into(), speed: 1.0 }
+    }
+
+    /// Same sink, consuming samples `speed` times faster than real time
+    pub fn with_speed(self, speed: f32) -> Self {
+        match self {
+            OutputSink::Device => OutputSink::Device,
+            OutputSink::Null { .. } => OutputSink::Null { speed },
+            OutputSink::WavFile { path, .. } => OutputSink::WavFile { path, speed },
+        }
+    }
+}
+
+/// A running output. Dropping it stops it.
+pub trait AudioSink {
+    fn name(&self) -> &str;
+}
+
+/// An output that has been picked but not started. Its format decides the ring buffer size,
+/// and the stream is only built once the buffer exists.
+pub enum SinkTarget {
+    Device {
+        device: cpal::Device,
+        id: OutputDeviceId,
+        config: cpal::StreamConfig,
+    },
+    Headless {
+        sink: OutputSink,
+        sample_rate: u32,
+        channels: usize,
+    },
+}
+
+impl SinkTarget {
+    /// Pick the output for a track. Headless sinks run at the track's own format.
+    pub fn open(
+        sink: &OutputSink,
+        device: Option<&OutputDeviceId>,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Self> {
+        match sink {
+            OutputSink::Device => {
+                let (device, id) = find_output_device(device)?;
+                let config = select_output_config(&device, sample_rate, channels)?.config();
+                Ok(SinkTarget::Device { device, id, config })
+            }
+            headless => Ok(SinkTarget::Headless {
+                sink: headless.clone(),
+                sample_rate: sample_rate.max(1),
+                channels: channels.max(1),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            SinkTarget::Device { id, .. } => id.name.clone(),
+            SinkTarget::Headless { sink: OutputSink::WavFile { path, .. }, .. } => {
+                format!("WAV file {}", path.display())
+            }
+            SinkTarget::Headless { .. } => "Null output".to_string(),
+        }
+    }
+
+    /// (sample rate, channels) the decoder has to produce
+    pub fn format(&self) -> (u32, usize) {
+        match self {
+            SinkTarget::Device { config, .. } => (config.sample_rate.0, config.channels as usize),
+            SinkTarget::Headless { sample_rate, channels, .. } => (*sample_rate, *channels),
+        }
+    }
+
+    /// Start pulling from the ring buffer
+    pub fn start(
+        self,
+        consumer: RingConsumer,
+        needs_data: Arc<AtomicBool>,
+        health: Arc<StreamHealth>,
+    ) -> Result<Box<dyn AudioSink>> {
+        let name = self.name();
+        match self {
+            SinkTarget::Device { device, config, .. } => {
+                let stream = build_ring_buffer_stream(&device, &config, consumer, needs_data, health)?;
+                Ok(Box::new(DeviceSink { name, _stream: stream }))
+            }
+            SinkTarget::Headless { sink, sample_rate, channels } => {
+                let (speed, writer) = match sink {
+                    OutputSink::WavFile { path, speed } => {
+                        let spec = hound::WavSpec {
+                            channels: channels as u16,
+                            sample_rate,
+                            bits_per_sample: 32,
+                            sample_format: hound::SampleFormat::Float,
+                        };
+                        let writer = hound::WavWriter::create(&path, spec)
+                            .map_err(|e| anyhow!("Could not create {}: {}", path.display(), e))?;
+                        (speed, Some(writer))
+                    }
+                    OutputSink::Null { speed } => (speed, None),
+                    OutputSink::Device => unreachable!("device sinks are opened as SinkTarget::Device"),
+                };
+                let clock = HeadlessClock {
+                    consumer,
+                    needs_data,
+                    health,
+                    stop: Arc::new(AtomicBool::new(false)),
+                    sample_rate,
+                    channels,
+                    // Keep the period sleeps meaningful
+                    speed: speed.clamp(0.01, 1000.0),
+                    writer,
+                };
+                Ok(Box::new(HeadlessSink::spawn(name, clock)?))
+            }
+        }
+    }
+}
+
+// A cpal stream, kept alive for as long as the sink
+struct DeviceSink {
+    name: String,
+    _stream: cpal::Stream,
+}
+
+impl AudioSink for DeviceSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+type WavOutput = hound::WavWriter<BufWriter<File>>;
+
+// Everything the headless clock thread needs
+struct HeadlessClock {
+    consumer: RingConsumer,
+    needs_data: Arc<AtomicBool>,
+    health: Arc<StreamHealth>,
+    stop: Arc<AtomicBool>,
+    sample_rate: u32,
+    channels: usize,
+    speed: f32,
+    writer: Option<WavOutput>,
+}
+
+/// Drains the ring buffer from a thread in fixed periods, like a device callback would
+pub struct HeadlessSink {
+    name: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HeadlessSink {
+    fn spawn(name: String, clock: HeadlessClock) -> Result<Self> {
+        info!("Starting headless output: {} ({} Hz, {} channels, {}x speed)",
+              name, clock.sample_rate, clock.channels, clock.speed);
+
+        let stop = Arc::clone(&clock.stop);
+        let thread = thread::Builder::new()
+            .name("headless-sink".to_string())
+            .spawn(move || clock.run())
+            .map_err(|e| anyhow!("Failed to start headless output: {}", e))?;
+
+        Ok(Self { name, stop, thread: Some(thread) })
+    }
+}
+
+impl HeadlessClock {
+    fn run(mut self) {
+        // 10ms periods, about what a device callback asks for
+        let period_frames = (self.sample_rate as usize / 100).max(1);
+        let mut period_buffer = vec![0.0f32; period_frames * self.channels];
+        let period = Duration::from_secs_f64(period_frames as f64 / self.sample_rate as f64 / self.speed as f64);
+        let low_water_mark = self.consumer.capacity() / 4;
+        let mut next_tick = Instant::now();
+
+        while !self.stop.load(Ordering::Acquire) {
+            self.health.mark_callback();
+
+            let samples_read = self.consumer.read(&mut period_buffer);
+            if let Some(writer) = self.writer.as_mut() {
+                for &sample in &period_buffer[..samples_read] {
+                    if let Err(e) = writer.write_sample(sample) {
+                        error!("Failed to write WAV output: {}", e);
+                        self.health.report_error(format!("WAV write failed: {}", e));
+                        self.writer = None;
+                        break;
+                    }
+                }
+            }
+
+            if self.consumer.available() < low_water_mark {
+                self.needs_data.store(true, Ordering::Release);
+            }
+
+            // Sleep to the next period boundary so the pace doesn't drift
+            next_tick += period;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else if now - next_tick > Duration::from_secs(1) {
+                // Fell far behind (suspended, debugger), don't try to catch up
+                next_tick = now;
+            }
+        }
+
+        if let Some(writer) = self.writer.take() {
+            match writer.finalize() {
+                Ok(()) => info!("WAV output finalized"),
+                Err(e) => error!("Failed to finalize WAV output: {}", e),
+            }
+        }
+    }
+}
+
+impl AudioSink for HeadlessSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for HeadlessSink {
+    fn drop(&mut self) {
+        // Joining makes sure the WAV file is complete once the sink is gone
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Headless output thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+    use std::path::Path;
+
+    const RATE: u32 = 44100;
+    // Far faster than real time, a 2 second track renders in a few tens of milliseconds
+    const SPEED: f32 = 100.0;
+
+    // Scratch file for one test, tests run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sink_test_{}_{}", std::process::id(), name))
+    }
+
+    // 16-bit stereo WAV whose samples count up, so any sample shows where in the file it came from
+    fn write_ramp(path: &Path, seconds: u32) -> Vec<f32> {
+        write_wav(path, seconds, |i| (i % 30000 - 15000) as i16)
+    }
+
+    fn write_wav(path: &Path, seconds: u32, sample: impl Fn(i32) -> i16) -> Vec<f32> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        let mut samples = Vec::new();
+        for i in 0..(RATE * seconds * 2) as i32 {
+            let value = sample(i);
+            writer.write_sample(value).unwrap();
+            samples.push(value as f32 / 32768.0);
+        }
+        writer.finalize().unwrap();
+        samples
+    }
+
+    fn read_output(path: &Path) -> Vec<f32> {
+        hound::WavReader::open(path).unwrap()
+            .into_samples::<f32>()
+            .map(|s| s.unwrap())
+            .collect()
+    }
+
+    // Play until the end of the file, returning how long it took. The WAV sink is
+    // finalized once the playback thread is done.
+    fn play_to_end(player: &mut Player) -> Duration {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(20) {
+            thread::sleep(Duration::from_millis(10));
+            player.update_progress();
+            if player.playback_thread.as_ref().is_none_or(|t| t.is_finished()) {
+                break;
+            }
+        }
+        start.elapsed()
+    }
+
+    fn headless_player(sink: OutputSink) -> Player {
+        let mut player = Player::new();
+        player.set_volume(1.0);
+        player.set_output_sink(sink.with_speed(SPEED)).unwrap();
+        player
+    }
+
+    fn assert_samples_eq(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "sample count");
+        if let Some(i) = (0..actual.len()).find(|&i| (actual[i] - expected[i]).abs() > 1e-6) {
+            panic!("sample {} is {}, expected {}", i, actual[i], expected[i]);
+        }
+    }
+
+    #[test]
+    fn wav_sink_writes_the_whole_track() {
+        let input = temp_path("whole_in.wav");
+        let output = temp_path("whole_out.wav");
+        let expected = write_ramp(&input, 2);
+
+        let mut player = headless_player(OutputSink::wav_file(&output));
+        player.play(input.to_str().unwrap()).unwrap();
+        play_to_end(&mut player);
+
+        assert!(player.track_completed_signal, "end of file wasn't reported");
+        assert_samples_eq(&read_output(&output), &expected);
+        let _ = std::fs::remove_file(input);
+        let _ = std::fs::remove_file(output);
+    }
+
+    #[test]
+    fn wav_sink_joins_tracks_without_gaps() {
+        let input = temp_path("gapless_in.wav");
+        let output = temp_path("gapless_out.wav");
+        let track = write_ramp(&input, 2);
+        let path = input.to_str().unwrap().to_string();
+
+        // Queued while paused, a track this short could otherwise be decoded to the end first
+        let mut player = headless_player(OutputSink::wav_file(&output));
+        player.load_paused(&path, Duration::ZERO).unwrap();
+        player.set_next_track(Some(path.clone()));
+        player.resume();
+        play_to_end(&mut player);
+
+        let expected: Vec<f32> = track.iter().chain(track.iter()).copied().collect();
+        assert_samples_eq(&read_output(&output), &expected);
+        assert_eq!(player.take_finished_tracks(), vec![path]);
+        let _ = std::fs::remove_file(input);
+        let _ = std::fs::remove_file(output);
+    }
+
+    #[test]
+    fn wav_sink_starts_at_the_seek_position() {
+        let input = temp_path("seek_in.wav");
+        let output = temp_path("seek_out.wav");
+        let track = write_ramp(&input, 2);
+
+        let mut player = headless_player(OutputSink::wav_file(&output));
+        player.play_from(input.to_str().unwrap(), Duration::from_millis(500)).unwrap();
+        play_to_end(&mut player);
+
+        let skipped = (RATE / 2 * 2) as usize;
+        assert_samples_eq(&read_output(&output), &track[skipped..]);
+        let _ = std::fs::remove_file(input);
+        let _ = std::fs::remove_file(output);
+    }
+
+    #[test]
+    fn wav_sink_applies_the_volume() {
+        let input = temp_path("volume_in.wav");
+        let output = temp_path("volume_out.wav");
+        let track = write_ramp(&input, 1);
+
+        let mut player = headless_player(OutputSink::wav_file(&output));
+        player.set_volume(0.5);
+        player.play(input.to_str().unwrap()).unwrap();
+        play_to_end(&mut player);
+
+        let expected: Vec<f32> = track.iter().map(|s| s * 0.5).collect();
+        assert_samples_eq(&read_output(&output), &expected);
+        let _ = std::fs::remove_file(input);
+        let _ = std::fs::remove_file(output);
+    }
+
+    #[test]
+    fn crossfade_shorter_than_the_setting_ends_at_full_volume() {
+        let outgoing = temp_path("fade_out_track.wav");
+        let incoming = temp_path("fade_in_track.wav");
+        let output = temp_path("fade_out.wav");
+        let first = write_ramp(&outgoing, 2);
+        let second = write_wav(&incoming, 3, |_| 16384);
+
+        // A 5 second fade into a 2 second track has to fit into those 2 seconds
+        let mut player = headless_player(OutputSink::wav_file(&output));
+        player.set_crossfade(crate::CrossfadeSettings {
+            enabled: true,
+            duration_secs: 5.0,
+            curve: crate::CrossfadeCurve::Linear,
+        });
+        player.load_paused(outgoing.to_str().unwrap(), Duration::ZERO).unwrap();
+        player.set_next_track(Some(incoming.to_str().unwrap().to_string()));
+        player.resume();
+        play_to_end(&mut player);
+
+        let rendered = read_output(&output);
+        assert_eq!(rendered.len(), second.len(), "the fade should overlap the whole first track");
+        // Right before the first track ends the incoming one is at (almost) full volume,
+        // so there's no jump where the first track stops
+        let handover = first.len();
+        assert!((rendered[handover - 1] - 0.5).abs() < 0.01, "incoming track at {} before the handover", rendered[handover - 1]);
+        assert_samples_eq(&rendered[handover..], &second[handover..]);
+        for path in [outgoing, incoming, output] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn null_sink_plays_faster_than_real_time() {
+        let input = temp_path("null_in.wav");
+        write_ramp(&input, 2);
+
+        let mut player = headless_player(OutputSink::null());
+        player.play(input.to_str().unwrap()).unwrap();
+        let took = play_to_end(&mut player);
+
+        assert!(player.track_completed_signal, "end of file wasn't reported");
+        assert!(took < Duration::from_secs(2), "2 seconds of audio took {:?}", took);
+        let _ = std::fs::remove_file(input);
+    }
+}
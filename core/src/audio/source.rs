@@ -0,0 +1,102 @@
+// core/src/audio/source.rs
+// Decoder backends. Each one opens a file and hands out interleaved f32 at the file's
+// own rate and channel count; AudioInput converts that for the output stream.
+use anyhow::{anyhow, Result};
+use log::debug;
+
+use crate::audio::replaygain::ReplayGainInfo;
+use crate::audio::tags::TrackTags;
+
+#[cfg(feature = "ffmpeg")]
+use crate::audio::ffmpeg_source::FfmpegSource;
+#[cfg(feature = "symphonia")]
+use crate::audio::symphonia_source::SymphoniaSource;
+
+/// What a backend found out about the file when opening it
+#[derive(Clone, Debug, Default)]
+pub struct StreamInfo {
+    pub sample_rate: u32,
+    pub channels: usize,
+    // None for live streams and files that don't say
+    pub duration_secs: Option<f64>,
+    // Whether `seek` can work at all (not for live streams)
+    pub seekable: bool,
+    pub codec: String,
+    // Bits per second, as reported by the container or estimated from the file size
+    pub bitrate: Option<u32>,
+    pub tags: TrackTags,
+    pub replaygain: ReplayGainInfo,
+}
+
+pub trait AudioSource: Send {
+    // Backend name, for logs
+    fn backend(&self) -> &'static str;
+
+    fn info(&self) -> &StreamInfo;
+
+    /// Decode the next packet and append its samples, interleaved, to `output`.
+    /// Returns the number of frames appended; at the end of the stream returns 0 and `is_eof` turns true.
+    fn next_frames(&mut self, output: &mut Vec<f32>) -> u64;
+
+    /// Frame index of the first sample the last `next_frames` call returned, taken from the
+    /// packet timestamps. None when the container doesn't provide usable timestamps.
+    fn frame_position(&self) -> Option<u64>;
+
+    /// Seek to a time in seconds. Returns the frame decoding continues from as far as the
+    /// backend knows; containers usually land on a packet or keyframe before the target.
+    fn seek(&mut self, seconds: f64) -> Result<u64>;
+
+    fn is_eof(&self) -> bool;
+}
+
+/// Open a file with the first compiled-in backend that can decode it
+pub fn open_source(path: &str) -> Result<Box<dyn AudioSource>> {
+    #[allow(unused_mut)]
+    let mut errors: Vec<String> = Vec::new();
+
+    #[cfg(feature = "ffmpeg")]
+    match FfmpegSource::open(path) {
+        Ok(source) => return Ok(Box::new(source)),
+        Err(e) => errors.push(format!("ffmpeg: {}", e)),
+    }
+
+    #[cfg(feature = "symphonia")]
+    match SymphoniaSource::open(path) {
+        Ok(source) => return Ok(Box::new(source)),
+        Err(e) => errors.push(format!("symphonia: {}", e)),
+    }
+
+    if errors.is_empty() {
+        return Err(anyhow!("No decoder backend compiled in, enable the ffmpeg or symphonia feature"));
+    }
+
+    debug!("No backend could open {}: {}", path, errors.join("; "));
+    Err(anyhow!("Could not open {}: {}", path, errors.join("; ")))
+}
+
+/// Quick check whether any backend finds an audio stream it can decode
+pub fn probe_audio(path: &str) -> bool {
+    #[cfg(feature = "ffmpeg")]
+    if FfmpegSource::probe(path) {
+        return true;
+    }
+
+    #[cfg(feature = "symphonia")]
+    if SymphoniaSource::probe(path) {
+        return true;
+    }
+
+    let _ = path;
+    false
+}
+
+/// Names of the compiled-in backends, in the order they are tried
+pub fn backends() -> Vec<&'static str> {
+    let names: &[&'static str] = &[
+        #[cfg(feature = "ffmpeg")]
+        "ffmpeg",
+        #[cfg(feature = "symphonia")]
+        "symphonia",
+    ];
+    names.to_vec()
+}
@@ -0,0 +1,312 @@
+// core/src/audio/symphonia_source.rs
+// AudioSource backed by Symphonia, a pure-Rust decoder (MP3/FLAC/Vorbis/WAV/AAC/ALAC)
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+use crate::audio::decoder::normalize_path_for_check;
+use crate::audio::replaygain::ReplayGainInfo;
+use crate::audio::tags::TrackTags;
+use crate::audio::source::{AudioSource, StreamInfo};
+
+// Same safety limit as the FFmpeg backend
+const MAX_CHANNELS: usize = 8;
+
+pub struct SymphoniaSource {
+    path: String,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    // Reused between packets, grown when a packet is bigger than any before it
+    sample_buf: Option<SampleBuffer<f32>>,
+    // Timestamp of the last decoded packet, in frames
+    last_position: Option<u64>,
+    info: StreamInfo,
+    eof: bool,
+}
+
+// Find a tag by its standard key, or by name for tags Symphonia doesn't map (R128_*)
+fn find_tag(revisions: &[MetadataRevision], std_key: Option<StandardTagKey>, name: &str) -> Option<String> {
+    revisions
+        .iter()
+        .flat_map(|revision| revision.tags().iter())
+        .find(|tag| {
+            (std_key.is_some() && tag.std_key == std_key) || tag.key.eq_ignore_ascii_case(name)
+        })
+        .map(|tag| tag.value.to_string())
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn open_format(path: &str) -> Result<symphonia::core::probe::ProbeResult> {
+    let local_path = normalize_path_for_check(path);
+    if local_path.contains("://") {
+        return Err(anyhow!("Symphonia only plays local files"));
+    }
+
+    let file = File::open(&local_path)
+        .map_err(|e| anyhow!("Could not open {}: {}", local_path, e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = Path::new(&local_path).extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    // Gapless trims encoder delay and padding (MP3/AAC), so track changes stay seamless
+    let format_options = FormatOptions { enable_gapless: true, ..Default::default() };
+    symphonia::default::get_probe()
+        .format(&hint, stream, &format_options, &MetadataOptions::default())
+        .map_err(|e| anyhow!("Unsupported format: {}", e))
+}
+
+impl SymphoniaSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut probed = open_format(path)?;
+
+        let track = probed.format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("Could not find any audio stream in the file"))?;
+        let params = track.codec_params.clone();
+        let track_id = track.id;
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|e| anyhow!("Unsupported codec: {}", e))?;
+
+        let mut info = StreamInfo {
+            sample_rate: params.sample_rate.unwrap_or(44100),
+            channels: params.channels.map(|c| c.count()).unwrap_or(2),
+            // Local files can always be seeked, Symphonia scans when there's no index
+            seekable: true,
+            codec: symphonia::default::get_codecs()
+                .get_codec(params.codec)
+                .map(|d| d.short_name.to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        if info.channels > MAX_CHANNELS {
+            warn!("Limiting channels from {} to {} for safety", info.channels, MAX_CHANNELS);
+            info.channels = MAX_CHANNELS;
+        }
+
+        if let (Some(frames), Some(time_base)) = (params.n_frames, params.time_base) {
+            let time = time_base.calc_time(frames);
+            info.duration_secs = Some(time.seconds as f64 + time.frac);
+        }
+
+        // Tags can come from the probe (ID3v2 ahead of the stream) or from the container
+        let mut revisions: Vec<MetadataRevision> = Vec::new();
+        if let Some(metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.current() {
+                revisions.push(revision.clone());
+            }
+        }
+        if let Some(revision) = probed.format.metadata().current() {
+            revisions.push(revision.clone());
+        }
+
+        info.tags = TrackTags::from_tags(|key| {
+            let std_key = match key {
+                "title" => Some(StandardTagKey::TrackTitle),
+                "artist" => Some(StandardTagKey::Artist),
+                "album_artist" => Some(StandardTagKey::AlbumArtist),
+                "album" => Some(StandardTagKey::Album),
+                "track" => Some(StandardTagKey::TrackNumber),
+                "disc" => Some(StandardTagKey::DiscNumber),
+                "date" => Some(StandardTagKey::Date),
+                "genre" => Some(StandardTagKey::Genre),
+                "composer" => Some(StandardTagKey::Composer),
+                "comment" => Some(StandardTagKey::Comment),
+                _ => None,
+            };
+            find_tag(&revisions, std_key, key)
+        });
+
+        // Symphonia doesn't report a bitrate, the average over the whole file is close enough
+        if let (Some(duration), Ok(metadata)) = (info.duration_secs, std::fs::metadata(normalize_path_for_check(path))) {
+            if duration > 0.0 {
+                info.bitrate = Some((metadata.len() as f64 * 8.0 / duration) as u32);
+            }
+        }
+        info.replaygain = ReplayGainInfo::from_tags(|key| {
+            let std_key = match key {
+                "REPLAYGAIN_TRACK_GAIN" => Some(StandardTagKey::ReplayGainTrackGain),
+                "REPLAYGAIN_TRACK_PEAK" => Some(StandardTagKey::ReplayGainTrackPeak),
+                "REPLAYGAIN_ALBUM_GAIN" => Some(StandardTagKey::ReplayGainAlbumGain),
+                "REPLAYGAIN_ALBUM_PEAK" => Some(StandardTagKey::ReplayGainAlbumPeak),
+                _ => None,
+            };
+            find_tag(&revisions, std_key, key)
+        });
+        if info.replaygain.is_empty() {
+            debug!("No ReplayGain/R128 tags found");
+        } else {
+            info!("ReplayGain: {:?}", info.replaygain);
+        }
+
+        info!("Symphonia opened {}: {} channels, {} Hz, codec {}",
+              path, info.channels, info.sample_rate, info.codec);
+
+        Ok(Self {
+            path: path.to_string(),
+            format: probed.format,
+            decoder,
+            track_id,
+            time_base: params.time_base,
+            sample_buf: None,
+            last_position: None,
+            info,
+            eof: false,
+        })
+    }
+
+    fn ts_to_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.info.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// Cheap check for a decodable track, without opening a decoder
+    pub fn probe(path: &str) -> bool {
+        match open_format(path) {
+            Ok(probed) => probed.format
+                .tracks()
+                .iter()
+                .any(|t| symphonia::default::get_codecs().get_codec(t.codec_params.codec).is_some()),
+            Err(_) => false,
+        }
+    }
+}
+
+impl AudioSource for SymphoniaSource {
+    fn backend(&self) -> &'static str {
+        "symphonia"
+    }
+
+    fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    fn next_frames(&mut self, output: &mut Vec<f32>) -> u64 {
+        if self.eof {
+            return 0;
+        }
+
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    info!("End of file reached: {}", self.path);
+                    self.eof = true;
+                    return 0;
+                }
+                Err(e) => {
+                    warn!("Error reading packet: {}, treating as end of stream", e);
+                    self.eof = true;
+                    return 0;
+                }
+            };
+
+            // Skip packets of other tracks
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            // With gapless on, the timestamp already accounts for trimmed encoder delay
+            let packet_position = self.ts_to_frames(packet.ts());
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let frames = decoded.frames();
+                    if frames == 0 {
+                        continue;
+                    }
+
+                    self.last_position = Some(packet_position);
+
+                    let spec = *decoded.spec();
+                    let needs_bigger = self.sample_buf
+                        .as_ref()
+                        .map(|buf| buf.capacity() < decoded.capacity() * spec.channels.count())
+                        .unwrap_or(true);
+                    if needs_bigger {
+                        self.sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                    }
+
+                    if let Some(buf) = self.sample_buf.as_mut() {
+                        buf.copy_interleaved_ref(decoded);
+                        let channels = spec.channels.count();
+                        if channels == self.info.channels {
+                            output.extend_from_slice(buf.samples());
+                        } else {
+                            // Over the channel limit, keep the first channels
+                            for frame in buf.samples().chunks(channels) {
+                                output.extend_from_slice(&frame[..self.info.channels.min(frame.len())]);
+                            }
+                        }
+                    }
+
+                    return frames as u64;
+                }
+                // A corrupt packet is skipped, the next one usually decodes fine
+                Err(SymphoniaError::DecodeError(e)) => {
+                    warn!("Error decoding packet: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Decoder error: {}, treating as end of stream", e);
+                    self.eof = true;
+                    return 0;
+                }
+            }
+        }
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<u64> {
+        let seeked = self.format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time { time: Time::from(seconds.max(0.0)), track_id: Some(self.track_id) },
+            )
+            .map_err(|e| {
+                warn!("Seeking failed: {}", e);
+                anyhow!("Seeking failed: {}", e)
+            })?;
+
+        // Decoder state from before the seek would bleed into the new position
+        self.decoder.reset();
+        self.eof = false;
+        info!("Seek successful");
+
+        // Decoding resumes at the packet containing the target
+        self.last_position = None;
+        Ok(self.ts_to_frames(seeked.actual_ts))
+    }
+
+    fn frame_position(&self) -> Option<u64> {
+        self.last_position
+    }
+
+    fn is_eof(&self) -> bool {
+        self.eof
+    }
+}
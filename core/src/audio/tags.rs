@@ -0,0 +1,85 @@
+// core/src/audio/tags.rs
+// Descriptive tags (title, artist, album...) as the decoder backends find them
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl TrackTags {
+    // Build from a tag lookup (key -> value), using FFmpeg's key names
+    pub fn from_tags<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        Self {
+            title: lookup("title"),
+            artist: lookup("artist"),
+            album_artist: lookup("album_artist"),
+            album: lookup("album"),
+            track_number: lookup("track").and_then(|v| parse_number(&v)),
+            disc_number: lookup("disc").and_then(|v| parse_number(&v)),
+            year: lookup("date").or_else(|| lookup("year")).and_then(|v| parse_year(&v)),
+            genre: lookup("genre"),
+            composer: lookup("composer"),
+            comment: lookup("comment"),
+        }
+    }
+}
+
+/// What tells albums apart: the album artist (the artist when there is none) and the
+/// title, trimmed and ignoring case. Two "Greatest Hits" by different artists are
+/// different albums, a compilation's tracks by different artists are one. None without
+/// an album tag.
+pub fn album_key(album_artist: Option<&str>, artist: Option<&str>, album: Option<&str>) -> Option<(String, String)> {
+    let normalize = |tag: Option<&str>| tag.map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+    let album = normalize(album)?;
+    let artist = normalize(album_artist).or_else(|| normalize(artist)).unwrap_or_default();
+    Some((artist, album))
+}
+
+// "3", "03" or "3/12"
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+// "1997", "1997-05-21" or "1997-05-21T00:00:00"
+fn parse_year(value: &str) -> Option<i32> {
+    let digits: String = value.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() == 4 {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn albums_are_told_apart_by_album_artist() {
+        let hits_a = album_key(None, Some("Band A"), Some("Greatest Hits"));
+        assert_eq!(hits_a, album_key(None, Some(" band a"), Some("greatest hits ")));
+        assert_ne!(hits_a, album_key(None, Some("Band B"), Some("Greatest Hits")));
+
+        // The album artist wins over the track artist
+        assert_eq!(
+            album_key(Some("Various Artists"), Some("Singer"), Some("Live")),
+            album_key(Some("Various Artists"), Some("Other Singer"), Some("Live")),
+        );
+        assert_eq!(album_key(Some("Band"), Some("Band feat. Guest"), Some("Debut")), album_key(None, Some("Band"), Some("Debut")));
+
+        assert_eq!(album_key(None, Some("Band A"), None), None);
+        assert_eq!(album_key(None, Some("Band A"), Some("  ")), None);
+    }
+}
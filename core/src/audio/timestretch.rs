@@ -0,0 +1,227 @@
+// core/src/audio/timestretch.rs
+// Playback speed without the pitch change (WSOLA): the output is built from overlapping
+// input segments, each taken where it lines up best with the end of the previous one
+use log::{debug, info};
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+// Segment, overlap and search lengths, tuned for speech (lectures, podcasts)
+const SEGMENT_MS: usize = 40;
+const OVERLAP_MS: usize = 10;
+const SEARCH_MS: usize = 15;
+// Candidates are first tried every few frames, then refined around the best one
+const COARSE_STEP: usize = 4;
+
+pub struct TimeStretcher {
+    channels: usize,
+    segment: usize,
+    overlap: usize,
+    search: usize,
+    speed: f64,
+    // Input not used up yet, interleaved. Its first frame is input frame `buffer_start`.
+    buffer: Vec<f32>,
+    buffer_start: u64,
+    // Input frames received since the last reset
+    frames_in: u64,
+    // Input position the next output segment stands for
+    analysis_pos: f64,
+    // End of the last segment, cross-faded into the next one
+    tail: Vec<f32>,
+}
+
+impl TimeStretcher {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let mut stretcher = Self {
+            channels: 2,
+            segment: 0,
+            overlap: 0,
+            search: 0,
+            speed: 1.0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            frames_in: 0,
+            analysis_pos: 0.0,
+            tail: Vec::new(),
+        };
+        stretcher.configure(sample_rate, channels);
+        stretcher
+    }
+
+    /// Set up for a new output format, dropping anything buffered
+    pub fn configure(&mut self, sample_rate: u32, channels: usize) {
+        let frames_per_ms = (sample_rate as usize / 1000).max(1);
+        self.channels = channels.max(1);
+        self.segment = SEGMENT_MS * frames_per_ms;
+        self.overlap = OVERLAP_MS * frames_per_ms;
+        self.search = SEARCH_MS * frames_per_ms;
+        debug!("Time-stretcher: {} frame segments, {} overlap, {} search",
+               self.segment, self.overlap, self.search);
+        self.reset();
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED) as f64;
+        if (speed - self.speed).abs() > f64::EPSILON {
+            info!("Playback speed {:.2}x -> {:.2}x", self.speed, speed);
+            self.speed = speed;
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// At normal speed with nothing buffered, blocks can skip the stretcher entirely
+    pub fn is_bypassed(&self) -> bool {
+        self.speed == 1.0 && self.buffer.is_empty() && self.tail.is_empty()
+    }
+
+    /// Input frames received since the last reset
+    pub fn input_frames(&self) -> u64 {
+        self.frames_in
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.tail.clear();
+        self.buffer_start = 0;
+        self.frames_in = 0;
+        self.analysis_pos = 0.0;
+    }
+
+    // Hop between segments in the output
+    fn hop(&self) -> usize {
+        self.segment - self.overlap
+    }
+
+    /// Stretch a block of interleaved samples, appending whatever is ready to `output`.
+    /// Returns the input frame (counted from the last reset) the first appended frame stands for.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) -> f64 {
+        let first_position = self.analysis_pos;
+        let channels = self.channels;
+        self.buffer.extend_from_slice(input);
+        self.frames_in += (input.len() / channels) as u64;
+
+        let hop = self.hop();
+        loop {
+            let nominal = (self.analysis_pos.round() as u64).max(self.buffer_start);
+            if nominal + (self.search + self.segment) as u64 > self.frames_in {
+                break;
+            }
+
+            let start_frame = if self.tail.is_empty() {
+                nominal
+            } else {
+                let lowest = nominal.saturating_sub(self.search as u64).max(self.buffer_start);
+                self.best_match(lowest, nominal + self.search as u64)
+            };
+
+            let start = (start_frame - self.buffer_start) as usize * channels;
+            let segment = &self.buffer[start..start + self.segment * channels];
+            if self.tail.is_empty() {
+                output.extend_from_slice(&segment[..hop * channels]);
+            } else {
+                cross_fade(&self.tail, segment, channels, output);
+                output.extend_from_slice(&segment[self.overlap * channels..hop * channels]);
+            }
+            self.tail.clear();
+            self.tail.extend_from_slice(&segment[hop * channels..]);
+
+            self.analysis_pos += hop as f64 * self.speed;
+
+            // Nothing before the next search window is needed again
+            let keep_from = (self.analysis_pos.round() as u64)
+                .saturating_sub(self.search as u64)
+                .clamp(self.buffer_start, self.frames_in);
+            self.buffer.drain(..(keep_from - self.buffer_start) as usize * channels);
+            self.buffer_start = keep_from;
+        }
+
+        first_position
+    }
+
+    /// Hand out everything still held, joined on without stretching, and start over.
+    /// Returns the input frame the first appended frame stands for, like `process`.
+    pub fn flush(&mut self, output: &mut Vec<f32>) -> f64 {
+        let first_position = self.analysis_pos;
+        let channels = self.channels;
+        let nominal = (self.analysis_pos.round() as u64).clamp(self.buffer_start, self.frames_in);
+
+        if self.tail.is_empty() {
+            let start = (nominal - self.buffer_start) as usize * channels;
+            output.extend_from_slice(&self.buffer[start..]);
+        } else if self.frames_in >= self.buffer_start + self.overlap as u64 {
+            let lowest = nominal.saturating_sub(self.search as u64).max(self.buffer_start);
+            let start_frame = self.best_match(lowest, nominal + self.search as u64);
+            let start = (start_frame - self.buffer_start) as usize * channels;
+            cross_fade(&self.tail, &self.buffer[start..], channels, output);
+            if let Some(rest) = self.buffer.get(start + self.overlap * channels..) {
+                output.extend_from_slice(rest);
+            }
+        } else {
+            // Too little input left to join onto, the tail is all there is
+            output.extend_from_slice(&self.tail);
+        }
+
+        self.reset();
+        first_position
+    }
+
+    // Input frame in lowest..=highest where a segment continues the tail most smoothly
+    fn best_match(&self, lowest: u64, highest: u64) -> u64 {
+        let last_start = self.frames_in.saturating_sub(self.overlap.max(1) as u64);
+        let highest = highest.min(last_start).max(lowest);
+
+        let mut best = lowest;
+        let mut best_score = f32::MIN;
+        let mut candidate = lowest;
+        while candidate <= highest {
+            let score = self.similarity(candidate);
+            if score > best_score {
+                best_score = score;
+                best = candidate;
+            }
+            candidate += COARSE_STEP as u64;
+        }
+
+        // Refine between the neighbouring coarse candidates
+        let fine_low = best.saturating_sub(COARSE_STEP as u64 - 1).max(lowest);
+        let fine_high = (best + COARSE_STEP as u64 - 1).min(highest);
+        for candidate in fine_low..=fine_high {
+            let score = self.similarity(candidate);
+            if score > best_score {
+                best_score = score;
+                best = candidate;
+            }
+        }
+
+        best
+    }
+
+    // Correlation between the tail and the input at a frame, normalized by the input's energy
+    fn similarity(&self, frame: u64) -> f32 {
+        let start = (frame - self.buffer_start) as usize * self.channels;
+        let candidate = &self.buffer[start..(start + self.tail.len()).min(self.buffer.len())];
+
+        let mut correlation = 0.0f32;
+        let mut energy = 0.0f32;
+        for (a, b) in self.tail.iter().zip(candidate) {
+            correlation += a * b;
+            energy += b * b;
+        }
+        correlation / (energy.sqrt() + 1e-9)
+    }
+}
+
+// Linear fade from `from` into `to` over the length of `from` (or less, if `to` is shorter)
+fn cross_fade(from: &[f32], to: &[f32], channels: usize, output: &mut Vec<f32>) {
+    let frames = from.len().min(to.len()) / channels;
+    for frame in 0..frames {
+        let weight = frame as f32 / frames as f32;
+        for channel in 0..channels {
+            let i = frame * channels + channel;
+            output.push(from[i] * (1.0 - weight) + to[i] * weight);
+        }
+    }
+}
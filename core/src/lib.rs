@@ -2,18 +2,35 @@
 pub mod audio;
 pub mod player;
 pub mod playlist;
+pub mod queue;
+pub mod shuffle;
 pub mod library;
+pub mod settings;
+pub mod session;
+pub mod store;
+pub mod watcher;
 
 // Re-export key types for convenience
-pub use player::state::{PlayerState, PlaybackStatus};
+pub use player::state::{PlayerState, PlaybackStatus, RepeatMode};
 pub use player::actions::PlayerAction;
 pub use player::Player;
+pub use player::history::{HistoryEntry, PlaybackHistory};
 pub use playlist::{PlaylistAction, PlaylistState, Playlist, Track};
-pub use library::{LibraryAction, LibraryState};
+pub use queue::{PlayQueue, QueueAction, QueueSource};
+pub use shuffle::{ShuffleMode, Shuffler};
+pub use library::{FileFingerprint, LibraryAction, LibraryScanner, LibraryState, ScanProgress, ScanReport, ScannedTrack};
+pub use audio::device::{OutputDeviceId, OutputDeviceInfo, OutputConfigInfo, list_hosts, list_output_devices};
+pub use audio::sink::OutputSink;
+pub use audio::equalizer::{EqBand, EqBandType, EqPreset, EqSettings};
+pub use session::Session;
+pub use store::{AlbumSummary, ArtistSummary, LibraryStore};
+pub use watcher::{LibraryChange, LibraryWatcher};
+pub use settings::{PlayerSettings, CrossfadeSettings, CrossfadeCurve, NormalizationSettings, NormalizationMode};
 
 #[derive(Debug, Clone)]
 pub enum Action {
     Player(PlayerAction),
     Playlist(PlaylistAction),
     Library(LibraryAction),
+    Queue(QueueAction),
 }
\ No newline at end of file
@@ -1,12 +1,96 @@
 // core/src/library.rs
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use log::{debug, error, info, warn};
 use serde::{Serialize, Deserialize};
+
 use crate::Track; // Import Track from lib.rs re-export
+use crate::audio::decoder::scan_directory_for_audio_files;
+use crate::audio::source::open_source;
+use crate::store::AlbumSummary;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LibraryState {
     pub tracks: Vec<Track>,
     pub scan_dirs: Vec<String>,
     pub scanning: bool,
+    // Where the running (or last) scan got to
+    #[serde(skip)]
+    pub scan_progress: ScanProgress,
+    // Files the last scan couldn't read: (path, error)
+    #[serde(skip)]
+    pub scan_errors: Vec<(String, String)>,
+    // From the library database, when there is one; `tracks` then only holds what
+    // was imported by hand
+    #[serde(skip)]
+    pub albums: Vec<AlbumSummary>,
+    #[serde(skip)]
+    pub track_count: usize,
+}
+
+/// How far a library scan has got
+#[derive(Clone, Debug, Default)]
+pub struct ScanProgress {
+    pub scanned: usize,
+    // Grows while the scan directories are still being walked
+    pub total: usize,
+    pub current: Option<String>,
+    pub finished: bool,
+    pub report: ScanReport,
+}
+
+/// What a scan changed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScanReport {
+    pub added: usize,
+    pub updated: usize,
+    // Fingerprint matched, so the file wasn't opened
+    pub unchanged: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+impl ScanReport {
+    pub fn summary(&self) -> String {
+        format!("{} added, {} updated, {} removed, {} failed",
+                self.added, self.updated, self.removed, self.failed)
+    }
+}
+
+/// Size and modification time of a file when its tags were read. A rescan only opens
+/// files whose fingerprint changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub size: u64,
+    // Nanoseconds since the Unix epoch, as precise as the filesystem keeps it. A tag edit
+    // that keeps the size can land within the same second as the last read.
+    pub mtime_ns: i64,
+}
+
+impl FileFingerprint {
+    pub fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime_ns = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        Ok(Self { size: metadata.len(), mtime_ns })
+    }
+}
+
+/// A file the scan read, with the fingerprint it had at the time
+#[derive(Clone, Debug)]
+pub struct ScannedTrack {
+    pub track: Track,
+    pub fingerprint: FileFingerprint,
 }
 
 impl LibraryState {
@@ -15,7 +99,34 @@ impl LibraryState {
             tracks: Vec::new(),
             scan_dirs: vec!["./music".to_string()],
             scanning: false,
+            scan_progress: ScanProgress::default(),
+            scan_errors: Vec::new(),
+            albums: Vec::new(),
+            track_count: 0,
+        }
+    }
+
+    /// Pick up what a running scan has found so far. Tracks already in the library are
+    /// updated in place, keeping their play counts and resume positions.
+    pub fn apply_scan(&mut self, scanner: &LibraryScanner) {
+        for ScannedTrack { track: scanned, .. } in scanner.take_tracks() {
+            match self.tracks.iter_mut().find(|t| t.path == scanned.path) {
+                Some(track) => track.update_metadata(scanned),
+                None => self.tracks.push(scanned),
+            }
+        }
+        let removed: HashSet<String> = scanner.take_removed().into_iter().collect();
+        if !removed.is_empty() {
+            self.tracks.retain(|t| !removed.contains(&t.path));
         }
+        self.update_scan_progress(scanner);
+    }
+
+    /// Pick up a running scan's progress and errors, leaving its tracks to the caller
+    pub fn update_scan_progress(&mut self, scanner: &LibraryScanner) {
+        self.scan_errors.extend(scanner.take_errors());
+        self.scan_progress = scanner.progress();
+        self.scanning = !self.scan_progress.finished;
     }
 }
 
@@ -27,4 +138,263 @@ pub enum LibraryAction {
     ImportFile(String),
     Search(String),
     None,
-}
\ No newline at end of file
+}
+
+// Shared between the scan thread and the UI
+#[derive(Default)]
+struct ScanShared {
+    progress: ScanProgress,
+    tracks: Vec<ScannedTrack>,
+    // Files that were in the library but are gone from the disk
+    removed: Vec<String>,
+    errors: Vec<(String, String)>,
+}
+
+/// Walks the scan directories and reads the tags of every new or changed audio file on a
+/// background thread. Dropping it cancels the scan.
+pub struct LibraryScanner {
+    shared: Arc<Mutex<ScanShared>>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl LibraryScanner {
+    /// `known` holds the fingerprints of the files already in the library; empty for a
+    /// full scan
+    pub fn start(scan_dirs: Vec<String>, known: HashMap<String, FileFingerprint>) -> Self {
+        info!("Starting library scan of {} directories ({} files known)", scan_dirs.len(), known.len());
+        Self::spawn(move |shared, cancel| run_scan(scan_dirs, known, shared, cancel))
+    }
+
+    /// Read just these files, e.g. the ones the library watcher saw change
+    pub fn start_files(files: Vec<PathBuf>, known: HashMap<String, FileFingerprint>) -> Self {
+        debug!("Scanning {} changed files", files.len());
+        Self::spawn(move |shared, cancel| {
+            if let Ok(mut s) = shared.lock() {
+                s.progress.total = files.len();
+            }
+            scan_files(files, &known, &shared, &cancel, &mut HashSet::new());
+            if let Ok(mut s) = shared.lock() {
+                s.progress.current = None;
+                s.progress.finished = true;
+            }
+        })
+    }
+
+    fn spawn<F>(job: F) -> Self
+    where
+        F: FnOnce(Arc<Mutex<ScanShared>>, Arc<AtomicBool>) + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(ScanShared::default()));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let shared_thread = Arc::clone(&shared);
+        let cancel_thread = Arc::clone(&cancel);
+
+        let handle = thread::Builder::new()
+            .name("library_scan".to_string())
+            .spawn(move || job(shared_thread, cancel_thread));
+
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                error!("Failed to create library scan thread: {}", e);
+                if let Ok(mut s) = shared.lock() {
+                    s.progress.finished = true;
+                    s.errors.push((String::new(), format!("Thread creation failed: {}", e)));
+                }
+                None
+            }
+        };
+
+        Self { shared, cancel, handle }
+    }
+
+    pub fn progress(&self) -> ScanProgress {
+        self.shared.lock()
+            .map(|s| s.progress.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn take_tracks(&self) -> Vec<ScannedTrack> {
+        self.shared.lock()
+            .map(|mut s| std::mem::take(&mut s.tracks))
+            .unwrap_or_default()
+    }
+
+    pub fn take_removed(&self) -> Vec<String> {
+        self.shared.lock()
+            .map(|mut s| std::mem::take(&mut s.removed))
+            .unwrap_or_default()
+    }
+
+    pub fn take_errors(&self) -> Vec<(String, String)> {
+        self.shared.lock()
+            .map(|mut s| std::mem::take(&mut s.errors))
+            .unwrap_or_default()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.lock().map(|s| s.progress.finished).unwrap_or(true)
+    }
+
+    pub fn cancel(&self) {
+        info!("Cancelling library scan");
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for LibraryScanner {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_scan(
+    scan_dirs: Vec<String>,
+    known: HashMap<String, FileFingerprint>,
+    shared: Arc<Mutex<ScanShared>>,
+    cancel: Arc<AtomicBool>,
+) {
+    // Walk everything first so the progress has a total to count towards
+    let mut files = Vec::new();
+    // Only directories that could be walked can tell which files were deleted; an
+    // unplugged drive or offline share shouldn't empty the library
+    let mut reachable = Vec::new();
+    for dir in &scan_dirs {
+        if cancel.load(Ordering::SeqCst) {
+            break;
+        }
+        let dir_path = Path::new(dir);
+        if !dir_path.is_dir() {
+            warn!("Scan directory not found: {}", dir);
+            if let Ok(mut s) = shared.lock() {
+                s.errors.push((dir.clone(), "Directory not found".to_string()));
+            }
+            continue;
+        }
+
+        let found = scan_directory_for_audio_files(dir_path);
+        info!("Found {} audio files in {}", found.len(), dir);
+        files.extend(found);
+        reachable.push(dir_path);
+        if let Ok(mut s) = shared.lock() {
+            s.progress.total = files.len();
+        }
+    }
+
+    let mut seen: HashSet<String> = HashSet::with_capacity(files.len());
+    scan_files(files, &known, &shared, &cancel, &mut seen);
+
+    // Known files under a scanned directory that weren't found any more. A cancelled
+    // scan hasn't seen everything, so it doesn't remove anything.
+    let removed: Vec<String> = if cancel.load(Ordering::SeqCst) {
+        Vec::new()
+    } else {
+        known.keys()
+            .filter(|path| !seen.contains(*path))
+            .filter(|path| reachable.iter().any(|dir| Path::new(path).starts_with(dir)))
+            .cloned()
+            .collect()
+    };
+
+    if let Ok(mut s) = shared.lock() {
+        s.progress.report.removed = removed.len();
+        s.removed = removed;
+        info!("Library scan finished: {}/{} files ({})",
+              s.progress.scanned, s.progress.total, s.progress.report.summary());
+        s.progress.current = None;
+        s.progress.finished = true;
+    }
+}
+
+// Read the files whose fingerprint differs from the known one, adding the paths to `seen`
+fn scan_files(
+    files: Vec<PathBuf>,
+    known: &HashMap<String, FileFingerprint>,
+    shared: &Arc<Mutex<ScanShared>>,
+    cancel: &Arc<AtomicBool>,
+    seen: &mut HashSet<String>,
+) {
+    for file in files {
+        if cancel.load(Ordering::SeqCst) {
+            info!("Library scan cancelled");
+            break;
+        }
+
+        let path = file.to_string_lossy().to_string();
+        seen.insert(path.clone());
+
+        let fingerprint = match FileFingerprint::of(&file) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                warn!("Could not read {}: {}", path, e);
+                if let Ok(mut s) = shared.lock() {
+                    s.errors.push((path, e.to_string()));
+                    s.progress.report.failed += 1;
+                    s.progress.scanned += 1;
+                }
+                continue;
+            }
+        };
+
+        let previous = known.get(&path);
+        if previous == Some(&fingerprint) {
+            if let Ok(mut s) = shared.lock() {
+                s.progress.report.unchanged += 1;
+                s.progress.scanned += 1;
+            }
+            continue;
+        }
+
+        if let Ok(mut s) = shared.lock() {
+            s.progress.current = Some(path.clone());
+        }
+
+        let result = scan_file(&path);
+        if let Ok(mut s) = shared.lock() {
+            match result {
+                Ok(track) => {
+                    if previous.is_some() {
+                        s.progress.report.updated += 1;
+                    } else {
+                        s.progress.report.added += 1;
+                    }
+                    s.tracks.push(ScannedTrack { track, fingerprint });
+                }
+                Err(e) => {
+                    warn!("Could not read {}: {}", path, e);
+                    s.errors.push((path, e.to_string()));
+                    s.progress.report.failed += 1;
+                }
+            }
+            s.progress.scanned += 1;
+        }
+    }
+}
+
+/// Open a file and read its tags and stream details into a Track
+pub fn scan_file(path: &str) -> Result<Track> {
+    let source = open_source(path)?;
+    let info = source.info();
+    debug!("Scanned {}: {:?} - {:?} ({:?})", path, info.tags.artist, info.tags.title, info.tags.album);
+    Ok(Track::from_stream_info(path, info))
+}
+
+/// Track for a file being added to a playlist. Network files aren't opened just to read
+/// tags, and files that can't be read are still added under their file name.
+pub fn read_track(path: &str) -> Track {
+    let is_network = path.starts_with("\\\\") || path.contains("://");
+    let scanned = if is_network { None } else { scan_file(path).ok() };
+    scanned.unwrap_or_else(|| Track {
+        path: path.to_string(),
+        title: Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string()),
+        ..Default::default()
+    })
+}
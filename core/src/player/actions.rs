@@ -1,16 +1,36 @@
 // core/src/player/actions.rs
+use std::time::Duration;
+
+use crate::audio::device::OutputDeviceId;
+use crate::audio::equalizer::EqSettings;
+use crate::settings::{CrossfadeSettings, NormalizationSettings};
 
 #[derive(Clone, Debug)]
 pub enum PlayerAction {
     Play(String),      // Path to file to play
+    PlayFrom(String, Duration), // Start a track part-way in (resume position)
     Pause,             // Pause playback
     Resume,            // Resume paused playback
     Stop,              // Stop playback completely
     SetVolume(f32),    // Set volume (0.0 to 1.0)
     Seek(f32),         // Seek to position (0.0 to 1.0)
-    SkipForward(f32),  // Skip ahead by N seconds
-    SkipBackward(f32), // Go back by N seconds
+    SeekTo(Duration),  // Seek to an absolute time
+    SkipForward(f32),  // Skip ahead by N seconds from the played position
+    SkipBackward(f32), // Go back by N seconds from the played position
+    SetSpeed(f32),     // Playback speed (0.5 to 3.0), pitch is preserved
+    SetLoopIn,         // Start an A–B loop at the played position
+    SetLoopOut,        // End the A–B loop at the played position
+    ClearLoop,         // Stop looping
     Shuffle,           // Toggle shuffle mode
+    CycleShuffleMode,  // Random -> artists -> albums -> least played -> random
+    CycleRepeatMode,   // Off -> all -> one -> stop after current -> off
     NextTrack,         // Skip to next track
     PreviousTrack,     // Go to previous track
+    SetCrossfade(CrossfadeSettings), // Configure crossfade between tracks
+    SetNormalization(NormalizationSettings), // ReplayGain / R128 loudness normalization
+    SetEqualizer(EqSettings),  // Replace equalizer bands while playing
+    LoadEqPreset(String),      // Apply a built-in or saved preset by name
+    SaveEqPreset(String),      // Save the current equalizer as a preset
+    DeleteEqPreset(String),    // Remove a saved preset
+    SetOutputDevice(Option<OutputDeviceId>), // Switch output device (None = system default)
 }
\ No newline at end of file
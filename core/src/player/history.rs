@@ -0,0 +1,194 @@
+// core/src/player/history.rs
+// Recently played tracks, newest last (data/history.json). Previous steps back through
+// these, so it works the same under shuffle.
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use log::debug;
+
+// Oldest entries are dropped beyond this
+pub const MAX_HISTORY_ENTRIES: usize = 500;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub path: String,
+    // Seconds since the Unix epoch when the track started
+    pub played_at: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlaybackHistory {
+    entries: VecDeque<HistoryEntry>,
+    // How far Previous has stepped back from the newest entry, and when it got there
+    #[serde(skip)]
+    back_steps: usize,
+    #[serde(skip)]
+    stepped_at: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl PlaybackHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        // Check if file exists and has content
+        if !path.exists() || path.metadata()?.len() == 0 {
+            return Ok(Self::new());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let history: Self = serde_json::from_str(&content)?;
+        Ok(history)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Newest first
+    pub fn recent(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.back_steps = 0;
+    }
+
+    // Entry Previous has stepped back to, if any
+    fn stepped_to(&self) -> Option<&HistoryEntry> {
+        if self.back_steps == 0 {
+            return None;
+        }
+        self.entries.get(self.entries.len().checked_sub(1 + self.back_steps)?)
+    }
+
+    /// A track started playing
+    pub fn record(&mut self, path: &str) {
+        if let Some(stepped_to) = self.stepped_to() {
+            // Replaying the track Previous stepped back to, keep the place
+            if stepped_to.path == path {
+                return;
+            }
+            // Moving on from it: the tracks after it are dropped, so it's the most recent
+            // track again and Previous from the new one comes back to it
+            let keep = self.entries.len() - self.back_steps;
+            self.entries.truncate(keep);
+            if let Some(last) = self.entries.back_mut() {
+                last.played_at = self.stepped_at;
+            }
+        }
+        self.back_steps = 0;
+        self.push(HistoryEntry { path: path.to_string(), played_at: now_secs() });
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        debug!("History: {}", entry.path);
+        self.entries.push_back(entry);
+        while self.entries.len() > MAX_HISTORY_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Step back to the track played before the one Previous last returned
+    /// (or before the newest one). None at the start of the history.
+    pub fn step_back(&mut self) -> Option<&HistoryEntry> {
+        if self.back_steps + 1 >= self.entries.len() {
+            return None;
+        }
+        self.back_steps += 1;
+        self.stepped_at = now_secs();
+        self.stepped_to()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(paths: &[&str]) -> PlaybackHistory {
+        let mut history = PlaybackHistory::new();
+        for path in paths {
+            history.record(path);
+        }
+        history
+    }
+
+    fn recent(history: &PlaybackHistory) -> Vec<&str> {
+        history.recent().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_beyond_the_limit() {
+        let mut history = PlaybackHistory::new();
+        for i in 0..MAX_HISTORY_ENTRIES + 10 {
+            history.record(&i.to_string());
+        }
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history.recent().last().unwrap().path, "10");
+        assert_eq!(history.recent().next().unwrap().path, (MAX_HISTORY_ENTRIES + 9).to_string());
+    }
+
+    #[test]
+    fn stepping_back_stops_at_the_oldest_entry() {
+        let mut history = history(&["a", "b", "c"]);
+        assert_eq!(history.step_back().unwrap().path, "b");
+        assert_eq!(history.step_back().unwrap().path, "a");
+        assert!(history.step_back().is_none());
+        assert!(history.step_back().is_none());
+
+        // Still at "a": the next play carries on from there
+        history.record("d");
+        assert_eq!(recent(&history), ["d", "a"]);
+    }
+
+    #[test]
+    fn playing_after_stepping_back_drops_what_came_after() {
+        let mut history = history(&["a", "b", "c"]);
+        assert_eq!(history.step_back().unwrap().path, "b");
+
+        // Previous plays the track it stepped back to, which doesn't move anything
+        history.record("b");
+        assert_eq!(recent(&history), ["c", "b", "a"]);
+
+        history.record("d");
+        assert_eq!(recent(&history), ["d", "b", "a"]);
+        assert_eq!(history.step_back().unwrap().path, "b");
+        assert_eq!(history.step_back().unwrap().path, "a");
+    }
+
+    #[test]
+    fn history_survives_a_save_and_load() {
+        let mut history = history(&["a", "b", "c"]);
+        history.step_back();
+        let path = std::env::temp_dir().join(format!("history-test-{}.json", std::process::id()));
+        history.save_to_file(&path).unwrap();
+        let mut loaded = PlaybackHistory::load_from_file(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.recent().collect::<Vec<_>>(), history.recent().collect::<Vec<_>>());
+        // Where Previous had got to isn't saved, a new session starts at the newest entry
+        assert_eq!(loaded.step_back().unwrap().path, "b");
+
+        // A missing file is an empty history
+        assert!(PlaybackHistory::load_from_file(&path).unwrap().is_empty());
+    }
+}
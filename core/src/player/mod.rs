@@ -7,10 +7,21 @@ use log::{info, error, debug, warn};
 
 pub mod state;
 pub mod actions;
+pub mod history;
 
 use crate::audio;
-use crate::audio::position::PlaybackPosition;
-use crate::player::state::{PlayerState, PlaybackStatus};
+use crate::audio::analysis::LoudnessInfo;
+use crate::audio::decoder::PlaybackOptions;
+use crate::audio::device::{self as audio_device, OutputDeviceId, OutputDeviceInfo};
+use crate::audio::dsp::DspChain;
+use crate::audio::equalizer::{Equalizer, EqSettings, EQUALIZER_STAGE};
+use crate::audio::position::{LoopRegion, PlaybackPosition};
+use crate::audio::sink::OutputSink;
+use crate::audio::timestretch;
+use crate::settings::{CrossfadeSettings, NormalizationSettings};
+use crate::shuffle::ShuffleMode;
+use crate::player::state::{PlayerState, PlaybackStatus, RepeatMode};
+use crate::player::history::PlaybackHistory;
 
 pub struct Player {
     pub state: Arc<Mutex<PlayerState>>,
@@ -18,6 +29,8 @@ pub struct Player {
     pub stop_flag: Arc<AtomicBool>,
     pub playback_position: Arc<Mutex<PlaybackPosition>>,
     pub volume: Arc<Mutex<f32>>,
+    // Next track and transition settings shared with the decode loop
+    pub playback_options: PlaybackOptions,
     pub playback_thread: Option<thread::JoinHandle<()>>,
     pub track_completed_signal: bool,
     track_completed_flag: Arc<AtomicBool>,
@@ -27,6 +40,10 @@ pub struct Player {
     pub network_prebuffer_seconds: Option<u64>,
     // Track last warning time to prevent log spam
     last_buffer_warning: Instant,
+    // Track the loop points were set on
+    loop_track: Option<String>,
+    // Tracks actually played, for Previous and the recently played list
+    pub history: PlaybackHistory,
 }
 
 impl Player {
@@ -46,12 +63,19 @@ impl Player {
         let stop_flag = Arc::new(AtomicBool::new(false));
         let track_completed_flag = Arc::new(AtomicBool::new(false));
 
+        // The equalizer is always in the chain, it just stays bypassed until enabled
+        let playback_options = PlaybackOptions::new();
+        if let Ok(mut chain) = playback_options.dsp.lock() {
+            chain.set_stage(Box::new(Equalizer::new(EqSettings::new())));
+        }
+
         Self {
             state: Arc::new(Mutex::new(PlayerState::new())),
             pause_flag,
             stop_flag,
             playback_position: Arc::new(Mutex::new(PlaybackPosition::new(44100))),
             volume: Arc::new(Mutex::new(0.8)),
+            playback_options,
             playback_thread: None,
             track_completed_signal: false,
             track_completed_flag,
@@ -59,6 +83,8 @@ impl Player {
             network_buffer_size: Some(4 * 1024 * 1024),  // Default 4MB
             network_prebuffer_seconds: Some(3),          // Default 3 seconds
             last_buffer_warning: Instant::now(),
+            loop_track: None,
+            history: PlaybackHistory::new(),
         }
     }
     
@@ -85,13 +111,31 @@ impl Player {
     }
 
     pub fn play(&mut self, path: &str) -> Result<()> {
+        self.play_from(path, Duration::ZERO)
+    }
+
+    /// Start a track at `position` rather than the beginning, e.g. its resume position
+    pub fn play_from(&mut self, path: &str, position: Duration) -> Result<()> {
+        self.start_playback(path, false, position)?;
+        self.history.record(path);
+        Ok(())
+    }
+
+    /// Open a track paused at `position`, e.g. to pick up where the last session left off
+    pub fn load_paused(&mut self, path: &str, position: Duration) -> Result<()> {
+        self.start_playback(path, true, position)?;
+        Ok(())
+    }
+
+    // Previously played track to go back to, stepping further back on every call
+    pub fn previous_in_history(&mut self) -> Option<String> {
+        self.history.step_back().map(|entry| entry.path.clone())
+    }
+
+    fn start_playback(&mut self, path: &str, paused: bool, position: Duration) -> Result<()> {
         // Stop any current playback first
         self.stop();
         
-        // Add a small delay to ensure audio resources are fully released
-        // This helps prevent overlapping audio when switching tracks
-        thread::sleep(Duration::from_millis(100));
-        
         info!("Player::play({})", path);
         
         // Detect if path is a network path
@@ -102,16 +146,22 @@ impl Player {
         }
         
         // Reset flags
-        self.pause_flag.store(false, Ordering::SeqCst);
+        self.pause_flag.store(paused, Ordering::SeqCst);
         self.stop_flag.store(false, Ordering::SeqCst);
         self.track_completed_flag.store(false, Ordering::SeqCst);
         
+        // Whatever was queued belonged to the previous track
+        self.set_next_track(None);
+        
         // Update current track
         {
             if let Ok(mut state) = self.state.lock() {
                 state.current_track = Some(path.to_string());
-                state.status = PlaybackStatus::Playing;
+                state.finished_tracks.clear();
+                state.buffer_underruns = 0;
+                state.status = if paused { PlaybackStatus::Paused } else { PlaybackStatus::Playing };
                 state.progress = 0.0;
+                state.position = Some(position);
                 state.track_completed = false;
                 
                 // Set buffering state for network files
@@ -129,6 +179,11 @@ impl Player {
             if let Some(flag) = &pos.seek_requested {
                 flag.store(false, Ordering::SeqCst);
             }
+            // Starting part-way in is a seek the decoder handles before it decodes anything.
+            // Whether the track can seek isn't known yet, the decoder checks that itself.
+            if !position.is_zero() {
+                pos.request_seek_to(position);
+            }
             pos.clear_buffers = false;
         }
         
@@ -138,6 +193,7 @@ impl Player {
         let stop_flag = Arc::clone(&self.stop_flag);
         let playback_position = Arc::clone(&self.playback_position);
         let volume = Arc::clone(&self.volume);
+        let playback_options = self.playback_options.clone();
         let track_completed = Arc::clone(&self.track_completed_flag);
         let is_network = self.is_network_path;
         let network_buffer_size = self.network_buffer_size;
@@ -169,6 +225,7 @@ impl Player {
                             state_arc_local, 
                             playback_position,
                             volume,
+                            playback_options,
                             true, // Enable prefetch mode
                             network_buffer_size // Use configured buffer size
                         )
@@ -180,7 +237,8 @@ impl Player {
                             stop_flag, 
                             state_arc_local,
                             playback_position, 
-                            volume
+                            volume,
+                            playback_options
                         )
                     };
                     
@@ -204,10 +262,18 @@ impl Player {
                     }
                 });
                 
-            // Handle thread creation failure
-            if let Err(e) = handle {
-                error!("Failed to create playback thread: {}", e);
-                let _ = error_tx_clone.send(format!("Thread creation failed: {}", e));
+            // Handle thread creation failure, otherwise wait for playback so
+            // stop() knows when the audio stream has really been released
+            match handle {
+                Ok(handle) => {
+                    if handle.join().is_err() {
+                        error!("Audio playback thread panicked");
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to create playback thread: {}", e);
+                    let _ = error_tx_clone.send(format!("Thread creation failed: {}", e));
+                }
             }
         }));
         
@@ -292,13 +358,280 @@ impl Player {
             state.network_buffering = false;
             state.current_track = None;  // Clear the current track reference
         }
+
+        // Loop points belong to the track that was playing
+        self.clear_loop();
         
         // Log completion of stop operation
         info!("Player stop operation completed");
     }
 
+    // Queue the track to play after the current one. The decoder opens it shortly
+    // before the current track ends and switches over on the same output stream.
+    pub fn set_next_track(&mut self, path: Option<String>) {
+        debug!("Player::set_next_track({:?})", path);
+        if let Ok(mut next) = self.playback_options.next_track.lock() {
+            *next = path;
+        }
+    }
+
+    pub fn set_crossfade(&mut self, mut settings: CrossfadeSettings) {
+        settings.duration_secs = settings.duration_secs.clamp(0.0, CrossfadeSettings::MAX_DURATION_SECS);
+        info!("Player::set_crossfade({:?})", settings);
+
+        // Picked up by the decode loop for the next transition
+        if let Ok(mut crossfade) = self.playback_options.crossfade.lock() {
+            *crossfade = settings;
+        }
+    }
+
+    // Analyzed loudness for a file without tags, picked up the next time it is opened
+    pub fn set_loudness_hint(&mut self, path: &str, loudness: LoudnessInfo) {
+        if let Ok(mut hints) = self.playback_options.loudness_hints.lock() {
+            hints.insert(path.to_string(), loudness);
+        }
+    }
+
+    // Applied to the running stream without restarting the track
+    pub fn set_equalizer(&mut self, settings: EqSettings) {
+        info!("Player::set_equalizer(enabled={}, {} bands)", settings.enabled, settings.bands.len());
+
+        if let Ok(mut chain) = self.playback_options.dsp.lock() {
+            if let Some(eq) = chain.stage_mut::<Equalizer>(EQUALIZER_STAGE) {
+                eq.set_settings(settings);
+            } else {
+                chain.set_stage(Box::new(Equalizer::new(settings)));
+            }
+        }
+    }
+
+    pub fn list_output_devices(&self) -> Vec<OutputDeviceInfo> {
+        audio_device::list_output_devices()
+    }
+
+    // Switch output device. A playing track is reopened on the new device at the same position.
+    pub fn set_output_device(&mut self, device: Option<OutputDeviceId>) -> Result<()> {
+        info!("Player::set_output_device({:?})", device);
+
+        if let Ok(mut selected) = self.playback_options.output_device.lock() {
+            if *selected == device {
+                return Ok(());
+            }
+            *selected = device;
+        }
+
+        self.restart_on_new_output()
+    }
+
+    // Render into a headless sink (null or WAV file) instead of a device, e.g. on machines
+    // without sound hardware. A playing track is restarted on the new sink.
+    pub fn set_output_sink(&mut self, sink: OutputSink) -> Result<()> {
+        info!("Player::set_output_sink({:?})", sink);
+
+        if let Ok(mut current) = self.playback_options.sink.lock() {
+            if *current == sink {
+                return Ok(());
+            }
+            *current = sink;
+        }
+
+        self.restart_on_new_output()
+    }
+
+    // Reopen a playing track at the same position after the output changed
+    fn restart_on_new_output(&mut self) -> Result<()> {
+        let (current_track, status) = match self.state.lock() {
+            Ok(state) => (state.current_track.clone(), state.status),
+            Err(_) => return Ok(()),
+        };
+
+        if let (Some(path), true) = (current_track, status != PlaybackStatus::Stopped) {
+            // Starting the track again clears these, so take them first. The position is
+            // handed to the decoder as a start position: seek() would be ignored until the
+            // reopened track knows its length.
+            let position = self.played_position();
+            let next_track = self.playback_options.next_track.lock()
+                .map(|next| next.clone())
+                .unwrap_or(None);
+
+            info!("Restarting {} on the new output at {:?}", path, position);
+            self.start_playback(&path, status == PlaybackStatus::Paused, position)?;
+            self.set_next_track(next_track);
+        }
+
+        Ok(())
+    }
+
+    // Shared processing chain, for adding custom stages
+    pub fn dsp_chain(&self) -> Arc<Mutex<DspChain>> {
+        Arc::clone(&self.playback_options.dsp)
+    }
+
+    pub fn set_normalization(&mut self, settings: NormalizationSettings) {
+        info!("Player::set_normalization({:?})", settings);
+
+        // Takes effect with the next decoded block
+        if let Ok(mut normalization) = self.playback_options.normalization.lock() {
+            *normalization = settings;
+        }
+    }
+
+    /// Play faster or slower (0.5x to 3x) without changing the pitch
+    pub fn set_speed(&mut self, speed: f32) {
+        let speed = speed.clamp(timestretch::MIN_SPEED, timestretch::MAX_SPEED);
+        info!("Player::set_speed({:.2})", speed);
+
+        let previous = self.playback_options.speed.lock()
+            .map(|mut s| std::mem::replace(&mut *s, speed))
+            .unwrap_or(1.0);
+
+        let (playing, seekable) = match self.state.lock() {
+            Ok(mut state) => {
+                state.speed = speed;
+                (state.status == PlaybackStatus::Playing, state.seekable)
+            }
+            Err(_) => (false, false),
+        };
+
+        // Up to a second of audio is buffered at the old speed. Restarting at the played
+        // position makes the change audible right away.
+        if playing && seekable && (previous - speed).abs() > f32::EPSILON {
+            if let Ok(mut playback_pos) = self.playback_position.lock() {
+                let position = playback_pos.position();
+                playback_pos.request_seek_to(position);
+            }
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.playback_options.speed.lock().map(|s| *s).unwrap_or(1.0)
+    }
+
+    // What follows the current track is decided by the app, this just keeps the mode with the rest of the state
+    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
+        info!("Player::set_repeat_mode({:?})", mode);
+        if let Ok(mut state) = self.state.lock() {
+            state.repeat_mode = mode;
+        }
+    }
+
+    pub fn repeat_mode(&self) -> RepeatMode {
+        self.state.lock().map(|s| s.repeat_mode).unwrap_or_default()
+    }
+
+    // Same for the shuffle mode, the queue order itself is the app's
+    pub fn set_shuffle_mode(&mut self, mode: ShuffleMode) {
+        info!("Player::set_shuffle_mode({:?})", mode);
+        if let Ok(mut state) = self.state.lock() {
+            state.shuffle_mode = mode;
+        }
+    }
+
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.state.lock().map(|s| s.shuffle_mode).unwrap_or_default()
+    }
+
+    /// Start an A–B loop at the played position
+    pub fn set_loop_in(&mut self) {
+        let position = self.played_position();
+        // An out point before the new in point doesn't make a loop anymore
+        let loop_out = self.state.lock().ok()
+            .and_then(|s| s.loop_out)
+            .filter(|&out| out > position);
+        info!("Player::set_loop_in({:.2}s)", position.as_secs_f64());
+        self.set_loop_points(Some(position), loop_out);
+    }
+
+    /// End the A–B loop at the played position. Without an in point the loop starts at 0:00.
+    pub fn set_loop_out(&mut self) {
+        let position = self.played_position();
+        let loop_in = self.state.lock().ok()
+            .and_then(|s| s.loop_in)
+            .unwrap_or(Duration::ZERO);
+        if position <= loop_in {
+            warn!("Loop out point {:.2}s is not after the in point {:.2}s, ignoring",
+                  position.as_secs_f64(), loop_in.as_secs_f64());
+            return;
+        }
+        info!("Player::set_loop_out({:.2}s)", position.as_secs_f64());
+        self.set_loop_points(Some(loop_in), Some(position));
+
+        // The decoder is already past the out point by the buffered audio.
+        // Restarting at the played position lets it see the out point coming.
+        if let Ok(mut playback_pos) = self.playback_position.lock() {
+            playback_pos.request_seek_to(position);
+        }
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.set_loop_points(None, None);
+    }
+
+    fn played_position(&self) -> Duration {
+        self.playback_position.lock().map(|p| p.position()).unwrap_or_default()
+    }
+
+    fn set_loop_points(&mut self, loop_in: Option<Duration>, loop_out: Option<Duration>) {
+        let mut current_track = None;
+        if let Ok(mut state) = self.state.lock() {
+            if loop_in.is_some() && !state.seekable {
+                info!("Current track is not seekable, can't loop it");
+                return;
+            }
+            state.loop_in = loop_in;
+            state.loop_out = loop_out;
+            current_track = state.current_track.clone();
+        }
+        self.loop_track = loop_in.and(current_track.clone());
+
+        let region = match (current_track, loop_in, loop_out) {
+            (Some(path), Some(start), Some(end)) => Some(LoopRegion { path, start, end }),
+            _ => None,
+        };
+        debug!("Loop region: {:?}", region);
+        if let Ok(mut ab_loop) = self.playback_options.ab_loop.lock() {
+            *ab_loop = region;
+        }
+    }
+
+    // Tracks that finished through a gapless transition since the last call
+    pub fn take_finished_tracks(&mut self) -> Vec<String> {
+        let (finished, current_track) = match self.state.lock() {
+            Ok(mut state) => (std::mem::take(&mut state.finished_tracks), state.current_track.clone()),
+            Err(_) => return Vec::new(),
+        };
+
+        // Every track after the first finished one started without going through play()
+        if !finished.is_empty() {
+            for path in finished.iter().skip(1).chain(current_track.as_ref()) {
+                self.history.record(path);
+            }
+        }
+        finished
+    }
+
+    // Output device changes (loss, failover, reconnect) since the last call
+    pub fn take_device_events(&mut self) -> Vec<String> {
+        if let Ok(mut state) = self.state.lock() {
+            std::mem::take(&mut state.device_events)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Whether the current track can be seeked (false for live streams)
+    pub fn is_seekable(&self) -> bool {
+        self.state.lock().map(|s| s.seekable).unwrap_or(false)
+    }
+
     pub fn seek(&mut self, position: f32) {
         debug!("Player::seek({})", position);
+
+        let duration_known = self.state.lock().map(|s| s.duration.is_some()).unwrap_or(false);
+        if !self.is_seekable() || !duration_known {
+            debug!("Ignoring seek, current track is not seekable or has no known length");
+            return;
+        }
         
         // Clamp position between 0 and 1
         let pos = position.clamp(0.0, 1.0);
@@ -314,6 +647,44 @@ impl Player {
         }
     }
 
+    /// Seek to an absolute time, clamped to the track length when it is known
+    pub fn seek_to(&mut self, position: Duration) {
+        debug!("Player::seek_to({:?})", position);
+
+        if !self.is_seekable() {
+            debug!("Ignoring seek, current track is not seekable");
+            return;
+        }
+
+        let duration = self.state.lock().ok().and_then(|s| s.duration);
+        let target = match duration {
+            Some(duration) => position.min(duration),
+            None => position,
+        };
+
+        if let Ok(mut playback_pos) = self.playback_position.lock() {
+            playback_pos.request_seek_to(target);
+        }
+
+        // Update the UI right away, like seek() does
+        if let Ok(mut state) = self.state.lock() {
+            state.position = Some(target);
+            if let Some(duration) = duration.filter(|d| !d.is_zero()) {
+                state.progress = (target.as_secs_f64() / duration.as_secs_f64()) as f32;
+            }
+        }
+    }
+
+    /// Seek relative to the played position, e.g. +10.0 or -10.0 seconds
+    pub fn seek_by(&mut self, offset_secs: f64) {
+        let current = self.playback_position.lock()
+            .map(|pos| pos.position().as_secs_f64())
+            .unwrap_or(0.0);
+        let target = (current + offset_secs).max(0.0);
+        debug!("Player::seek_by({:+.1}s) from {:.1}s", offset_secs, current);
+        self.seek_to(Duration::from_secs_f64(target));
+    }
+
     pub fn set_volume(&mut self, volume: f32) {
         let vol = volume.clamp(0.0, 1.0);
         if let Ok(mut v) = self.volume.lock() {
@@ -343,7 +714,10 @@ impl Player {
         if let Ok(playback_pos) = self.playback_position.lock() {
             progress = playback_pos.progress();
             position = Some(playback_pos.position());
-            duration = Some(playback_pos.duration());
+            // Unknown length (live streams) leaves total_samples at 0
+            if playback_pos.total_samples > 0 {
+                duration = Some(playback_pos.duration());
+            }
         }
         
         // Update the player state
@@ -351,9 +725,7 @@ impl Player {
             state.progress = progress;
             
             // Update duration and position
-            if let Some(dur) = duration {
-                state.duration = Some(dur);
-            }
+            state.duration = duration;
             state.position = position;
             
             // Update track completion state
@@ -361,6 +733,13 @@ impl Player {
                 state.track_completed = true;
             }
         }
+
+        // A gapless change moved on to another track, its loop points don't apply there
+        let current_track = self.state.lock().ok().and_then(|s| s.current_track.clone());
+        if self.loop_track.is_some() && self.loop_track != current_track {
+            info!("Track changed, clearing loop points");
+            self.clear_loop();
+        }
         
         // For network playback, periodically log the buffer health
         if self.is_network_path {
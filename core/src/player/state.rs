@@ -1,6 +1,8 @@
 use std::time::Duration;
 use serde::{Serialize, Deserialize};
 
+use crate::shuffle::ShuffleMode;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PlayerState {
     pub status: PlaybackStatus,
@@ -11,11 +13,41 @@ pub struct PlayerState {
     pub duration: Option<Duration>,
     #[serde(skip)]
     pub position: Option<Duration>,
+    // Live streams (and files without a known length) can't be seeked
+    #[serde(skip)]
+    pub seekable: bool,
+    // Playback speed, pitch is preserved
+    #[serde(skip)]
+    pub speed: f32,
+    // A–B loop points on the current track; playback loops once both are set
+    #[serde(skip)]
+    pub loop_in: Option<Duration>,
+    #[serde(skip)]
+    pub loop_out: Option<Duration>,
     pub shuffle_enabled: bool,
+    #[serde(default)]
+    pub shuffle_mode: ShuffleMode,
+    #[serde(default)]
+    pub repeat_mode: RepeatMode,
     pub track_completed: bool,
     // Network playback fields
     pub network_buffering: bool,
     pub buffer_progress: f32,
+    // Tracks that finished during gapless playback, oldest first
+    #[serde(skip)]
+    pub finished_tracks: Vec<String>,
+    // Device the current stream is playing on, after any fallback
+    #[serde(skip)]
+    pub output_device: Option<String>,
+    // No usable output device; playback is paused until one shows up
+    #[serde(skip)]
+    pub device_lost: bool,
+    // Output device changes (loss, failover, reconnect) not yet shown to the user
+    #[serde(skip)]
+    pub device_events: Vec<String>,
+    // Times the audio callback ran dry during the current playback
+    #[serde(skip)]
+    pub buffer_underruns: u64,
 }
 
 impl PlayerState {
@@ -27,10 +59,43 @@ impl PlayerState {
             volume: 0.8,
             duration: None,
             position: None,
+            seekable: false,
+            speed: 1.0,
+            loop_in: None,
+            loop_out: None,
             shuffle_enabled: false,
+            shuffle_mode: ShuffleMode::Random,
+            repeat_mode: RepeatMode::Off,
             track_completed: false,
             network_buffering: false,
             buffer_progress: 0.0,
+            finished_tracks: Vec::new(),
+            output_device: None,
+            device_lost: false,
+            device_events: Vec::new(),
+            buffer_underruns: 0,
+        }
+    }
+}
+
+/// What happens when a track ends
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RepeatMode {
+    #[default]
+    Off,              // Play to the end of the playlist, then stop
+    All,              // Start over at the top after the last track
+    One,              // Play the current track again
+    StopAfterCurrent, // Stop once the current track is done
+}
+
+impl RepeatMode {
+    /// The mode the repeat button switches to
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::StopAfterCurrent,
+            RepeatMode::StopAfterCurrent => RepeatMode::Off,
         }
     }
 }
@@ -5,13 +5,116 @@ use std::fs;
 use anyhow::Result;
 use log::warn;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use crate::audio::analysis::LoudnessInfo;
+use crate::audio::source::StreamInfo;
+
+// Every field but the path has a default, so playlists saved before a field existed
+// still load; the missing details are filled in the next time the file is read
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Track {
     pub path: String,
+    #[serde(default)]
     pub title: Option<String>,
+    #[serde(default)]
     pub artist: Option<String>,
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    #[serde(default)]
     pub album: Option<String>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    #[serde(default)]
+    pub year: Option<i32>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub composer: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    // Stream details
+    #[serde(default)]
+    pub codec: Option<String>,
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    #[serde(default)]
+    pub channels: Option<u16>,
+    #[serde(default)]
     pub play_count: u32,
+    // Measured by the loudness analyzer, used when the file has no ReplayGain tags
+    #[serde(default)]
+    pub loudness: Option<LoudnessInfo>,
+    // Length in seconds, from the file or from playing it
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    // Long-form audio (audiobooks, mixes, podcasts): where listening stopped, in seconds
+    #[serde(default)]
+    pub resume_position: Option<f64>,
+    // Heard to the end, or marked as finished by hand
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl Track {
+    /// A track for `path` with the tags and stream details a decoder found. Files
+    /// without a title tag are named after the file.
+    pub fn from_stream_info(path: &str, info: &StreamInfo) -> Self {
+        let tags = info.tags.clone();
+        let title = tags.title.or_else(|| {
+            Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.to_string())
+        });
+
+        Self {
+            path: path.to_string(),
+            title,
+            artist: tags.artist,
+            album_artist: tags.album_artist,
+            album: tags.album,
+            track_number: tags.track_number,
+            disc_number: tags.disc_number,
+            year: tags.year,
+            genre: tags.genre,
+            composer: tags.composer,
+            comment: tags.comment,
+            codec: Some(info.codec.clone()).filter(|c| !c.is_empty()),
+            bitrate: info.bitrate,
+            sample_rate: Some(info.sample_rate).filter(|r| *r > 0),
+            channels: u16::try_from(info.channels).ok().filter(|c| *c > 0),
+            duration_secs: info.duration_secs,
+            ..Default::default()
+        }
+    }
+
+    /// Take over the file details from a fresh read of the same file, keeping
+    /// play count, loudness and listening progress
+    pub fn update_metadata(&mut self, scanned: Track) {
+        *self = Track {
+            play_count: self.play_count,
+            loudness: self.loudness.take(),
+            resume_position: self.resume_position,
+            finished: self.finished,
+            duration_secs: scanned.duration_secs.or(self.duration_secs),
+            ..scanned
+        };
+    }
+
+    /// How far listening got, 0.0 to 1.0, when a position has been saved
+    pub fn listened_fraction(&self) -> Option<f32> {
+        let duration = self.duration_secs.filter(|d| *d > 0.0)?;
+        let position = self.resume_position?;
+        Some((position / duration).clamp(0.0, 1.0) as f32)
+    }
+
+    pub fn mark_finished(&mut self) {
+        self.resume_position = None;
+        self.finished = true;
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -20,10 +123,12 @@ pub enum PlaylistAction {
     Delete(u32),
     Select(u32),
     Rename(u32, String),
-    AddTrack(u32, Track),
+    AddTrack(u32, Box<Track>),
     RemoveTrack(u32, usize),
     PlayTrack(u32, usize),
     BatchAddTracks(u32, Vec<Track>),
+    AnalyzeLoudness(u32), // Measure loudness of every track in a playlist
+    MarkFinished(u32, usize), // Forget the resume position, the track has been heard
     None,
 }
 
@@ -43,6 +148,13 @@ impl PlaylistState {
         }
     }
     
+    /// Rebuild from playlists loaded elsewhere (the library database)
+    pub fn from_playlists(playlists: Vec<Playlist>, selected: Option<usize>) -> Self {
+        let next_id = playlists.iter().map(|p| p.id + 1).max().unwrap_or(1);
+        let selected = selected.filter(|&idx| idx < playlists.len());
+        Self { playlists, selected, next_id }
+    }
+    
     pub fn load_from_file(path: &Path) -> Result<Self> {
         // Check if file exists and has content
         if !path.exists() || path.metadata()?.len() == 0 {
@@ -0,0 +1,327 @@
+// core/src/queue.rs
+// What plays next. Starting a track from a playlist copies that playlist into the queue,
+// so browsing or editing other playlists afterwards doesn't change what comes next.
+use serde::{Serialize, Deserialize};
+use log::{debug, warn};
+
+use crate::Track;
+use crate::shuffle::{ShuffleMode, Shuffler};
+
+/// Where the queue's tracks came from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueueSource {
+    Playlist(u32),
+    Library,
+}
+
+#[derive(Clone, Debug)]
+pub enum QueueAction {
+    PlayNext(Box<Track>),   // Insert right after the current track
+    AddToQueue(Box<Track>), // Insert after the other manually queued tracks
+    Move(usize, usize),  // Reorder: (from, to) queue positions
+    Remove(usize),
+    Clear,               // Drop everything after the current track
+    Jump(usize),         // Play the track at this queue position
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlayQueue {
+    pub source: Option<QueueSource>,
+    // Played, current and upcoming tracks, in order
+    tracks: Vec<Track>,
+    current: Option<usize>,
+    // Tracks queued by hand sit between the current track and this position
+    queued_end: usize,
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the queue with `tracks`, starting at `index`
+    pub fn start(&mut self, source: QueueSource, tracks: Vec<Track>, index: usize) {
+        debug!("Queue started from {:?} at {} of {}", source, index, tracks.len());
+        self.current = (index < tracks.len()).then_some(index);
+        self.queued_end = self.current.map_or(0, |i| i + 1);
+        self.tracks = tracks;
+        self.source = Some(source);
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.tracks.get(self.current?)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Track> {
+        self.tracks.get(index)
+    }
+
+    /// Tracks after the current one
+    pub fn upcoming(&self) -> &[Track] {
+        &self.tracks[self.upcoming_start()..]
+    }
+
+    /// Queue position of the first upcoming track
+    pub fn upcoming_start(&self) -> usize {
+        self.current.map_or(0, |i| i + 1)
+    }
+
+    /// Whether tracks queued by hand are waiting after the current one
+    pub fn has_queued(&self) -> bool {
+        self.queued_end > self.upcoming_start()
+    }
+
+    pub fn play_next(&mut self, track: Track) {
+        let at = self.upcoming_start();
+        debug!("Playing next: {}", track.path);
+        self.tracks.insert(at, track);
+        self.queued_end = self.queued_end.max(at) + 1;
+    }
+
+    pub fn add_to_queue(&mut self, track: Track) {
+        let at = self.queued_end.clamp(self.upcoming_start(), self.tracks.len());
+        debug!("Added to queue at {}: {}", at, track.path);
+        self.tracks.insert(at, track);
+        self.queued_end = at + 1;
+    }
+
+    /// Append to the end, e.g. when tracks are added to the playlist the queue came from
+    pub fn extend(&mut self, tracks: impl IntoIterator<Item = Track>) {
+        self.tracks.extend(tracks);
+    }
+
+    /// Move an upcoming track to another upcoming position
+    pub fn move_track(&mut self, from: usize, to: usize) -> bool {
+        let first = self.upcoming_start();
+        if from < first || from >= self.tracks.len() {
+            warn!("Can't move queue entry {} (upcoming tracks start at {})", from, first);
+            return false;
+        }
+        let to = to.clamp(first, self.tracks.len() - 1);
+        let was_queued = self.queued_range().contains(&from);
+        let track = self.tracks.remove(from);
+        if was_queued {
+            self.queued_end -= 1;
+        }
+        self.tracks.insert(to, track);
+        // Dropped among the tracks queued by hand, it's one of them now. A queued track
+        // moved to the end of them stays queued.
+        if to < self.queued_end || (was_queued && to == self.queued_end) {
+            self.queued_end += 1;
+        }
+        true
+    }
+
+    /// Remove a track other than the current one
+    pub fn remove(&mut self, index: usize) -> Option<Track> {
+        if index >= self.tracks.len() || Some(index) == self.current {
+            warn!("Can't remove queue entry {}", index);
+            return None;
+        }
+        let track = self.tracks.remove(index);
+        if let Some(current) = self.current.as_mut() {
+            if index < *current {
+                *current -= 1;
+            }
+        }
+        if index < self.queued_end {
+            self.queued_end -= 1;
+        }
+        Some(track)
+    }
+
+    /// Drop everything after the current track. With nothing playing, the whole queue goes.
+    pub fn clear(&mut self) {
+        let keep = self.upcoming_start();
+        self.tracks.truncate(keep);
+        self.queued_end = keep;
+        if self.current.is_none() {
+            self.source = None;
+        }
+    }
+
+    /// Make the track at `index` the current one
+    pub fn jump(&mut self, index: usize) -> Option<&Track> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+        self.current = Some(index);
+        self.queued_end = self.queued_end.max(index + 1);
+        self.tracks.get(index)
+    }
+
+    /// Position after (or before) the current track; only `wrap` goes around the ends
+    pub fn step(&self, forward: bool, wrap: bool) -> Option<usize> {
+        let len = self.tracks.len();
+        match (self.current, forward) {
+            (None, true) => (len > 0).then_some(0),
+            (None, false) => len.checked_sub(1),
+            (Some(i), true) if i + 1 < len => Some(i + 1),
+            (Some(_), true) => (wrap && len > 0).then_some(0),
+            (Some(0), false) => if wrap { len.checked_sub(1) } else { None },
+            (Some(i), false) => Some(i - 1),
+        }
+    }
+
+    /// Queue position of `path`, searching forward from the current track and wrapping around
+    pub fn find_following(&self, path: &str) -> Option<usize> {
+        let len = self.tracks.len();
+        let first = self.upcoming_start();
+        (0..len)
+            .map(|offset| (first + offset) % len)
+            .find(|&i| self.tracks[i].path == path)
+    }
+
+    // Range of the tracks queued by hand
+    fn queued_range(&self) -> std::ops::Range<usize> {
+        let first = self.upcoming_start().min(self.tracks.len());
+        first..self.queued_end.clamp(first, self.tracks.len())
+    }
+
+    /// Shuffle the whole queue: the current track goes first, the tracks queued by hand
+    /// follow in their order, then everything else in shuffled order
+    pub fn shuffle(&mut self, shuffler: &mut Shuffler, mode: ShuffleMode) {
+        let queued = self.queued_range();
+        let mut head = Vec::new();
+        let mut rest = Vec::new();
+        for (i, track) in std::mem::take(&mut self.tracks).into_iter().enumerate() {
+            if Some(i) == self.current || queued.contains(&i) {
+                head.push(track);
+            } else {
+                rest.push(track);
+            }
+        }
+
+        let order = shuffler.order(mode, &rest);
+        let mut rest: Vec<Option<Track>> = rest.into_iter().map(Some).collect();
+        self.current = self.current.map(|_| 0);
+        self.queued_end = head.len();
+        self.tracks = head;
+        self.tracks.extend(order.into_iter().filter_map(|i| rest[i].take()));
+    }
+
+    /// Go back to the order of `context` (normally the source playlist), carrying on from
+    /// the current track's place in it. Tracks queued by hand stay next.
+    pub fn restore_order(&mut self, context: Vec<Track>) {
+        let queued: Vec<Track> = self.tracks[self.queued_range()].to_vec();
+        let current = match self.current() {
+            Some(track) => match context.iter().position(|t| t.path == track.path) {
+                Some(i) => Some(i),
+                // The current track isn't in the playlist any more, keep the shuffled order
+                None => return,
+            },
+            None => None,
+        };
+
+        self.tracks = context;
+        self.current = current;
+        self.queued_end = self.upcoming_start();
+        for track in queued {
+            self.add_to_queue(track);
+        }
+    }
+
+    /// Keep the queue's copies of a track in step with the playlists
+    pub fn update_track(&mut self, path: &str, update: impl Fn(&mut Track)) {
+        for track in self.tracks.iter_mut().filter(|t| t.path == path) {
+            update(track);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(name: &str) -> Track {
+        Track { path: name.to_string(), ..Default::default() }
+    }
+
+    // Playing the first of tracks "0" to "3" from a playlist
+    fn playing() -> PlayQueue {
+        let mut queue = PlayQueue::new();
+        queue.start(QueueSource::Playlist(1), ["0", "1", "2", "3"].map(track).to_vec(), 0);
+        queue
+    }
+
+    fn paths(queue: &PlayQueue) -> Vec<&str> {
+        queue.tracks().iter().map(|t| t.path.as_str()).collect()
+    }
+
+    #[test]
+    fn moving_into_the_queued_tracks_queues_it() {
+        let mut queue = playing();
+        queue.play_next(track("a"));
+        assert_eq!(paths(&queue), ["0", "a", "1", "2", "3"]);
+
+        // "3" dragged in front of "a"
+        assert!(queue.move_track(4, 1));
+        assert_eq!(paths(&queue), ["0", "3", "a", "1", "2"]);
+
+        // Queued after both of them, not between them
+        queue.add_to_queue(track("b"));
+        assert_eq!(paths(&queue), ["0", "3", "a", "b", "1", "2"]);
+    }
+
+    #[test]
+    fn moving_out_of_the_queued_tracks_unqueues_it() {
+        let mut queue = playing();
+        queue.play_next(track("a"));
+        queue.add_to_queue(track("b"));
+        assert_eq!(paths(&queue), ["0", "a", "b", "1", "2", "3"]);
+
+        // "a" dragged to the end of the queue
+        assert!(queue.move_track(1, 5));
+        assert_eq!(paths(&queue), ["0", "b", "1", "2", "3", "a"]);
+
+        // The queued tracks are just "b" now
+        queue.add_to_queue(track("c"));
+        assert_eq!(paths(&queue), ["0", "b", "c", "1", "2", "3", "a"]);
+
+        // And with "b" moved out too, nothing is queued by hand
+        assert!(queue.move_track(1, 4));
+        assert!(queue.move_track(1, 4));
+        assert!(!queue.has_queued());
+        queue.play_next(track("d"));
+        queue.add_to_queue(track("e"));
+        assert_eq!(paths(&queue), ["0", "d", "e", "1", "2", "b", "c", "3", "a"]);
+    }
+
+    #[test]
+    fn moving_within_the_queued_tracks_keeps_them_queued() {
+        let mut queue = playing();
+        queue.play_next(track("a"));
+        queue.add_to_queue(track("b"));
+
+        // "a" moved behind "b", the last queued position
+        assert!(queue.move_track(1, 2));
+        assert_eq!(paths(&queue), ["0", "b", "a", "1", "2", "3"]);
+
+        queue.add_to_queue(track("c"));
+        assert_eq!(paths(&queue), ["0", "b", "a", "c", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn moving_within_the_playlist_tracks_queues_nothing() {
+        let mut queue = playing();
+        assert!(queue.move_track(3, 1));
+        assert_eq!(paths(&queue), ["0", "3", "1", "2"]);
+        assert!(!queue.has_queued());
+    }
+}
@@ -0,0 +1,49 @@
+// core/src/session.rs
+// Where playback was when the app last ran (data/session.json), restored paused on startup
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use std::fs;
+use anyhow::Result;
+
+use crate::player::state::RepeatMode;
+use crate::queue::PlayQueue;
+use crate::shuffle::ShuffleMode;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub queue: PlayQueue,
+    // None when playback was stopped
+    pub current_track: Option<String>,
+    #[serde(default)]
+    pub position_secs: f64,
+    pub volume: f32,
+    #[serde(default)]
+    pub shuffle_enabled: bool,
+    #[serde(default)]
+    pub shuffle_mode: ShuffleMode,
+    #[serde(default)]
+    pub repeat_mode: RepeatMode,
+}
+
+impl Session {
+    /// None when there is no saved session yet
+    pub fn load_from_file(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() || path.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)?;
+        let session: Self = serde_json::from_str(&content)?;
+        Ok(Some(session))
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+
+        // Written often while playing, so never leave a half-written file behind
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, json)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+// core/src/settings.rs
+// Persisted player settings (data/settings.json)
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use std::fs;
+use anyhow::Result;
+
+use crate::audio::device::OutputDeviceId;
+use crate::audio::equalizer::{EqPreset, EqSettings};
+use crate::player::state::RepeatMode;
+use crate::shuffle::ShuffleMode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CrossfadeCurve {
+    Linear,     // Straight gain ramps, dips slightly in the middle
+    EqualPower, // Sine/cosine ramps, keeps perceived loudness constant
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrossfadeSettings {
+    pub enabled: bool,
+    pub duration_secs: f32,
+    pub curve: CrossfadeCurve,
+}
+
+impl CrossfadeSettings {
+    pub const MAX_DURATION_SECS: f32 = 12.0;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            duration_secs: 5.0,
+            curve: CrossfadeCurve::EqualPower,
+        }
+    }
+
+    // Gains for the outgoing and incoming track at fade position t (0.0 to 1.0)
+    pub fn gains(&self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self.curve {
+            CrossfadeCurve::Linear => (1.0 - t, t),
+            CrossfadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+        }
+    }
+}
+
+impl Default for CrossfadeSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NormalizationMode {
+    Off,
+    Track, // Every track at the same loudness
+    Album, // Keep the level differences within an album
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NormalizationSettings {
+    pub mode: NormalizationMode,
+    pub preamp_db: f32,
+    // Reduce the gain when the tagged peak would clip
+    pub prevent_clipping: bool,
+}
+
+impl NormalizationSettings {
+    pub fn new() -> Self {
+        Self {
+            mode: NormalizationMode::Off,
+            preamp_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl Default for NormalizationSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn default_playback_speed() -> f32 {
+    1.0
+}
+
+// Ten minutes: podcasts and mixes, but not songs
+fn default_resume_min_duration_secs() -> u32 {
+    600
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerSettings {
+    #[serde(default)]
+    pub crossfade: CrossfadeSettings,
+    #[serde(default)]
+    pub normalization: NormalizationSettings,
+    #[serde(default)]
+    pub equalizer: EqSettings,
+    // User presets; the built-in ones come from EqPreset::builtin()
+    #[serde(default)]
+    pub eq_presets: Vec<EqPreset>,
+    // None follows the system default device
+    #[serde(default)]
+    pub output_device: Option<OutputDeviceId>,
+    // Kept between sessions, lectures and podcasts tend to be listened to at the same speed
+    #[serde(default = "default_playback_speed")]
+    pub playback_speed: f32,
+    #[serde(default)]
+    pub repeat_mode: RepeatMode,
+    #[serde(default)]
+    pub shuffle_mode: ShuffleMode,
+    // Tracks at least this long remember where playback stopped and resume there
+    #[serde(default = "default_resume_min_duration_secs")]
+    pub resume_min_duration_secs: u32,
+}
+
+impl PlayerSettings {
+    pub fn new() -> Self {
+        Self {
+            crossfade: CrossfadeSettings::default(),
+            normalization: NormalizationSettings::default(),
+            equalizer: EqSettings::default(),
+            eq_presets: Vec::new(),
+            output_device: None,
+            playback_speed: default_playback_speed(),
+            repeat_mode: RepeatMode::Off,
+            shuffle_mode: ShuffleMode::Random,
+            resume_min_duration_secs: default_resume_min_duration_secs(),
+        }
+    }
+
+    /// Look up a preset by name, user presets first
+    pub fn find_eq_preset(&self, name: &str) -> Option<EqPreset> {
+        self.eq_presets
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .or_else(|| EqPreset::builtin().into_iter().find(|p| p.name == name))
+    }
+
+    /// Store the current equalizer under a name, replacing a user preset with the same name
+    pub fn save_eq_preset(&mut self, name: &str) {
+        let preset = EqPreset { name: name.to_string(), settings: self.equalizer.clone() };
+        if let Some(existing) = self.eq_presets.iter_mut().find(|p| p.name == name) {
+            *existing = preset;
+        } else {
+            self.eq_presets.push(preset);
+        }
+    }
+
+    pub fn delete_eq_preset(&mut self, name: &str) -> bool {
+        let before = self.eq_presets.len();
+        self.eq_presets.retain(|p| p.name != name);
+        self.eq_presets.len() != before
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        // Check if file exists and has content
+        if !path.exists() || path.metadata()?.len() == 0 {
+            return Ok(Self::new());
+        }
+
+        // Read file as string
+        let content = fs::read_to_string(path)?;
+
+        // Parse JSON
+        let settings: Self = serde_json::from_str(&content)?;
+        Ok(settings)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        // Serialize to JSON
+        let json = serde_json::to_string_pretty(self)?;
+
+        // Write to file
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
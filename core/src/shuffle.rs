@@ -0,0 +1,292 @@
+// core/src/shuffle.rs
+// Shuffle orders for the play queue. Every mode produces a full permutation up front,
+// so nothing repeats until the whole queue has played.
+use std::collections::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+use log::debug;
+
+use crate::audio::tags::album_key;
+use crate::Track;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ShuffleMode {
+    #[default]
+    Random,       // Plain Fisher–Yates permutation
+    ArtistSpread, // Random, but the same artist doesn't play twice in a row where avoidable
+    Album,        // Albums in random order, each played through in order
+    LeastPlayed,  // Least played tracks first, random among equal play counts
+}
+
+impl ShuffleMode {
+    /// The mode the shuffle mode button switches to
+    pub fn next(self) -> Self {
+        match self {
+            ShuffleMode::Random => ShuffleMode::ArtistSpread,
+            ShuffleMode::ArtistSpread => ShuffleMode::Album,
+            ShuffleMode::Album => ShuffleMode::LeastPlayed,
+            ShuffleMode::LeastPlayed => ShuffleMode::Random,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShuffleMode::Random => "Random",
+            ShuffleMode::ArtistSpread => "Artists",
+            ShuffleMode::Album => "Albums",
+            ShuffleMode::LeastPlayed => "Least played",
+        }
+    }
+}
+
+pub struct Shuffler {
+    rng: StdRng,
+}
+
+impl Shuffler {
+    pub fn new() -> Self {
+        Self { rng: StdRng::from_entropy() }
+    }
+
+    /// Same seed, same orders - for tests and reproducible sessions
+    pub fn seeded(seed: u64) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Order to play `tracks` in, as a permutation of their indices
+    pub fn order(&mut self, mode: ShuffleMode, tracks: &[Track]) -> Vec<usize> {
+        debug!("Shuffling {} tracks ({:?})", tracks.len(), mode);
+        match mode {
+            ShuffleMode::Random => {
+                let mut order: Vec<usize> = (0..tracks.len()).collect();
+                self.fisher_yates(&mut order);
+                order
+            }
+            ShuffleMode::ArtistSpread => self.artist_spread(tracks),
+            ShuffleMode::Album => self.albums(tracks),
+            ShuffleMode::LeastPlayed => {
+                let mut order: Vec<usize> = (0..tracks.len()).collect();
+                self.fisher_yates(&mut order);
+                // Stable, so equal play counts keep their random order
+                order.sort_by_key(|&i| tracks[i].play_count);
+                order
+            }
+        }
+    }
+
+    fn fisher_yates<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.rng.gen_range(0..=i);
+            items.swap(i, j);
+        }
+    }
+
+    // Shuffle within each artist, then interleave: pick a random artist weighted by how
+    // many tracks it has left, never the one just played unless nothing else is left
+    fn artist_spread(&mut self, tracks: &[Track]) -> Vec<usize> {
+        let mut groups = group_by(tracks, |track| {
+            track.artist.clone().or_else(|| track.album.clone())
+        });
+        for group in &mut groups {
+            self.fisher_yates(group);
+        }
+
+        let mut order = Vec::with_capacity(tracks.len());
+        let mut last: Option<usize> = None;
+        while order.len() < tracks.len() {
+            let remaining = tracks.len() - order.len();
+            let candidates: Vec<usize> = (0..groups.len())
+                .filter(|&g| !groups[g].is_empty() && Some(g) != last)
+                .collect();
+
+            // An artist with more than half of what's left has to go now, or it would
+            // end up back to back later on
+            let crowded = candidates.iter().copied().find(|&g| groups[g].len() * 2 > remaining);
+            let group = match (crowded, candidates.is_empty()) {
+                (Some(g), _) => g,
+                (None, false) => {
+                    let weights: usize = candidates.iter().map(|&g| groups[g].len()).sum();
+                    let mut pick = self.rng.gen_range(0..weights);
+                    let mut chosen = candidates[0];
+                    for &g in &candidates {
+                        if pick < groups[g].len() {
+                            chosen = g;
+                            break;
+                        }
+                        pick -= groups[g].len();
+                    }
+                    chosen
+                }
+                // Only the artist just played is left
+                (None, true) => match last {
+                    Some(g) => g,
+                    None => break,
+                },
+            };
+
+            if let Some(track) = groups[group].pop() {
+                order.push(track);
+            }
+            last = Some(group);
+        }
+        order
+    }
+
+    // Albums in random order, each in disc and track order. Albums are told apart by
+    // album artist, so compilations and "feat." tracks stay together.
+    fn albums(&mut self, tracks: &[Track]) -> Vec<usize> {
+        let mut groups = group_by(tracks, |track| {
+            album_key(track.album_artist.as_deref(), track.artist.as_deref(), track.album.as_deref())
+        });
+        for group in &mut groups {
+            // Stable, so untagged tracks keep their queue order after the numbered ones
+            group.sort_by_key(|&i| {
+                (tracks[i].disc_number.unwrap_or(1), tracks[i].track_number.unwrap_or(u32::MAX))
+            });
+        }
+        self.fisher_yates(&mut groups);
+        groups.into_iter().flatten().collect()
+    }
+}
+
+impl Default for Shuffler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Track indices grouped by key, in order of first appearance. Tracks without a key
+// are a group of their own each.
+fn group_by<K, F>(tracks: &[Track], key: F) -> Vec<Vec<usize>>
+where
+    K: std::hash::Hash + Eq,
+    F: Fn(&Track) -> Option<K>,
+{
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_key: HashMap<K, usize> = HashMap::new();
+    for (i, track) in tracks.iter().enumerate() {
+        match key(track) {
+            Some(k) => {
+                let group = *by_key.entry(k).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[group].push(i);
+            }
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [ShuffleMode; 4] = [
+        ShuffleMode::Random,
+        ShuffleMode::ArtistSpread,
+        ShuffleMode::Album,
+        ShuffleMode::LeastPlayed,
+    ];
+
+    fn track(artist: &str, album: &str, disc: u32, number: u32) -> Track {
+        Track {
+            path: format!("/music/{}/{}/{}-{}.flac", artist, album, disc, number),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            disc_number: Some(disc),
+            track_number: Some(number),
+            ..Default::default()
+        }
+    }
+
+    fn library() -> Vec<Track> {
+        let mut tracks = Vec::new();
+        for (artist, count) in [("Alpha", 6), ("Beta", 4), ("Gamma", 3), ("Delta", 2)] {
+            for number in 1..=count {
+                tracks.push(track(artist, "Album", 1, number));
+            }
+        }
+        tracks
+    }
+
+    #[test]
+    fn every_mode_plays_each_track_once() {
+        let tracks = library();
+        for mode in MODES {
+            for seed in 0..20 {
+                let mut order = Shuffler::seeded(seed).order(mode, &tracks);
+                order.sort_unstable();
+                assert_eq!(order, (0..tracks.len()).collect::<Vec<_>>(), "{:?} with seed {}", mode, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let tracks = library();
+        for mode in MODES {
+            assert_eq!(
+                Shuffler::seeded(7).order(mode, &tracks),
+                Shuffler::seeded(7).order(mode, &tracks),
+            );
+        }
+    }
+
+    #[test]
+    fn artist_spread_avoids_back_to_back_artists() {
+        // 6 of 15 tracks are by one artist, so there's always a way to keep them apart
+        let tracks = library();
+        for seed in 0..50 {
+            let order = Shuffler::seeded(seed).order(ShuffleMode::ArtistSpread, &tracks);
+            for pair in order.windows(2) {
+                assert_ne!(tracks[pair[0]].artist, tracks[pair[1]].artist, "seed {}: {:?}", seed, order);
+            }
+        }
+    }
+
+    #[test]
+    fn artist_spread_plays_an_unavoidable_repeat() {
+        let mut tracks = vec![track("Alpha", "A", 1, 1), track("Alpha", "A", 1, 2), track("Alpha", "A", 1, 3)];
+        tracks.push(track("Beta", "B", 1, 1));
+        let order = Shuffler::seeded(3).order(ShuffleMode::ArtistSpread, &tracks);
+        assert_eq!(order.len(), tracks.len());
+        // Alpha has to go first to be kept apart as much as possible: Alpha, Beta, Alpha, Alpha
+        let artists: Vec<&str> = order.iter().map(|&i| tracks[i].artist.as_deref().unwrap()).collect();
+        assert_eq!(artists, ["Alpha", "Beta", "Alpha", "Alpha"]);
+    }
+
+    #[test]
+    fn albums_stay_together_in_disc_and_track_order() {
+        // A compilation with a different artist on every track, and an album where one
+        // track features a guest, both in scrambled queue order
+        let mut tracks = Vec::new();
+        for (disc, number, artist) in [(2, 1, "Gamma"), (1, 2, "Beta"), (1, 1, "Alpha"), (2, 2, "Delta")] {
+            tracks.push(Track { album_artist: Some("Various Artists".to_string()), ..track(artist, "Hits", disc, number) });
+        }
+        for (number, artist) in [(3, "Band"), (1, "Band feat. Guest"), (2, "Band")] {
+            tracks.push(Track { album_artist: Some("Band".to_string()), ..track(artist, "Debut", 1, number) });
+        }
+        // Same title, different artist: a separate album
+        tracks.push(track("Other", "Debut", 1, 1));
+
+        for seed in 0..20 {
+            let order = Shuffler::seeded(seed).order(ShuffleMode::Album, &tracks);
+            let albums: Vec<(Option<&str>, &str)> = order.iter()
+                .map(|&i| (tracks[i].album_artist.as_deref(), tracks[i].album.as_deref().unwrap()))
+                .collect();
+            let runs = 1 + albums.windows(2).filter(|pair| pair[0] != pair[1]).count();
+            assert_eq!(runs, 3, "seed {}: albums split up {:?}", seed, albums);
+
+            for pair in order.windows(2) {
+                let (a, b) = (&tracks[pair[0]], &tracks[pair[1]]);
+                if a.album == b.album && a.album_artist == b.album_artist {
+                    assert!((a.disc_number, a.track_number) < (b.disc_number, b.track_number),
+                            "seed {}: {} before {}", seed, a.path, b.path);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,766 @@
+// core/src/store.rs
+// SQLite library database (data/library.db): tracks, artists, albums, play stats and
+// playlists. Queries page and filter in SQL, so big libraries never have to be loaded
+// into memory as a whole.
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+
+use crate::audio::analysis::LoudnessInfo;
+use crate::library::{FileFingerprint, ScannedTrack};
+use crate::playlist::{Playlist, PlaylistState, Track};
+
+// Schema changes, oldest first. The database's user_version is the number applied so far;
+// add new steps to the end, never edit one that has shipped.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE artists (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE
+    );
+    CREATE TABLE albums (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL COLLATE NOCASE,
+        -- The album artist, or the track artist when the album artist isn't tagged
+        artist_id INTEGER REFERENCES artists(id),
+        year INTEGER
+    );
+    CREATE INDEX albums_title ON albums(title, artist_id);
+    CREATE INDEX albums_artist ON albums(artist_id);
+    CREATE TABLE tracks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        title TEXT,
+        artist_id INTEGER REFERENCES artists(id),
+        album_id INTEGER REFERENCES albums(id),
+        album_artist TEXT,
+        track_number INTEGER,
+        disc_number INTEGER,
+        year INTEGER,
+        genre TEXT COLLATE NOCASE,
+        composer TEXT,
+        comment TEXT,
+        codec TEXT,
+        bitrate INTEGER,
+        sample_rate INTEGER,
+        channels INTEGER,
+        duration_secs REAL,
+        -- LoudnessInfo as JSON
+        loudness TEXT,
+        added_at INTEGER NOT NULL
+    );
+    CREATE INDEX tracks_artist ON tracks(artist_id);
+    CREATE INDEX tracks_album ON tracks(album_id, disc_number, track_number);
+    CREATE INDEX tracks_genre ON tracks(genre);
+    CREATE TABLE play_stats (
+        track_id INTEGER PRIMARY KEY REFERENCES tracks(id) ON DELETE CASCADE,
+        play_count INTEGER NOT NULL DEFAULT 0,
+        last_played INTEGER,
+        resume_position REAL,
+        finished INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE playlists (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        position INTEGER NOT NULL
+    );
+    CREATE TABLE playlist_tracks (
+        playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        track_id INTEGER NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+        PRIMARY KEY (playlist_id, position)
+    );
+    CREATE INDEX playlist_tracks_track ON playlist_tracks(track_id);
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT
+    );",
+    // 2: file fingerprints for incremental rescans, and files a rescan didn't find.
+    // Missing tracks stay in the playlists but drop out of the library views.
+    "ALTER TABLE tracks ADD COLUMN file_size INTEGER;
+    ALTER TABLE tracks ADD COLUMN file_mtime INTEGER;
+    ALTER TABLE tracks ADD COLUMN missing INTEGER NOT NULL DEFAULT 0;",
+    // 3: modification times in nanoseconds. Whole seconds only match a file modified
+    // exactly on the second, so most files are read again once.
+    "ALTER TABLE tracks RENAME COLUMN file_mtime TO file_mtime_ns;
+    UPDATE tracks SET file_mtime_ns = file_mtime_ns * 1000000000 WHERE file_mtime_ns IS NOT NULL;",
+];
+
+// Columns `track_from_row` reads, in order
+const TRACK_COLUMNS: &str = "
+    t.path, t.title, ar.name, t.album_artist, al.title, t.track_number, t.disc_number,
+    t.year, t.genre, t.composer, t.comment, t.codec, t.bitrate, t.sample_rate, t.channels,
+    t.duration_secs, t.loudness, s.play_count, s.resume_position, s.finished
+    FROM tracks t
+    LEFT JOIN artists ar ON ar.id = t.artist_id
+    LEFT JOIN albums al ON al.id = t.album_id
+    LEFT JOIN play_stats s ON s.track_id = t.id";
+
+// Library order: artist, album, disc, track
+const TRACK_ORDER: &str = "ORDER BY ar.name, al.title, t.disc_number, t.track_number, t.path";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtistSummary {
+    pub id: i64,
+    pub name: String,
+    pub track_count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlbumSummary {
+    pub id: i64,
+    pub title: String,
+    pub artist: Option<String>,
+    pub year: Option<i32>,
+    pub track_count: usize,
+}
+
+pub struct LibraryStore {
+    conn: Connection,
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+impl LibraryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        info!("Opening library database {}", path.display());
+        let conn = Connection::open(path)?;
+        // WAL keeps reads going while a scan writes
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Self::with_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    /// Another connection to the same database file, for use on a background thread
+    pub fn reopen(&self) -> Result<Self> {
+        match self.conn.path() {
+            Some(path) if !path.is_empty() => Self::open(Path::new(path)),
+            _ => Err(anyhow!("An in-memory library database can't be opened twice")),
+        }
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        let mut store = Self { conn };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        let version: usize = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            return Err(anyhow!(
+                "Library database is schema version {}, this version only knows up to {}",
+                version, MIGRATIONS.len()
+            ));
+        }
+
+        for (step, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+            info!("Migrating library database to version {}", step + 1);
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(sql)?;
+            tx.pragma_update(None, "user_version", step + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Add tracks or update their tags and stream details. Play stats already in the
+    /// database are kept.
+    pub fn add_tracks(&self, tracks: &[Track]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut ids = IdCache::default();
+        for track in tracks {
+            upsert_track(&tx, &mut ids, track)?;
+        }
+        tx.commit()?;
+        debug!("Stored {} tracks", tracks.len());
+        Ok(tracks.len())
+    }
+
+    /// Store what a library scan read, with the files' fingerprints
+    pub fn add_scanned_tracks(&self, scanned: &[ScannedTrack]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut ids = IdCache::default();
+        for ScannedTrack { track, fingerprint } in scanned {
+            let id = upsert_track(&tx, &mut ids, track)?;
+            tx.prepare_cached(
+                "UPDATE tracks SET file_size = ?2, file_mtime_ns = ?3, missing = 0 WHERE id = ?1",
+            )?.execute(params![id, fingerprint.size as i64, fingerprint.mtime_ns])?;
+        }
+        tx.commit()?;
+        debug!("Stored {} scanned tracks", scanned.len());
+        Ok(scanned.len())
+    }
+
+    /// Fingerprints of the library's files as they were last read, for a rescan
+    pub fn fingerprints(&self) -> Result<HashMap<String, FileFingerprint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, file_size, file_mtime_ns FROM tracks
+             WHERE missing = 0 AND file_size IS NOT NULL AND file_mtime_ns IS NOT NULL",
+        )?;
+        let fingerprints = stmt.query_map([], |row| {
+            let size: i64 = row.get(1)?;
+            Ok((row.get(0)?, FileFingerprint { size: size as u64, mtime_ns: row.get(2)? }))
+        })?.collect::<rusqlite::Result<_>>()?;
+        Ok(fingerprints)
+    }
+
+    /// Flag files a rescan didn't find. They're kept for the playlists that use them.
+    pub fn mark_missing(&self, paths: &[String]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut marked = 0;
+        for path in paths {
+            marked += tx.prepare_cached("UPDATE tracks SET missing = 1 WHERE path = ?1")?
+                .execute([path])?;
+        }
+        tx.commit()?;
+        info!("Marked {} tracks as missing", marked);
+        Ok(marked)
+    }
+
+    /// Flag every file under a directory that went away
+    pub fn mark_missing_under(&self, dir: &str) -> Result<usize> {
+        let marked = self.conn.execute(
+            "UPDATE tracks SET missing = 1
+             WHERE missing = 0 AND substr(path, 1, length(?1)) = ?1",
+            [dir_prefix(dir)],
+        )?;
+        info!("Marked {} tracks under {} as missing", marked, dir);
+        Ok(marked)
+    }
+
+    /// Whether any library file is inside `dir`
+    pub fn has_tracks_under(&self, dir: &str) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tracks WHERE missing = 0 AND substr(path, 1, length(?1)) = ?1)",
+            [dir_prefix(dir)],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn remove_track(&self, path: &str) -> Result<bool> {
+        let removed = self.conn.execute("DELETE FROM tracks WHERE path = ?1", [path])?;
+        Ok(removed > 0)
+    }
+
+    pub fn track(&self, path: &str) -> Result<Option<Track>> {
+        let sql = format!("SELECT {} WHERE t.path = ?1", TRACK_COLUMNS);
+        Ok(self.conn.query_row(&sql, [path], track_from_row).optional()?)
+    }
+
+    pub fn track_count(&self) -> Result<usize> {
+        Ok(self.conn.query_row("SELECT COUNT(*) FROM tracks WHERE missing = 0", [], |row| row.get(0))?)
+    }
+
+    /// One page of the whole library, in library order
+    pub fn tracks(&self, offset: usize, limit: usize) -> Result<Vec<Track>> {
+        let sql = format!("SELECT {} WHERE t.missing = 0 {} LIMIT ?1 OFFSET ?2", TRACK_COLUMNS, TRACK_ORDER);
+        self.query_tracks(&sql, params![limit as i64, offset as i64])
+    }
+
+    pub fn tracks_by_artist(&self, artist: &str) -> Result<Vec<Track>> {
+        let sql = format!("SELECT {} WHERE ar.name = ?1 AND t.missing = 0 {}", TRACK_COLUMNS, TRACK_ORDER);
+        self.query_tracks(&sql, [artist])
+    }
+
+    pub fn tracks_by_album(&self, album_id: i64) -> Result<Vec<Track>> {
+        let sql = format!("SELECT {} WHERE t.album_id = ?1 AND t.missing = 0 {}", TRACK_COLUMNS, TRACK_ORDER);
+        self.query_tracks(&sql, [album_id])
+    }
+
+    pub fn tracks_by_genre(&self, genre: &str) -> Result<Vec<Track>> {
+        let sql = format!("SELECT {} WHERE t.genre = ?1 AND t.missing = 0 {}", TRACK_COLUMNS, TRACK_ORDER);
+        self.query_tracks(&sql, [genre])
+    }
+
+    fn query_tracks(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Track>> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let tracks = stmt.query_map(params, track_from_row)?.collect::<rusqlite::Result<_>>()?;
+        Ok(tracks)
+    }
+
+    pub fn artists(&self) -> Result<Vec<ArtistSummary>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT ar.id, ar.name, COUNT(t.id) FROM artists ar
+             JOIN tracks t ON t.artist_id = ar.id AND t.missing = 0
+             GROUP BY ar.id ORDER BY ar.name",
+        )?;
+        let artists = stmt.query_map([], |row| {
+            Ok(ArtistSummary { id: row.get(0)?, name: row.get(1)?, track_count: row.get(2)? })
+        })?.collect::<rusqlite::Result<_>>()?;
+        Ok(artists)
+    }
+
+    /// Albums with at least one track, optionally only those by `artist`
+    pub fn albums(&self, artist: Option<&str>) -> Result<Vec<AlbumSummary>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT al.id, al.title, ar.name, al.year, COUNT(t.id) FROM albums al
+             LEFT JOIN artists ar ON ar.id = al.artist_id
+             JOIN tracks t ON t.album_id = al.id AND t.missing = 0
+             WHERE ?1 IS NULL OR ar.name = ?1
+             GROUP BY al.id ORDER BY ar.name, al.year, al.title",
+        )?;
+        let albums = stmt.query_map([artist], |row| {
+            Ok(AlbumSummary {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                year: row.get(3)?,
+                track_count: row.get(4)?,
+            })
+        })?.collect::<rusqlite::Result<_>>()?;
+        Ok(albums)
+    }
+
+    /// (genre, track count)
+    pub fn genres(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT genre, COUNT(*) FROM tracks WHERE genre IS NOT NULL AND missing = 0
+             GROUP BY genre ORDER BY genre",
+        )?;
+        let genres = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(genres)
+    }
+
+    /// Count a play of a track that's in the library
+    pub fn record_play(&self, path: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE play_stats SET play_count = play_count + 1, last_played = ?2
+             WHERE track_id = (SELECT id FROM tracks WHERE path = ?1)",
+            params![path, now_secs()],
+        )?;
+        Ok(())
+    }
+
+    /// Playlists as last saved, None when none have been saved yet
+    pub fn load_playlists(&self) -> Result<Option<PlaylistState>> {
+        let stored: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM meta WHERE key = 'playlists_saved')", [], |row| row.get(0),
+        )?;
+        if !stored {
+            return Ok(None);
+        }
+
+        let mut playlists: Vec<Playlist> = {
+            let mut stmt = self.conn.prepare("SELECT id, name FROM playlists ORDER BY position")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(Playlist { id: row.get(0)?, name: row.get(1)?, tracks: Vec::new() })
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let sql = format!(
+            "SELECT {} JOIN playlist_tracks pt ON pt.track_id = t.id
+             WHERE pt.playlist_id = ?1 ORDER BY pt.position",
+            TRACK_COLUMNS
+        );
+        for playlist in &mut playlists {
+            playlist.tracks = self.query_tracks(&sql, [playlist.id])?;
+        }
+
+        let selected: Option<usize> = self.conn.query_row(
+            "SELECT value FROM meta WHERE key = 'selected_playlist'", [], |row| row.get::<_, String>(0),
+        ).optional()?.and_then(|value| value.parse().ok());
+
+        info!("Loaded {} playlists from the library database", playlists.len());
+        Ok(Some(PlaylistState::from_playlists(playlists, selected)))
+    }
+
+    /// Replace the stored playlists, for creating, deleting and renaming them. Tracks that
+    /// aren't in the library yet are added with their play stats; the stats of tracks
+    /// already there are left alone; they're kept up to date by the targeted updates below.
+    pub fn save_playlists(&self, state: &PlaylistState) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM playlists", [])?;
+
+        let mut ids = IdCache::default();
+        for (position, playlist) in state.playlists.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO playlists (id, name, position) VALUES (?1, ?2, ?3)",
+                params![playlist.id, playlist.name, position as i64],
+            )?;
+            insert_playlist_tracks(&tx, &mut ids, playlist.id, 0, &playlist.tracks, false)?;
+        }
+
+        set_selected_playlist(&tx, state.selected)?;
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('playlists_saved', '1')", [])?;
+        tx.commit()?;
+        debug!("Rewrote {} playlists", state.playlists.len());
+        Ok(())
+    }
+
+    /// Append tracks to a stored playlist, starting at `position`. The tracks' tags are
+    /// stored too, they've just been read from the files.
+    pub fn add_playlist_tracks(&self, playlist_id: u32, position: usize, tracks: &[Track]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        insert_playlist_tracks(&tx, &mut IdCache::default(), playlist_id, position, tracks, true)?;
+        tx.commit()?;
+        debug!("Added {} tracks to playlist {}", tracks.len(), playlist_id);
+        Ok(())
+    }
+
+    /// Remove the track at `position` from a stored playlist, closing the gap
+    pub fn remove_playlist_track(&self, playlist_id: u32, position: usize) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND position = ?2",
+            params![playlist_id, position as i64],
+        )?;
+        // Positions are part of the primary key, so the ones after the gap are moved out
+        // of the way (negated) before they're moved down, instead of colliding on the way
+        tx.execute(
+            "UPDATE playlist_tracks SET position = -position - 1 WHERE playlist_id = ?1 AND position > ?2",
+            params![playlist_id, position as i64],
+        )?;
+        tx.execute(
+            "UPDATE playlist_tracks SET position = -position - 2 WHERE playlist_id = ?1 AND position < 0",
+            [playlist_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn save_selected_playlist(&self, selected: Option<usize>) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        set_selected_playlist(&tx, selected)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Store a track's resume position and whether it has been heard to the end
+    pub fn save_play_state(&self, track: &Track) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let Some(id) = tx.prepare_cached("SELECT id FROM tracks WHERE path = ?1")?
+            .query_row([&track.path], |row| row.get::<_, i64>(0))
+            .optional()? else {
+            return Ok(());
+        };
+        // The length may only have become known while the track played
+        tx.prepare_cached("UPDATE tracks SET duration_secs = COALESCE(?2, duration_secs) WHERE id = ?1")?
+            .execute(params![id, track.duration_secs])?;
+        tx.prepare_cached(
+            "INSERT INTO play_stats (track_id, resume_position, finished) VALUES (?1, ?2, ?3)
+             ON CONFLICT(track_id) DO UPDATE SET
+                resume_position = excluded.resume_position, finished = excluded.finished",
+        )?.execute(params![id, track.resume_position, track.finished])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Store an analyzed track's loudness
+    pub fn set_loudness(&self, path: &str, loudness: &LoudnessInfo) -> Result<bool> {
+        let json = serde_json::to_string(loudness)?;
+        let updated = self.conn.execute("UPDATE tracks SET loudness = ?2 WHERE path = ?1", params![path, json])?;
+        Ok(updated > 0)
+    }
+}
+
+// Add tracks to a playlist from `start` on. Tracks already in the library are only
+// rewritten with `update_tags`, their play stats never are.
+fn insert_playlist_tracks(
+    tx: &Transaction,
+    ids: &mut IdCache,
+    playlist_id: u32,
+    start: usize,
+    tracks: &[Track],
+    update_tags: bool,
+) -> Result<()> {
+    for (offset, track) in tracks.iter().enumerate() {
+        let existing: Option<i64> = if update_tags {
+            None
+        } else {
+            tx.prepare_cached("SELECT id FROM tracks WHERE path = ?1")?
+                .query_row([&track.path], |row| row.get(0))
+                .optional()?
+        };
+        let track_id = match existing {
+            Some(id) => id,
+            None => upsert_track(tx, ids, track)?,
+        };
+        tx.prepare_cached(
+            "INSERT INTO playlist_tracks (playlist_id, position, track_id) VALUES (?1, ?2, ?3)",
+        )?.execute(params![playlist_id, (start + offset) as i64, track_id])?;
+    }
+    Ok(())
+}
+
+fn set_selected_playlist(tx: &Transaction, selected: Option<usize>) -> Result<()> {
+    tx.prepare_cached("INSERT OR REPLACE INTO meta (key, value) VALUES ('selected_playlist', ?1)")?
+        .execute([selected.map(|idx| idx.to_string())])?;
+    Ok(())
+}
+
+// "dir/" for matching the paths inside a directory. Prefixes are compared rather than
+// using LIKE, so '%' and '_' in folder names don't match anything.
+fn dir_prefix(dir: &str) -> String {
+    format!("{}{}", dir.trim_end_matches(std::path::MAIN_SEPARATOR), std::path::MAIN_SEPARATOR)
+}
+
+// Artist and album ids looked up during one transaction
+#[derive(Default)]
+struct IdCache {
+    artists: HashMap<String, i64>,
+    albums: HashMap<(String, Option<i64>), i64>,
+}
+
+fn artist_id(tx: &Transaction, ids: &mut IdCache, name: &str) -> Result<i64> {
+    let key = name.to_lowercase();
+    if let Some(&id) = ids.artists.get(&key) {
+        return Ok(id);
+    }
+    tx.prepare_cached("INSERT INTO artists (name) VALUES (?1) ON CONFLICT(name) DO NOTHING")?
+        .execute([name])?;
+    let id = tx.prepare_cached("SELECT id FROM artists WHERE name = ?1")?
+        .query_row([name], |row| row.get(0))?;
+    ids.artists.insert(key, id);
+    Ok(id)
+}
+
+fn album_id(tx: &Transaction, ids: &mut IdCache, title: &str, artist: Option<i64>, year: Option<i32>) -> Result<i64> {
+    let key = (title.to_lowercase(), artist);
+    if let Some(&id) = ids.albums.get(&key) {
+        return Ok(id);
+    }
+    let existing: Option<i64> = tx.prepare_cached("SELECT id FROM albums WHERE title = ?1 AND artist_id IS ?2")?
+        .query_row(params![title, artist], |row| row.get(0))
+        .optional()?;
+    let id = match existing {
+        Some(id) => id,
+        None => {
+            tx.prepare_cached("INSERT INTO albums (title, artist_id, year) VALUES (?1, ?2, ?3)")?
+                .execute(params![title, artist, year])?;
+            tx.last_insert_rowid()
+        }
+    };
+    ids.albums.insert(key, id);
+    Ok(id)
+}
+
+// Insert or update a track's tags and stream details, returning its id
+fn upsert_track(tx: &Transaction, ids: &mut IdCache, track: &Track) -> Result<i64> {
+    let artist = match &track.artist {
+        Some(name) => Some(artist_id(tx, ids, name)?),
+        None => None,
+    };
+    let album = match &track.album {
+        Some(title) => {
+            let album_artist = match &track.album_artist {
+                Some(name) => Some(artist_id(tx, ids, name)?),
+                None => artist,
+            };
+            Some(album_id(tx, ids, title, album_artist, track.year)?)
+        }
+        None => None,
+    };
+    let loudness = track.loudness.as_ref().map(serde_json::to_string).transpose()?;
+
+    let id: i64 = tx.prepare_cached(
+        "INSERT INTO tracks (path, title, artist_id, album_id, album_artist, track_number,
+            disc_number, year, genre, composer, comment, codec, bitrate, sample_rate, channels,
+            duration_secs, loudness, added_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)
+         ON CONFLICT(path) DO UPDATE SET
+            title = excluded.title, artist_id = excluded.artist_id, album_id = excluded.album_id,
+            album_artist = excluded.album_artist, track_number = excluded.track_number,
+            disc_number = excluded.disc_number, year = excluded.year, genre = excluded.genre,
+            composer = excluded.composer, comment = excluded.comment, codec = excluded.codec,
+            bitrate = excluded.bitrate, sample_rate = excluded.sample_rate,
+            channels = excluded.channels, duration_secs = excluded.duration_secs,
+            loudness = COALESCE(excluded.loudness, tracks.loudness)
+         RETURNING id",
+    )?.query_row(params![
+        track.path, track.title, artist, album, track.album_artist, track.track_number,
+        track.disc_number, track.year, track.genre, track.composer, track.comment, track.codec,
+        track.bitrate, track.sample_rate, track.channels, track.duration_secs, loudness, now_secs(),
+    ], |row| row.get(0))?;
+
+    // Stats come along only with a track that's new to the database, e.g. from playlists.json
+    tx.prepare_cached(
+        "INSERT OR IGNORE INTO play_stats (track_id, play_count, resume_position, finished)
+         VALUES (?1, ?2, ?3, ?4)",
+    )?.execute(params![id, track.play_count, track.resume_position, track.finished])?;
+    Ok(id)
+}
+
+fn track_from_row(row: &Row) -> rusqlite::Result<Track> {
+    let loudness: Option<String> = row.get(16)?;
+    Ok(Track {
+        path: row.get(0)?,
+        title: row.get(1)?,
+        artist: row.get(2)?,
+        album_artist: row.get(3)?,
+        album: row.get(4)?,
+        track_number: row.get(5)?,
+        disc_number: row.get(6)?,
+        year: row.get(7)?,
+        genre: row.get(8)?,
+        composer: row.get(9)?,
+        comment: row.get(10)?,
+        codec: row.get(11)?,
+        bitrate: row.get(12)?,
+        sample_rate: row.get(13)?,
+        channels: row.get(14)?,
+        duration_secs: row.get(15)?,
+        // A value that no longer parses just means measuring again
+        loudness: loudness.and_then(|json| serde_json::from_str(&json).ok()),
+        play_count: row.get::<_, Option<u32>>(17)?.unwrap_or(0),
+        resume_position: row.get(18)?,
+        finished: row.get::<_, Option<bool>>(19)?.unwrap_or(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path under the test library root, with the platform's separator
+    fn music(parts: &[&str]) -> String {
+        let mut path = String::from("music");
+        for part in parts {
+            path.push(std::path::MAIN_SEPARATOR);
+            path.push_str(part);
+        }
+        path
+    }
+
+    fn track(path: &str, title: &str) -> Track {
+        Track {
+            path: path.to_string(),
+            title: Some(title.to_string()),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn user_version(store: &LibraryStore) -> usize {
+        store.conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrates_from_every_older_version() {
+        let path = music(&["a.flac"]);
+        for version in 0..MIGRATIONS.len() {
+            let conn = Connection::open_in_memory().unwrap();
+            for sql in &MIGRATIONS[..version] {
+                conn.execute_batch(sql).unwrap();
+            }
+            conn.pragma_update(None, "user_version", version).unwrap();
+            match version {
+                0 => {}
+                1 => {
+                    conn.execute("INSERT INTO tracks (path, title, added_at) VALUES (?1, 'A', 0)", [&path]).unwrap();
+                }
+                // Fingerprints were whole seconds before version 3
+                _ => {
+                    conn.execute(
+                        "INSERT INTO tracks (path, title, added_at, file_size, file_mtime) VALUES (?1, 'A', 0, 10, 1700000000)",
+                        [&path],
+                    ).unwrap();
+                }
+            }
+
+            let store = LibraryStore::with_connection(conn).unwrap();
+            assert_eq!(user_version(&store), MIGRATIONS.len(), "from version {}", version);
+            if version > 0 {
+                assert_eq!(store.track(&path).unwrap().unwrap().title.as_deref(), Some("A"));
+            }
+            let fingerprints = store.fingerprints().unwrap();
+            if version >= 2 {
+                assert_eq!(fingerprints[&path], FileFingerprint { size: 10, mtime_ns: 1_700_000_000_000_000_000 });
+            } else {
+                assert!(fingerprints.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn newer_databases_are_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        assert!(LibraryStore::with_connection(conn).is_err());
+    }
+
+    #[test]
+    fn rescanning_a_track_keeps_its_play_stats() {
+        let store = LibraryStore::open_in_memory().unwrap();
+        let path = music(&["a.flac"]);
+        store.add_tracks(&[track(&path, "Old title")]).unwrap();
+        store.record_play(&path).unwrap();
+        store.record_play(&path).unwrap();
+        store.save_play_state(&Track { resume_position: Some(42.0), ..track(&path, "Old title") }).unwrap();
+
+        // A rescan knows nothing about plays
+        store.add_tracks(&[track(&path, "New title")]).unwrap();
+
+        let stored = store.track(&path).unwrap().unwrap();
+        assert_eq!(stored.title.as_deref(), Some("New title"));
+        assert_eq!(stored.play_count, 2);
+        assert_eq!(stored.resume_position, Some(42.0));
+        let last_played: Option<i64> = store.conn.query_row(
+            "SELECT last_played FROM play_stats s JOIN tracks t ON t.id = s.track_id WHERE t.path = ?1",
+            [&path],
+            |row| row.get(0),
+        ).unwrap();
+        assert!(last_played.is_some());
+    }
+
+    #[test]
+    fn removing_a_playlist_track_closes_the_gap() {
+        let store = LibraryStore::open_in_memory().unwrap();
+        let tracks: Vec<Track> = ["a", "b", "c", "d"].iter()
+            .map(|name| track(&music(&[&format!("{}.flac", name)]), name))
+            .collect();
+        let playlist = Playlist { id: 1, name: "Mix".to_string(), tracks };
+        store.save_playlists(&PlaylistState::from_playlists(vec![playlist], Some(0))).unwrap();
+
+        store.remove_playlist_track(1, 1).unwrap();
+
+        let positions: Vec<i64> = store.conn.prepare("SELECT position FROM playlist_tracks ORDER BY position").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(positions, [0, 1, 2]);
+        let loaded = store.load_playlists().unwrap().unwrap();
+        let titles: Vec<&str> = loaded.playlists[0].tracks.iter().map(|t| t.title.as_deref().unwrap()).collect();
+        assert_eq!(titles, ["a", "c", "d"]);
+
+        // Adding after the removal goes on the end, without colliding
+        store.add_playlist_tracks(1, 3, &[track(&music(&["e.flac"]), "e")]).unwrap();
+        let loaded = store.load_playlists().unwrap().unwrap();
+        assert_eq!(loaded.playlists[0].tracks.len(), 4);
+    }
+
+    #[test]
+    fn directory_prefixes_match_wildcards_literally() {
+        let store = LibraryStore::open_in_memory().unwrap();
+        store.add_tracks(&[
+            track(&music(&["a_b", "1.flac"]), "1"),
+            track(&music(&["axb", "2.flac"]), "2"),
+            track(&music(&["50%", "3.flac"]), "3"),
+            track(&music(&["50% off", "4.flac"]), "4"),
+        ]).unwrap();
+
+        // As LIKE patterns these would match their siblings
+        assert!(!store.has_tracks_under(&music(&["a"])).unwrap());
+        assert!(!store.has_tracks_under(&music(&["a%"])).unwrap());
+        assert!(store.has_tracks_under(&music(&["a_b"])).unwrap());
+
+        assert_eq!(store.mark_missing_under(&music(&["a_b"])).unwrap(), 1);
+        assert_eq!(store.mark_missing_under(&music(&["50%"])).unwrap(), 1);
+        assert!(store.track(&music(&["axb", "2.flac"])).unwrap().is_some());
+        assert_eq!(store.track_count().unwrap(), 2);
+        assert!(!store.has_tracks_under(&music(&["a_b"])).unwrap());
+    }
+}
@@ -0,0 +1,392 @@
+// core/src/watcher.rs
+// Watches the library's scan directories and reports audio files that appeared, changed
+// or went away. Events are collected per path and only handed out once a path has been
+// quiet for a while, so a bulk copy turns into one batch instead of thousands of events.
+// Working out what settled paths mean (walking new directories, asking the library about
+// vanished ones) happens on the watcher's own thread, never on the UI's.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use notify::{recommended_watcher, Config, Event, EventKind, PollWatcher, RecursiveMode, Watcher};
+
+use crate::audio::decoder::{get_supported_extensions, is_network_path, scan_directory_for_audio_files};
+#[cfg(windows)]
+use crate::audio::decoder::normalize_path_for_check;
+
+// How long a path has to be quiet before its change is reported
+const DEBOUNCE: Duration = Duration::from_secs(2);
+// Network shares don't report changes made from other machines, so they are polled
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+// How often the watcher thread looks for settled paths
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LibraryChange {
+    Changed(String),    // A new or modified audio file
+    Removed(String),    // An audio file that was deleted or moved away
+    RemovedDir(String), // A whole directory went away
+}
+
+/// Watches the scan directories until dropped or stopped
+pub struct LibraryWatcher {
+    watchers: Vec<Box<dyn Watcher + Send>>,
+    // Paths with events not reported yet, and when the last event for each came in
+    pending: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+    changes: Receiver<LibraryChange>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl LibraryWatcher {
+    /// Watch every directory in `scan_dirs` that exists. Local directories use the
+    /// platform's notifications; network paths and mounts (or anything notifications
+    /// fail for) are polled. A vanished path that isn't an audio file could have been a
+    /// file or a directory; `has_tracks_under` tells whether the library has anything
+    /// inside it. It's called on the watcher thread.
+    pub fn start<F>(scan_dirs: &[String], has_tracks_under: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + 'static,
+    {
+        let pending: Arc<Mutex<HashMap<PathBuf, Instant>>> = Arc::default();
+        let mut watchers: Vec<Box<dyn Watcher + Send>> = Vec::new();
+
+        for dir in scan_dirs {
+            let path = Path::new(dir);
+            if !path.is_dir() {
+                debug!("Not watching {}, it isn't a directory", dir);
+                continue;
+            }
+
+            let watched = if is_network_path(dir) || is_network_filesystem(path) {
+                poll_watcher(path, &pending)
+            } else {
+                native_watcher(path, &pending).or_else(|e| {
+                    warn!("Change notifications unavailable for {} ({}), polling instead", dir, e);
+                    poll_watcher(path, &pending)
+                })
+            };
+            match watched {
+                Ok(watcher) => watchers.push(watcher),
+                Err(e) => warn!("Could not watch {}: {}", dir, e),
+            }
+        }
+
+        info!("Watching {} of {} library directories", watchers.len(), scan_dirs.len());
+
+        let (sender, changes) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = if watchers.is_empty() {
+            None
+        } else {
+            let pending = Arc::clone(&pending);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("library_watcher".to_string())
+                .spawn(move || settle_changes(pending, sender, stop, has_tracks_under))
+                .map_err(|e| warn!("Failed to create library watcher thread: {}", e))
+                .ok()
+        };
+
+        Self { watchers, pending, changes, stop, handle }
+    }
+
+    pub fn is_watching(&self) -> bool {
+        !self.watchers.is_empty()
+    }
+
+    /// Stop watching and forget unreported changes
+    pub fn stop(&mut self) {
+        if !self.watchers.is_empty() {
+            info!("Stopped watching library directories");
+        }
+        self.watchers.clear();
+        self.stop.store(true, Ordering::SeqCst);
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.clear();
+        }
+    }
+
+    /// Changes that have settled since the last call
+    pub fn take_changes(&self) -> Vec<LibraryChange> {
+        self.changes.try_iter().collect()
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Watcher thread: hands out the changes to paths that have been quiet for long enough
+fn settle_changes<F>(
+    pending: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+    sender: Sender<LibraryChange>,
+    stop: Arc<AtomicBool>,
+    has_tracks_under: F,
+) where
+    F: Fn(&str) -> bool,
+{
+    while !stop.load(Ordering::SeqCst) {
+        thread::sleep(CHECK_INTERVAL);
+        let settled: Vec<PathBuf> = match pending.lock() {
+            Ok(mut pending) => {
+                let settled: Vec<PathBuf> = pending.iter()
+                    .filter(|(_, last_event)| last_event.elapsed() >= DEBOUNCE)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in &settled {
+                    pending.remove(path);
+                }
+                settled
+            }
+            Err(_) => return,
+        };
+
+        let changes = resolve_changes(settled, &has_tracks_under);
+        if !changes.is_empty() {
+            debug!("Library changes: {:?}", changes);
+        }
+        for change in changes {
+            // Nobody is listening any more
+            if sender.send(change).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Whether a settled path changed or went away is decided now, from what is on disk,
+// rather than from the events
+fn resolve_changes<F>(settled: Vec<PathBuf>, has_tracks_under: &F) -> Vec<LibraryChange>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut changes = Vec::new();
+    for path in settled {
+        let path_str = path.to_string_lossy().to_string();
+        if path.is_dir() {
+            // A directory moved or copied in: everything in it is new
+            changes.extend(
+                scan_directory_for_audio_files(&path)
+                    .into_iter()
+                    .map(|file| LibraryChange::Changed(file.to_string_lossy().to_string())),
+            );
+        } else if path.is_file() {
+            if is_audio_file(&path) {
+                changes.push(LibraryChange::Changed(path_str));
+            }
+        } else if is_audio_file(&path) {
+            changes.push(LibraryChange::Removed(path_str));
+        } else if has_tracks_under(&path_str) {
+            changes.push(LibraryChange::RemovedDir(path_str));
+        }
+    }
+    changes
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|ext| {
+            let ext = ext.to_lowercase();
+            get_supported_extensions().contains(&ext)
+        })
+        .unwrap_or(false)
+}
+
+// Mounted network filesystems start change notifications fine but never report what
+// other machines change, so they're told apart by filesystem type
+#[cfg(target_os = "linux")]
+fn is_network_filesystem(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    const NFS: u32 = 0x6969;
+    const SMB: u32 = 0x517b;
+    const SMB2: u32 = 0xfe53_4d42;
+    const CIFS: u32 = 0xff53_4d42;
+    const CODA: u32 = 0x7375_7245;
+    const AFS: u32 = 0x5346_414f;
+    const CEPH: u32 = 0x00c3_6400;
+    const V9FS: u32 = 0x0102_1997;
+    // sshfs, rclone and the like
+    const FUSE: u32 = 0x6573_5546;
+
+    let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return false;
+    }
+    // f_type's width differs between targets, the magic numbers all fit in 32 bits
+    let fs_type = stats.f_type as u32;
+    let network = matches!(fs_type, NFS | SMB | SMB2 | CIFS | CODA | AFS | CEPH | V9FS | FUSE);
+    if network {
+        debug!("{} is on a network filesystem ({:#x})", path.display(), fs_type);
+    }
+    network
+}
+
+#[cfg(windows)]
+fn is_network_filesystem(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+
+    const DRIVE_REMOTE: u32 = 4;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDriveTypeW(root_path_name: *const u16) -> u32;
+    }
+
+    // Relative paths have no drive to ask about. canonicalize() gives verbatim paths
+    // (\\?\C:\Music, \\?\UNC\server\share) that would all look like shares, so they're
+    // turned back into plain ones first.
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let path = PathBuf::from(normalize_path_for_check(&path.to_string_lossy()));
+    if path.to_string_lossy().starts_with("\\\\") {
+        return true;
+    }
+    // The drive the path is on, e.g. "Z:\\"
+    let Some(root) = path.ancestors().last() else {
+        return false;
+    };
+    let wide: Vec<u16> = root.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let network = unsafe { GetDriveTypeW(wide.as_ptr()) } == DRIVE_REMOTE;
+    if network {
+        debug!("{} is on a network drive", path.display());
+    }
+    network
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn is_network_filesystem(_path: &Path) -> bool {
+    false
+}
+
+// Event handler that records the paths an event touched
+fn record_events(pending: &Arc<Mutex<HashMap<PathBuf, Instant>>>) -> impl FnMut(notify::Result<Event>) + Send + 'static {
+    let pending = Arc::clone(pending);
+    move |event: notify::Result<Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Library watcher error: {}", e);
+                return;
+            }
+        };
+        // Reads and other access don't change anything
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        if let Ok(mut pending) = pending.lock() {
+            let now = Instant::now();
+            for path in event.paths {
+                pending.insert(path, now);
+            }
+        }
+    }
+}
+
+fn native_watcher(dir: &Path, pending: &Arc<Mutex<HashMap<PathBuf, Instant>>>) -> Result<Box<dyn Watcher + Send>> {
+    let mut watcher = recommended_watcher(record_events(pending))?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    debug!("Watching {} for changes", dir.display());
+    Ok(Box::new(watcher))
+}
+
+fn poll_watcher(dir: &Path, pending: &Arc<Mutex<HashMap<PathBuf, Instant>>>) -> Result<Box<dyn Watcher + Send>> {
+    let config = Config::default().with_poll_interval(POLL_INTERVAL);
+    let mut watcher = PollWatcher::new(record_events(pending), config)?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    debug!("Polling {} for changes every {:?}", dir.display(), POLL_INTERVAL);
+    Ok(Box::new(watcher))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh, empty directory under the system temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("watcher-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn path_string(path: PathBuf) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn settled_changes_are_reported_once_quiet() {
+        let dir = temp_dir("changes");
+        std::fs::write(dir.join("kept.mp3"), b"old").unwrap();
+        std::fs::write(dir.join("deleted.ogg"), b"old").unwrap();
+        std::fs::write(dir.join("cover"), b"not audio").unwrap();
+        std::fs::create_dir(dir.join("album")).unwrap();
+        std::fs::write(dir.join("album").join("01.flac"), b"old").unwrap();
+
+        // Only "album" held library files
+        let album = path_string(dir.join("album"));
+        let library_dir = album.clone();
+        let watcher = LibraryWatcher::start(&[path_string(dir.clone())], move |path| path == library_dir);
+        assert!(watcher.is_watching());
+
+        std::fs::write(dir.join("new.flac"), b"new").unwrap();
+        std::fs::write(dir.join("kept.mp3"), b"modified").unwrap();
+        std::fs::remove_file(dir.join("deleted.ogg")).unwrap();
+        std::fs::remove_file(dir.join("cover")).unwrap();
+        std::fs::remove_dir_all(dir.join("album")).unwrap();
+
+        let expected = [
+            LibraryChange::Changed(path_string(dir.join("new.flac"))),
+            LibraryChange::Changed(path_string(dir.join("kept.mp3"))),
+            LibraryChange::Removed(path_string(dir.join("deleted.ogg"))),
+            LibraryChange::Removed(path_string(dir.join("album").join("01.flac"))),
+            LibraryChange::RemovedDir(album),
+        ];
+
+        // Nothing comes out before the paths have been quiet for the debounce time
+        std::thread::sleep(DEBOUNCE / 2);
+        assert_eq!(watcher.take_changes(), []);
+
+        let mut changes = Vec::new();
+        let deadline = Instant::now() + DEBOUNCE * 5;
+        while Instant::now() < deadline && !expected.iter().all(|change| changes.contains(change)) {
+            std::thread::sleep(CHECK_INTERVAL);
+            for change in watcher.take_changes() {
+                if !changes.contains(&change) {
+                    changes.push(change);
+                }
+            }
+        }
+        drop(watcher);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        for change in &expected {
+            assert!(changes.contains(change), "{:?} missing from {:?}", change, changes);
+        }
+        // The deleted file without an extension held nothing, so it isn't a removed directory
+        assert_eq!(changes.len(), expected.len(), "{:?}", changes);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn local_folders_are_not_network_filesystems() {
+        let dir = temp_dir("local");
+        assert!(!is_network_filesystem(&dir));
+        assert!(!is_network_filesystem(&dir.canonicalize().unwrap()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}